@@ -22,13 +22,21 @@ use std::{convert::TryFrom, process::exit};
 use anyhow::{bail, Context};
 use clap::{crate_authors, crate_version, Clap};
 use env_logger::*;
-use krator::OperatorRuntime;
+use krator::{Operator, OperatorRuntime};
 use log::{debug, info, LevelFilter};
 pub use schemars::JsonSchema;
 
+use self_service_operators::project::config::parse_key_value;
+use self_service_operators::project::conversion;
+use self_service_operators::project::diff;
+use self_service_operators::project::expiry::parse_duration;
 use self_service_operators::project::operator;
 use self_service_operators::project::project::DEFAULT_MANIFESTS_SECRET;
+use self_service_operators::project::secret_admission;
+use self_service_operators::project::source_watch;
+use self_service_operators::project::ManifestBundle;
 use self_service_operators::project::Project;
+use self_service_operators::project::ProjectOperatorConfig;
 use self_service_operators::project::Sample;
 
 #[derive(Clap)]
@@ -41,10 +49,22 @@ struct Opts {
     #[clap(short = 'c', long)]
     print_crd: bool,
 
-    /// Install self service crd into cluster
+    /// Prints the manifest bundle crd to stdout
+    #[clap(long)]
+    print_manifest_bundle_crd: bool,
+
+    /// Install self service crd and manifest bundle crd into cluster
     #[clap(short = 'C', long)]
     install_crd: bool,
 
+    /// Makes v2 the storage version of the project crd and migrates all stored projects to v2 -- needs a running operator that converts the projects
+    #[clap(long)]
+    migrate_storage_version: bool,
+
+    /// Prints what a reconciliation of the given (live) project would change: a unified diff per object between the live objects and a server-side apply dry run of the rendered manifests
+    #[clap(long, value_name = "PROJECT")]
+    diff: Option<String>,
+
     /// Prints necessary resources to setup admission controller. Uses current namespaces unless set by --namespace
     #[clap(short = 'a', long)]
     print_admission_controller_manifests: bool,
@@ -66,6 +86,70 @@ struct Opts {
     #[clap(short = 't', long)]
     test_manifest_template: Option<String>,
 
+    /// Label that gets set on every project namespace: expects KEY=VALUE (can be given multiple times)
+    #[clap(long)]
+    namespace_label: Vec<String>,
+
+    /// Annotation that gets set on every project namespace: expects KEY=VALUE (can be given multiple times)
+    #[clap(long)]
+    namespace_annotation: Vec<String>,
+
+    /// Namespace label key that projects are allowed to set via spec.namespaceLabels -- a trailing '*' allows all keys with this prefix (can be given multiple times)
+    #[clap(long)]
+    allowed_namespace_label: Vec<String>,
+
+    /// Namespace annotation key that projects are allowed to set via spec.namespaceAnnotations -- a trailing '*' allows all keys with this prefix (can be given multiple times)
+    #[clap(long)]
+    allowed_namespace_annotation: Vec<String>,
+
+    /// Group that can view all non-private projects and their namespaces (private projects are only visible to their owners)
+    #[clap(long)]
+    project_viewer_group: Option<String>,
+
+    /// Maximum time a project can live from now on, e.g. '30d' -- owners can extend the expiry of their projects within this limit
+    #[clap(long)]
+    max_project_ttl: Option<String>,
+
+    /// How long before the expiry of a project its owners get warned, e.g. '1d'
+    #[clap(long, default_value = "1d")]
+    project_expiry_warning: String,
+
+    /// How long applied resources can take to become ready, e.g. '5m' -- manifests can override it with the annotation 'project.selfservice.innoq.io/readiness-timeout'
+    #[clap(long, default_value = "5m")]
+    readiness_timeout: String,
+
+    /// Only record events for resources that are no longer part of a project's manifests instead of deleting them
+    #[clap(long)]
+    prune_dry_run: bool,
+
+    /// How often projects are fully reconciled, even if neither they nor their resources changed, e.g. '1h' (disabled by default)
+    #[clap(long)]
+    resync_interval: Option<String>,
+
+    /// Time between re-reconciling two projects after a manifest source (manifest bundle, secret or config map) they use changed, e.g. '5s'
+    #[clap(long, default_value = "5s")]
+    manifest_rollout_interval: String,
+
+    /// Only warn about changes of manifest secrets that break the manifests of projects instead of rejecting them
+    #[clap(long)]
+    manifest_validation_warn_only: bool,
+
+    /// Apply manifests without forcing field ownership: fields changed by others are reported as conflicts instead of being overwritten (unless a manifest is annotated with 'project.selfservice.innoq.io/field-ownership: authoritative')
+    #[clap(long)]
+    report_field_conflicts: bool,
+
+    /// Handlebars template for the names of project namespaces, e.g. 'team-{{ labels.team }}-{{ name }}' -- the project's `name` and `labels` are available (defaults to the project's name)
+    #[clap(long)]
+    namespace_name_template: Option<String>,
+
+    /// Prefix for the names of project namespaces (shortcut for --namespace-name-template)
+    #[clap(long)]
+    namespace_prefix: Option<String>,
+
+    /// Suffix for the names of project namespaces (shortcut for --namespace-name-template)
+    #[clap(long)]
+    namespace_suffix: Option<String>,
+
     /// verbose level
     #[clap(short, long, default_value = "info", possible_values = &["debug", "info", "warn", "error"]) ]
     verbosity_level: String,
@@ -98,7 +182,15 @@ async fn main() -> anyhow::Result<()> {
     if opts.print_crd {
         println!(
             "# self service crd (auto-generated):\n{}\n",
-            serde_yaml::to_string(&Project::crd()).unwrap()
+            serde_yaml::to_string(&conversion::crd()).unwrap()
+        );
+        exit(0)
+    }
+
+    if opts.print_manifest_bundle_crd {
+        println!(
+            "# manifest bundle crd (auto-generated):\n{}\n",
+            serde_yaml::to_string(&ManifestBundle::crd()).unwrap()
         );
         exit(0)
     }
@@ -111,6 +203,68 @@ async fn main() -> anyhow::Result<()> {
         exit(0)
     }
 
+    let namespace_name_template = match (
+        &opts.namespace_name_template,
+        &opts.namespace_prefix,
+        &opts.namespace_suffix,
+    ) {
+        (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+            bail!("--namespace-name-template can't be combined with --namespace-prefix or --namespace-suffix")
+        }
+        (Some(template), None, None) => Some(template.clone()),
+        (None, None, None) => None,
+        (None, prefix, suffix) => Some(format!(
+            "{}{{{{ name }}}}{}",
+            prefix.as_deref().unwrap_or_default(),
+            suffix.as_deref().unwrap_or_default()
+        )),
+    };
+
+    let config = ProjectOperatorConfig {
+        namespace_labels: opts
+            .namespace_label
+            .iter()
+            .map(|label| parse_key_value(label))
+            .collect::<anyhow::Result<_>>()
+            .context("error parsing --namespace-label")?,
+        namespace_annotations: opts
+            .namespace_annotation
+            .iter()
+            .map(|annotation| parse_key_value(annotation))
+            .collect::<anyhow::Result<_>>()
+            .context("error parsing --namespace-annotation")?,
+        allowed_namespace_labels: opts.allowed_namespace_label.clone(),
+        allowed_namespace_annotations: opts.allowed_namespace_annotation.clone(),
+        project_viewer_group: opts.project_viewer_group.clone(),
+        max_project_ttl: opts
+            .max_project_ttl
+            .as_deref()
+            .map(parse_duration)
+            .transpose()
+            .context("error parsing --max-project-ttl")?,
+        project_expiry_warning: Some(
+            parse_duration(&opts.project_expiry_warning)
+                .context("error parsing --project-expiry-warning")?,
+        ),
+        namespace_name_template,
+        readiness_timeout: Some(
+            parse_duration(&opts.readiness_timeout).context("error parsing --readiness-timeout")?,
+        ),
+        prune_dry_run: opts.prune_dry_run,
+        resync_interval: opts
+            .resync_interval
+            .as_deref()
+            .map(parse_duration)
+            .transpose()
+            .context("error parsing --resync-interval")?,
+        manifest_rollout_interval: Some(
+            parse_duration(&opts.manifest_rollout_interval)
+                .context("error parsing --manifest-rollout-interval")?,
+        ),
+        manifest_validation_warn_only: opts.manifest_validation_warn_only,
+        report_field_conflicts: opts.report_field_conflicts,
+    };
+
     if let Some(files) = opts.test_manifest_template {
         let filenames: Vec<&str> = files.split(',').collect();
         if filenames.len() != 2 {
@@ -122,9 +276,13 @@ async fn main() -> anyhow::Result<()> {
         let mut manifest_file = String::new();
         File::open(filenames[1])?.read_to_string(&mut manifest_file)?;
 
-        let rendered_file = project.render(&manifest_file, filenames[1])?;
+        // the manifest is rendered once per environment of the project
+        for environment in project.environments(&config)? {
+            let rendered_file =
+                project.render_for_environment(&manifest_file, filenames[1], &environment)?;
 
-        println!("{}", rendered_file);
+            println!("---\n{}", rendered_file);
+        }
 
         exit(0)
     }
@@ -143,7 +301,7 @@ async fn main() -> anyhow::Result<()> {
         println!(
             "{}",
             krator::admission::WebhookResources::from(Project::admission_webhook_resources(
-                &namespace
+                namespace
             ))
         );
 
@@ -155,28 +313,89 @@ async fn main() -> anyhow::Result<()> {
 
     if opts.install_crd {
         info!("installing crd");
-        return self_service_operators::install_crd(&client, &Project::crd())
+        self_service_operators::install_crd(&client, &conversion::crd()).await?;
+        info!("installing manifest bundle crd");
+        return self_service_operators::install_crd(&client, &ManifestBundle::crd())
             .await
             .and(Ok(()));
     }
 
+    if opts.migrate_storage_version {
+        info!("migrating projects to storage version v2");
+        return conversion::migrate_storage_version(&client).await;
+    }
+
+    if let Some(name) = opts.diff {
+        let project = kube::Api::<Project>::all(client.clone())
+            .get(&name)
+            .await
+            .context(format!("error reading project {}", name))?;
+
+        print!(
+            "{}",
+            diff::diff_project(
+                &client,
+                &project,
+                DEFAULT_MANIFESTS_SECRET,
+                namespace,
+                &config
+            )
+            .await?
+        );
+
+        exit(0)
+    }
+
     if !opts.skip_install_admission_controller_manifests {
         info!("installing admission controller resources");
         let resources = krator::admission::WebhookResources::from(
-            Project::admission_webhook_resources(&namespace),
+            Project::admission_webhook_resources(namespace),
         );
 
         resources.apply(&client).await?;
+
+        info!("installing conversion webhook");
+        conversion::install_conversion_webhook(&client, namespace).await?;
+
+        info!("installing manifest secret admission webhook");
+        secret_admission::install_secret_admission_webhook(&client, namespace).await?;
     }
 
+    info!("watching manifest sources");
+    tokio::spawn(source_watch::reconcile_on_source_changes(
+        client.clone(),
+        namespace.to_string(),
+        DEFAULT_MANIFESTS_SECRET.to_string(),
+        config.clone(),
+    ));
+
     let tracker = operator::ProjectOperator::new(
-        client,
-        &namespace,
+        client.clone(),
+        namespace,
         DEFAULT_MANIFESTS_SECRET,
         Duration::from_secs(5),
+        config.clone(),
     )
     .await?;
 
+    info!(
+        "starting conversion webhook on port {}",
+        conversion::CONVERSION_WEBHOOK_PORT
+    );
+    tokio::spawn(conversion::serve(tracker.admission_hook_tls().await?));
+
+    info!(
+        "starting manifest secret admission webhook on port {}",
+        secret_admission::SECRET_ADMISSION_WEBHOOK_PORT
+    );
+    tokio::spawn(secret_admission::serve(
+        tracker.admission_hook_tls().await?,
+        client,
+        namespace.to_string(),
+        DEFAULT_MANIFESTS_SECRET.to_string(),
+        config,
+    ));
+
     info!("starting operator");
     // let params = ListParams::default().labels("nps.gov/park=glacier");
     let mut runtime = OperatorRuntime::new(&kubeconfig, tracker, None);
@@ -0,0 +1,165 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+use anyhow::Context;
+use chrono::{Duration, Utc};
+
+use crate::project::expiry::format_countdown;
+use crate::project::Project;
+
+/// Operator wide settings that are controlled by the cluster admin
+#[derive(Clone, Debug, Default)]
+pub struct ProjectOperatorConfig {
+    /// labels that get set on every project namespace
+    pub namespace_labels: BTreeMap<String, String>,
+    /// annotations that get set on every project namespace
+    pub namespace_annotations: BTreeMap<String, String>,
+    /// label keys projects are allowed to set on their namespace -- a trailing `*` matches all
+    /// keys with the given prefix
+    pub allowed_namespace_labels: Vec<String>,
+    /// annotation keys projects are allowed to set on their namespace -- a trailing `*` matches
+    /// all keys with the given prefix
+    pub allowed_namespace_annotations: Vec<String>,
+    /// group that can view non-private projects and their namespaces
+    pub project_viewer_group: Option<String>,
+    /// maximum time a project can live from now on -- owners can extend their project's expiry
+    /// within this limit
+    pub max_project_ttl: Option<Duration>,
+    /// how long before the expiry of a project a warning gets issued (defaults to one day)
+    pub project_expiry_warning: Option<Duration>,
+    /// handlebars template for the name of project namespaces, with access to the project's
+    /// `name` and `labels` (defaults to the project's name)
+    pub namespace_name_template: Option<String>,
+    /// how long applied resources can take to become ready (defaults to five minutes) --
+    /// manifests can override it with the annotation `project.selfservice.innoq.io/readiness-timeout`
+    pub readiness_timeout: Option<Duration>,
+    /// only report resources that would be pruned instead of deleting them
+    pub prune_dry_run: bool,
+    /// how often projects are fully reconciled, even if neither they nor their resources changed
+    pub resync_interval: Option<Duration>,
+    /// time between re-reconciling two projects after a manifest source they use changed
+    /// (defaults to five seconds) -- a bad change doesn't hit all projects at once
+    pub manifest_rollout_interval: Option<Duration>,
+    /// only warn about changes of manifest secrets that break the manifests of projects instead of
+    /// rejecting them
+    pub manifest_validation_warn_only: bool,
+    /// apply manifests without forcing: fields other field managers changed are reported as
+    /// conflicts instead of being overwritten -- manifests can still force with the annotation
+    /// `project.selfservice.innoq.io/field-ownership: authoritative`
+    pub report_field_conflicts: bool,
+}
+
+impl ProjectOperatorConfig {
+    /// returns the labels and annotations the namespace of `project` should carry: the admin
+    /// defaults, overwritten by the (allowed) labels and annotations of the project spec
+    pub fn namespace_metadata(
+        &self,
+        project: &Project,
+    ) -> anyhow::Result<(BTreeMap<String, String>, BTreeMap<String, String>)> {
+        let labels = merge_allowed(
+            &self.namespace_labels,
+            project.spec.namespace_labels.as_ref(),
+            &self.allowed_namespace_labels,
+            "label",
+        )?;
+
+        let annotations = merge_allowed(
+            &self.namespace_annotations,
+            project.spec.namespace_annotations.as_ref(),
+            &self.allowed_namespace_annotations,
+            "annotation",
+        )?;
+
+        Ok((labels, annotations))
+    }
+
+    /// checks that the expiry of `project` is valid and does not exceed the maximum ttl
+    pub fn validate_expiry(&self, project: &Project) -> anyhow::Result<()> {
+        if let (Some(expiry), Some(max_ttl)) = (project.expiry()?, self.max_project_ttl) {
+            let latest_expiry = Utc::now()
+                .checked_add_signed(max_ttl)
+                .context("the maximum project ttl is too long")?;
+            if expiry > latest_expiry {
+                bail!(
+                    "Invalid project spec: projects can't live longer than {} from now (expiry {} is after {})",
+                    format_countdown(max_ttl),
+                    expiry.to_rfc3339(),
+                    latest_expiry.to_rfc3339()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn project_expiry_warning(&self) -> Duration {
+        self.project_expiry_warning
+            .unwrap_or_else(|| Duration::days(1))
+    }
+
+    pub fn readiness_timeout(&self) -> Duration {
+        self.readiness_timeout
+            .unwrap_or_else(|| Duration::minutes(5))
+    }
+
+    pub fn manifest_rollout_interval(&self) -> Duration {
+        self.manifest_rollout_interval
+            .unwrap_or_else(|| Duration::seconds(5))
+    }
+}
+
+fn merge_allowed(
+    defaults: &BTreeMap<String, String>,
+    requested: Option<&BTreeMap<String, String>>,
+    allowed: &[String],
+    what: &str,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut merged = defaults.clone();
+
+    for (key, value) in requested.into_iter().flatten() {
+        let is_allowed = allowed
+            .iter()
+            .any(|allowed_key| match allowed_key.strip_suffix('*') {
+                Some(prefix) => key.starts_with(prefix),
+                None => key == allowed_key,
+            });
+
+        if !is_allowed {
+            bail!(
+                "Invalid project spec: projects are not allowed to set the namespace {} '{}' (allowed keys: {})",
+                what,
+                key,
+                if allowed.is_empty() { "none".to_string() } else { allowed.join(", ") }
+            );
+        }
+
+        merged.insert(key.clone(), value.clone());
+    }
+
+    Ok(merged)
+}
+
+/// parses a `KEY=VALUE` pair as passed in on the command line
+pub fn parse_key_value(s: &str) -> anyhow::Result<(String, String)> {
+    let (key, value) = s
+        .split_once('=')
+        .context(format!("expected KEY=VALUE, got '{}'", s))?;
+
+    Ok((key.to_string(), value.to_string()))
+}
@@ -0,0 +1,446 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+use anyhow::Context;
+use k8s_openapi::api::core::v1::{Secret, Service, ServicePort, ServiceSpec};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ListMeta, ObjectMeta, Status};
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use k8s_openapi::ByteString;
+use krator::admission::AdmissionTls;
+use kube::api::{ListParams, Patch, PatchParams, PostParams};
+use kube::Resource;
+use serde::{Deserialize, Serialize};
+
+use crate::project::{v2, Project, ProjectSpec};
+
+/// port the conversion webhook listens on -- it is exposed as an additional port of the
+/// admission webhook service, so the admission webhook certificate can be reused
+pub const CONVERSION_WEBHOOK_PORT: u16 = 8444;
+pub const CONVERSION_WEBHOOK_PATH: &str = "convert";
+
+/// the (deprecated) v1 field `manifestValues` has no counterpart in v2 -- when converting to v2 it
+/// is merged into `values` and the original fields are kept in this annotation, so converting
+/// back to v1 restores them as long as the values were not changed in between
+pub const V1_VALUES_ANNOTATION_KEY: &str = "project.selfservice.innoq.io/v1-values";
+
+const V1: &str = "selfservice.innoq.io/v1";
+const V2: &str = "selfservice.innoq.io/v2";
+
+const FIELD_MANAGER: &str = "self-service-operator-conversion";
+
+/// returns the project crd with all versions: `v1` stays the storage version until the storage
+/// version gets migrated with `migrate_storage_version()`. `v2` is only served once
+/// `install_conversion_webhook()` configured the conversion between both versions
+pub fn crd() -> CustomResourceDefinition {
+    let mut crd = Project::crd();
+    let mut v2_version = v2::Project::crd().spec.versions.remove(0);
+    v2_version.storage = false;
+    v2_version.served = false;
+    crd.spec.versions.push(v2_version);
+
+    crd
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct V1Values {
+    manifest_values: Option<String>,
+    values: Option<BTreeMap<String, serde_json::Value>>,
+}
+
+impl From<&ProjectSpec> for V1Values {
+    fn from(spec: &ProjectSpec) -> Self {
+        V1Values {
+            manifest_values: spec.manifest_values.clone(),
+            values: spec.values.clone(),
+        }
+    }
+}
+
+// the values as they are seen by the operator: manifestValues merged with values
+fn merged_values(
+    spec: &ProjectSpec,
+) -> anyhow::Result<Option<BTreeMap<String, serde_json::Value>>> {
+    if spec.manifest_values.is_none() {
+        return Ok(spec.values.clone());
+    }
+
+    let project = Project::new("", spec.clone());
+    let values = serde_json::to_value(project.values()?)?;
+
+    Ok(Some(serde_json::from_value(values).context(
+        "manifestValues can only be converted if all keys are strings",
+    )?))
+}
+
+impl Project {
+    pub fn to_v2(&self) -> anyhow::Result<v2::Project> {
+        let mut metadata = self.metadata.clone();
+
+        if self.spec.manifest_values.is_some() {
+            metadata
+                .annotations
+                .get_or_insert_with(BTreeMap::new)
+                .insert(
+                    V1_VALUES_ANNOTATION_KEY.to_string(),
+                    serde_json::to_string(&V1Values::from(&self.spec))?,
+                );
+        }
+
+        let spec = v2::ProjectSpec(ProjectSpec {
+            values: merged_values(&self.spec)?,
+            manifest_values: None,
+            ..self.spec.clone()
+        });
+
+        let mut project = v2::Project::new(&self.metadata.name.clone().unwrap_or_default(), spec);
+        project.metadata = metadata;
+        project.status = self.status.clone();
+
+        Ok(project)
+    }
+}
+
+impl v2::Project {
+    pub fn to_v1(&self) -> anyhow::Result<Project> {
+        let mut metadata = self.metadata.clone();
+
+        let mut spec = ProjectSpec {
+            manifest_values: None,
+            ..self.spec.0.clone()
+        };
+
+        let v1_values = metadata
+            .annotations
+            .as_mut()
+            .and_then(|annotations| annotations.remove(V1_VALUES_ANNOTATION_KEY));
+
+        if metadata.annotations == Some(BTreeMap::new()) {
+            metadata.annotations = None;
+        }
+
+        // restore the original v1 fields -- unless the values were changed via v2
+        if let Some(v1_values) = v1_values {
+            let v1_values: V1Values = serde_json::from_str(&v1_values).context(format!(
+                "error parsing annotation {}",
+                V1_VALUES_ANNOTATION_KEY
+            ))?;
+
+            let original_spec = ProjectSpec {
+                manifest_values: v1_values.manifest_values,
+                values: v1_values.values,
+                ..spec.clone()
+            };
+
+            if merged_values(&original_spec)? == spec.values {
+                spec = original_spec;
+            }
+        }
+
+        let mut project = Project::new(&self.metadata.name.clone().unwrap_or_default(), spec);
+        project.metadata = metadata;
+        project.status = self.status.clone();
+
+        Ok(project)
+    }
+}
+
+/// converts a project object (as json) to the desired api version
+pub fn convert(
+    object: serde_json::Value,
+    desired_api_version: &str,
+) -> anyhow::Result<serde_json::Value> {
+    let api_version = object["apiVersion"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+
+    Ok(match (api_version.as_str(), desired_api_version) {
+        (from, to) if from == to => object,
+        (V1, V2) => serde_json::to_value(serde_json::from_value::<Project>(object)?.to_v2()?)?,
+        (V2, V1) => serde_json::to_value(serde_json::from_value::<v2::Project>(object)?.to_v1()?)?,
+        (from, to) => bail!("can't convert project from {} to {}", from, to),
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversionReview {
+    pub api_version: String,
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request: Option<ConversionRequest>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<ConversionResponse>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversionRequest {
+    pub uid: String,
+    #[serde(rename = "desiredAPIVersion")]
+    pub desired_api_version: String,
+    pub objects: Vec<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversionResponse {
+    pub uid: String,
+    pub converted_objects: Vec<serde_json::Value>,
+    pub result: Status,
+}
+
+/// answers a conversion review -- if a single object can't be converted, the whole review fails
+pub fn review(review: ConversionReview) -> ConversionReview {
+    let request = match review.request {
+        Some(request) => request,
+        None => {
+            return ConversionReview {
+                response: None,
+                ..review
+            }
+        }
+    };
+
+    let desired_api_version = request.desired_api_version;
+    let converted_objects = request
+        .objects
+        .into_iter()
+        .map(|object| convert(object, &desired_api_version))
+        .collect::<anyhow::Result<Vec<_>>>();
+
+    let (converted_objects, result) = match converted_objects {
+        Ok(converted_objects) => (converted_objects, status("Success", None)),
+        Err(e) => {
+            warn!("error converting projects: {:#}", e);
+            (vec![], status("Failure", Some(format!("{:#}", e))))
+        }
+    };
+
+    ConversionReview {
+        api_version: review.api_version,
+        kind: review.kind,
+        request: None,
+        response: Some(ConversionResponse {
+            uid: request.uid,
+            converted_objects,
+            result,
+        }),
+    }
+}
+
+fn status(status: &str, message: Option<String>) -> Status {
+    Status {
+        code: None,
+        details: None,
+        message,
+        metadata: ListMeta::default(),
+        reason: None,
+        status: Some(status.to_string()),
+    }
+}
+
+/// serves conversion reviews on `CONVERSION_WEBHOOK_PORT` with the certificate of the admission
+/// webhook
+pub async fn serve(tls: AdmissionTls) {
+    use warp::Filter;
+    let routes = warp::post()
+        .and(warp::path(CONVERSION_WEBHOOK_PATH))
+        .and(warp::body::json())
+        .map(|conversion_review: ConversionReview| warp::reply::json(&review(conversion_review)));
+
+    warp::serve(routes)
+        .tls()
+        .cert(tls.cert)
+        .key(tls.private_key)
+        .run(([0, 0, 0, 0], CONVERSION_WEBHOOK_PORT))
+        .await;
+}
+
+/// exposes the conversion webhook on the admission webhook service, configures the crd to use it
+/// and serves all its versions -- the admission webhook resources must have been installed before
+pub async fn install_conversion_webhook(
+    client: &kube::Client,
+    namespace: &str,
+) -> anyhow::Result<()> {
+    let service_name = Project::admission_webhook_service_name();
+
+    // ports of services with more than one port must be named
+    let service = Service {
+        metadata: ObjectMeta {
+            name: Some(service_name.clone()),
+            namespace: Some(namespace.to_string()),
+            ..Default::default()
+        },
+        spec: Some(ServiceSpec {
+            ports: Some(vec![
+                ServicePort {
+                    name: Some("https".to_string()),
+                    protocol: Some("TCP".to_string()),
+                    port: 443,
+                    target_port: Some(IntOrString::Int(8443)),
+                    ..Default::default()
+                },
+                ServicePort {
+                    name: Some("conversion".to_string()),
+                    protocol: Some("TCP".to_string()),
+                    port: CONVERSION_WEBHOOK_PORT.into(),
+                    target_port: Some(IntOrString::Int(CONVERSION_WEBHOOK_PORT.into())),
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        }),
+        status: None,
+    };
+
+    kube::Api::<Service>::namespaced(client.clone(), namespace)
+        .patch(
+            &service_name,
+            &PatchParams::apply(FIELD_MANAGER).force(),
+            &Patch::Apply(&service),
+        )
+        .await
+        .context(format!(
+            "error adding the conversion port to service {}/{}",
+            namespace, service_name
+        ))?;
+
+    let ca_bundle = admission_webhook_ca_bundle(client, namespace).await?;
+
+    let crd_api = kube::Api::<CustomResourceDefinition>::all(client.clone());
+    let crd_name = Project::crd().metadata.name.unwrap();
+    let mut versions = crd_api
+        .get(&crd_name)
+        .await
+        .context(format!("error reading crd {}", crd_name))?
+        .spec
+        .versions;
+    for version in versions.iter_mut() {
+        version.served = true;
+    }
+
+    let conversion = serde_json::json!({
+        "spec": {
+            "conversion": {
+                "strategy": "Webhook",
+                "webhook": {
+                    "conversionReviewVersions": ["v1"],
+                    "clientConfig": {
+                        "caBundle": ca_bundle,
+                        "service": {
+                            "name": service_name,
+                            "namespace": namespace,
+                            "path": format!("/{}", CONVERSION_WEBHOOK_PATH),
+                            "port": CONVERSION_WEBHOOK_PORT,
+                        }
+                    }
+                }
+            },
+            // versions can only be served along with the conversion between them
+            "versions": versions,
+        }
+    });
+
+    crd_api
+        .patch(
+            &crd_name,
+            &PatchParams::default(),
+            &Patch::Merge(&conversion),
+        )
+        .await
+        .context(format!(
+            "error configuring the conversion webhook of crd {}",
+            crd_name
+        ))?;
+
+    Ok(())
+}
+
+/// the certificate of the admission webhook -- webhooks served with it (see `serve()`) use it as
+/// their ca bundle
+pub async fn admission_webhook_ca_bundle(
+    client: &kube::Client,
+    namespace: &str,
+) -> anyhow::Result<ByteString> {
+    let secret_name = Project::admission_webhook_secret_name();
+    kube::Api::<Secret>::namespaced(client.clone(), namespace)
+        .get(&secret_name)
+        .await?
+        .data
+        .and_then(|mut data| data.remove("tls.crt"))
+        .context(format!(
+            "secret {}/{} does not contain a certificate",
+            namespace, secret_name
+        ))
+}
+
+/// makes `v2` the storage version: all projects are rewritten so they get stored as `v2` and `v1`
+/// is removed from the stored versions of the crd -- this needs a running operator, as the objects
+/// are converted by its conversion webhook
+pub async fn migrate_storage_version(client: &kube::Client) -> anyhow::Result<()> {
+    let crd_api = kube::Api::<CustomResourceDefinition>::all(client.clone());
+    let crd_name = Project::crd().metadata.name.unwrap();
+
+    let mut crd = crd_api.get(&crd_name).await?;
+    for version in crd.spec.versions.iter_mut() {
+        version.storage = version.name == "v2";
+    }
+    ensure_version_exists(&crd, "v2")?;
+    info!("making v2 the storage version of crd {}", crd_name);
+    crd_api
+        .replace(&crd_name, &PostParams::default(), &crd)
+        .await?;
+
+    let project_api = kube::Api::<v2::Project>::all(client.clone());
+    for project in project_api.list(&ListParams::default()).await?.items {
+        let name = project.meta().name.clone().unwrap_or_default();
+        info!("migrating project {} to storage version v2", name);
+        project_api
+            .replace(&name, &PostParams::default(), &project)
+            .await
+            .context(format!("error migrating project {}", name))?;
+    }
+
+    crd_api
+        .patch_status(
+            &crd_name,
+            &PatchParams::default(),
+            &Patch::Merge(serde_json::json!({ "status": { "storedVersions": ["v2"] } })),
+        )
+        .await?;
+
+    info!("all projects are stored as v2");
+
+    Ok(())
+}
+
+fn ensure_version_exists(crd: &CustomResourceDefinition, version: &str) -> anyhow::Result<()> {
+    if !crd.spec.versions.iter().any(|v| v.name == version) {
+        bail!(
+            "crd {} does not serve version {} -- install the current crd first",
+            crd.metadata.name.clone().unwrap_or_default(),
+            version
+        );
+    }
+
+    Ok(())
+}
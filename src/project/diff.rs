@@ -0,0 +1,281 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! preview of a reconciliation: the manifests of a project are dry-run against the api server and
+//! the results are diffed against the live objects
+
+use http::Request;
+use serde_json::Value;
+
+use crate::project::one_shot::{needs_apply, one_shot_mode, one_shot_record};
+use crate::project::prune::{is_prunable, stale_resources};
+use crate::project::states::apply_manifests::{
+    add_owner_to_yaml_manifest, describe_manifest, field_manager_query_args, field_ownership,
+    resource_path, FieldOwnership,
+};
+use crate::project::{Project, ProjectOperatorConfig};
+
+/// lines of context around changes
+const CONTEXT: usize = 3;
+
+#[derive(Debug, PartialEq)]
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+// the lines of `old` and `new`, aligned along their longest common subsequence
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Line<'a>> {
+    let (n, m) = (old.len(), new.len());
+
+    // lcs[i][j]: length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            lines.push(Line::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            lines.push(Line::Removed(old[i]));
+            i += 1;
+        } else {
+            lines.push(Line::Added(new[j]));
+            j += 1;
+        }
+    }
+    lines.extend(old[i..].iter().map(|line| Line::Removed(line)));
+    lines.extend(new[j..].iter().map(|line| Line::Added(line)));
+
+    lines
+}
+
+// a hunk range: `start,len` with `start` counted from 1 -- an empty range starts at the line before
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+/// a unified diff (with three lines of context) of two texts -- empty if they are equal
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    let old_lines = old.lines().collect::<Vec<_>>();
+    let new_lines = new.lines().collect::<Vec<_>>();
+    let lines = diff_lines(&old_lines, &new_lines);
+
+    // hunks as ranges of `lines`: changes with their context, merged if they overlap
+    let mut hunks: Vec<(usize, usize)> = vec![];
+    for (i, _) in lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Line::Same(_)))
+    {
+        let (start, end) = (
+            i.saturating_sub(CONTEXT),
+            (i + CONTEXT + 1).min(lines.len()),
+        );
+        match hunks.last_mut() {
+            Some((_, hunk_end)) if start <= *hunk_end => *hunk_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    let mut diff = format!("--- {}\n+++ {}\n", old_name, new_name);
+    for (start, end) in hunks {
+        let count = |lines: &[Line], skip: fn(&Line) -> bool| -> usize {
+            lines.iter().filter(|line| !skip(line)).count()
+        };
+        let is_added = |line: &Line| matches!(line, Line::Added(_));
+        let is_removed = |line: &Line| matches!(line, Line::Removed(_));
+
+        diff.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(
+                count(&lines[..start], is_added),
+                count(&lines[start..end], is_added)
+            ),
+            hunk_range(
+                count(&lines[..start], is_removed),
+                count(&lines[start..end], is_removed)
+            ),
+        ));
+
+        for line in &lines[start..end] {
+            let (prefix, text) = match line {
+                Line::Same(text) => (' ', text),
+                Line::Removed(text) => ('-', text),
+                Line::Added(text) => ('+', text),
+            };
+            diff.push_str(&format!("{}{}\n", prefix, text));
+        }
+    }
+
+    diff
+}
+
+/// an object (as returned by the api server) without the fields that are maintained by the api
+/// server and would clutter diffs: the status and the bookkeeping fields of the metadata
+pub fn comparable(object: &Value) -> Value {
+    let mut object = object.clone();
+    if let Some(object) = object.as_object_mut() {
+        object.remove("status");
+    }
+    if let Some(metadata) = object["metadata"].as_object_mut() {
+        for field in &[
+            "managedFields",
+            "resourceVersion",
+            "uid",
+            "generation",
+            "creationTimestamp",
+            "selfLink",
+        ] {
+            metadata.remove(*field);
+        }
+    }
+
+    object
+}
+
+// the object as yaml for diffs -- nothing if there is no object
+fn diffable(object: Option<&Value>) -> anyhow::Result<String> {
+    match object {
+        Some(object) => Ok(serde_yaml::to_string(&comparable(object))?
+            .trim_start_matches("---\n")
+            .to_string()),
+        None => Ok(String::new()),
+    }
+}
+
+// reads an object -- `None` if it doesn't exist
+async fn live_object(client: &kube::Client, path: &str) -> anyhow::Result<Option<Value>> {
+    let request = Request::builder()
+        .uri(path)
+        .method("GET")
+        .body("".into())
+        .unwrap();
+
+    match client.request::<Value>(request).await {
+        Ok(object) => Ok(Some(object)),
+        Err(kube::Error::Api(e)) if e.code == 404 => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// what a reconciliation of `project` would change: a unified diff per object between the live
+/// object and the result of a server-side apply dry run of its manifest. Objects that would be
+/// pruned are shown as deleted; manifests that can't be dry-run are listed as `#` comments
+pub async fn diff_project(
+    client: &kube::Client,
+    project: &Project,
+    default_manifests_secret: &str,
+    namespace: &str,
+    config: &ProjectOperatorConfig,
+) -> anyhow::Result<String> {
+    let status = project.status.clone().unwrap_or_default();
+
+    let mut diff = String::new();
+    let mut paths = vec![];
+    for manifest in project
+        .associated_manifests(client, default_manifests_secret, namespace, config)
+        .await?
+    {
+        let path = match resource_path(client, &manifest).await {
+            Ok(path) => path,
+            Err(e) => {
+                diff.push_str(&format!("# {}: {:#}\n", describe_manifest(&manifest), e));
+                continue;
+            }
+        };
+        paths.push(path.clone());
+
+        // one shot resources are only applied when their mode says so
+        if let Some(mode) = one_shot_mode(&manifest)? {
+            let record = one_shot_record(mode, &path, &manifest, project);
+            if !needs_apply(mode, &record, &status.applied_one_shot_resources) {
+                continue;
+            }
+        }
+
+        let live = live_object(client, &path).await?;
+
+        // as when applying, existing `initial` resources are left alone and resources that aren't
+        // forced can run into field conflicts
+        let ownership = field_ownership(&manifest, config)?;
+        if ownership == FieldOwnership::Initial && live.is_some() {
+            continue;
+        }
+
+        let request = Request::builder()
+            .uri(format!(
+                "{}?{}&dryRun=All",
+                path,
+                field_manager_query_args(ownership != FieldOwnership::Shared)
+            ))
+            .method("PATCH")
+            .header("Content-Type", "application/apply-patch+yaml")
+            .body(add_owner_to_yaml_manifest(&manifest, project)?.into())
+            .unwrap();
+
+        let reconciled = match client.request::<Value>(request).await {
+            Ok(reconciled) => reconciled,
+            Err(e) => {
+                diff.push_str(&format!("# {}: {}\n", describe_manifest(&manifest), e));
+                continue;
+            }
+        };
+        diff.push_str(&unified_diff(
+            &diffable(live.as_ref())?,
+            &diffable(Some(&reconciled))?,
+            &format!("{} (live)", path),
+            &format!("{} (reconciled)", path),
+        ));
+    }
+
+    if !config.prune_dry_run {
+        for path in stale_resources(&status.inventory.unwrap_or_default(), &paths) {
+            if let Some(live) = live_object(client, &path).await? {
+                if is_prunable(&live, project) {
+                    diff.push_str(&unified_diff(
+                        &diffable(Some(&live))?,
+                        "",
+                        &format!("{} (live)", path),
+                        &format!("{} (pruned)", path),
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(diff)
+}
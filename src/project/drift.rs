@@ -0,0 +1,242 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! drift correction: the resources that were applied for a project are watched while the project
+//! waits for changes -- resources that get deleted or whose spec or metadata is modified are
+//! re-applied (status updates are ignored). There is one watch per collection (kind and
+//! namespace) of the applied resources, which selects them by their `applied-for` label. The
+//! watches run in the background, so they survive the wake-ups of a waiting project
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::bail;
+use futures::{StreamExt, TryStreamExt};
+use http::Request;
+use kube::api::WatchEvent;
+use serde_json::Value;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::project::diff::comparable;
+use crate::project::project::APPLIED_FOR_LABEL_KEY;
+use crate::project::states::apply_manifests::{apply_yaml_manifest, Applied};
+use crate::project::states::ProjectState;
+use crate::project::{Project, ProjectOperatorConfig};
+
+const WATCH_RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// the watches of the resources that were applied for a project -- they are stopped once the last
+/// clone of this is dropped
+#[derive(Clone)]
+pub struct DriftWatch(Arc<Watches>);
+
+struct Watches {
+    paths: Vec<String>,
+    drifted: Mutex<mpsc::UnboundedReceiver<String>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Watches {
+    fn drop(&mut self) {
+        for task in self.tasks.iter() {
+            task.abort();
+        }
+    }
+}
+
+impl DriftWatch {
+    /// starts watching the resources with the given api paths, which were applied for the project
+    /// `project_name`
+    pub fn start(client: &kube::Client, project_name: &str, paths: Vec<String>) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let label_selector = format!("{}%3D{}", APPLIED_FOR_LABEL_KEY, project_name);
+
+        let tasks = collections(&paths)
+            .into_iter()
+            .map(|(collection, names)| {
+                tokio::spawn(watch_collection(
+                    client.clone(),
+                    collection,
+                    label_selector.clone(),
+                    names,
+                    sender.clone(),
+                ))
+            })
+            .collect();
+
+        DriftWatch(Arc::new(Watches {
+            paths,
+            drifted: Mutex::new(receiver),
+            tasks,
+        }))
+    }
+
+    /// whether exactly the resources with the given api paths are watched
+    pub fn watches(&self, paths: &[String]) -> bool {
+        self.0.paths == paths
+    }
+
+    /// the api path of the next resource that drifted: its spec or metadata was modified, or it
+    /// was deleted (or didn't exist when the watch started) -- waits until there is one
+    pub async fn next_drifted(&self) -> String {
+        match self.0.drifted.lock().await.recv().await {
+            Some(path) => path,
+            None => futures::future::pending().await,
+        }
+    }
+}
+
+// the api paths grouped by their collection, e.g. `/api/v1/namespaces/foo/configmaps` -- with the
+// names of the resources in it
+fn collections(paths: &[String]) -> BTreeMap<String, BTreeSet<String>> {
+    let mut collections: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for path in paths {
+        if let (Some(collection), Some(name)) =
+            (Path::new(path).parent(), Path::new(path).file_name())
+        {
+            collections
+                .entry(collection.display().to_string())
+                .or_default()
+                .insert(name.to_string_lossy().to_string());
+        }
+    }
+
+    collections
+}
+
+// watches the resources `names` of a collection and sends the paths of the ones that drift --
+// watches end (e.g. when they time out), so the collection is listed again before the watch is
+// restarted: changes in between are not missed
+async fn watch_collection(
+    client: kube::Client,
+    collection: String,
+    label_selector: String,
+    names: BTreeSet<String>,
+    drifted: mpsc::UnboundedSender<String>,
+) {
+    let mut last_seen = BTreeMap::new();
+    loop {
+        if let Err(e) = watch_collection_once(
+            &client,
+            &collection,
+            &label_selector,
+            &names,
+            &mut last_seen,
+            &drifted,
+        )
+        .await
+        {
+            warn!("error watching {} for drift: {:#}", collection, e);
+        }
+
+        tokio::time::sleep(WATCH_RESTART_DELAY).await;
+    }
+}
+
+async fn watch_collection_once(
+    client: &kube::Client,
+    collection: &str,
+    label_selector: &str,
+    names: &BTreeSet<String>,
+    last_seen: &mut BTreeMap<String, Value>,
+    drifted: &mpsc::UnboundedSender<String>,
+) -> anyhow::Result<()> {
+    let path = |name: &str| format!("{}/{}", collection, name);
+    let name = |resource: &Value| resource["metadata"]["name"].as_str().map(String::from);
+
+    let request = Request::builder()
+        .uri(format!("{}?labelSelector={}", collection, label_selector))
+        .method("GET")
+        .body("".into())
+        .unwrap();
+    let list = client.request::<Value>(request).await?;
+
+    let mut listed = BTreeMap::new();
+    for resource in list["items"].as_array().into_iter().flatten() {
+        if let Some(name) = name(resource).filter(|name| names.contains(name)) {
+            listed.insert(name, comparable(resource));
+        }
+    }
+
+    for name in names {
+        match listed.remove(name) {
+            Some(resource) => {
+                if last_seen.get(name).is_some_and(|seen| *seen != resource) {
+                    drifted.send(path(name))?;
+                }
+                last_seen.insert(name.clone(), resource);
+            }
+            None => {
+                last_seen.remove(name);
+                drifted.send(path(name))?;
+            }
+        }
+    }
+
+    let request = Request::builder()
+        .uri(format!(
+            "{}?watch=true&labelSelector={}&resourceVersion={}",
+            collection,
+            label_selector,
+            list["metadata"]["resourceVersion"]
+                .as_str()
+                .unwrap_or_default()
+        ))
+        .method("GET")
+        .body("".into())
+        .unwrap();
+    let mut events = client.request_events::<Value>(request).await?.boxed();
+
+    while let Some(event) = events.try_next().await? {
+        match event {
+            // e.g. status updates don't change what was applied
+            WatchEvent::Added(resource) | WatchEvent::Modified(resource) => {
+                if let Some(name) = name(&resource).filter(|name| names.contains(name)) {
+                    let resource = comparable(&resource);
+                    if last_seen.get(&name).is_some_and(|seen| *seen != resource) {
+                        drifted.send(path(&name))?;
+                    }
+                    last_seen.insert(name, resource);
+                }
+            }
+            WatchEvent::Deleted(resource) => {
+                if let Some(name) = name(&resource).filter(|name| names.contains(name)) {
+                    last_seen.remove(&name);
+                    drifted.send(path(&name))?;
+                }
+            }
+            WatchEvent::Error(e) => bail!(e.message),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// re-applies a resource -- returns whether this changed (or recreated) it, i.e. whether it had
+/// drifted from its manifest
+pub async fn correct_drift(
+    client: &kube::Client,
+    manifest: &str,
+    project: &Project,
+    state: &mut ProjectState,
+    config: &ProjectOperatorConfig,
+) -> anyhow::Result<bool> {
+    Ok(apply_yaml_manifest(client, manifest, project, state, config).await? == Applied::Changed)
+}
@@ -0,0 +1,211 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! server-side dry run of rendered manifests: the api server checks them (kinds, namespaces,
+//! schemas, admission) without persisting anything, so invalid projects can be rejected before
+//! they are stored
+
+use http::Request;
+use k8s_openapi::api::core::v1::Namespace;
+use serde_yaml::Value;
+
+use crate::project::one_shot::{needs_apply, one_shot_mode, one_shot_record};
+use crate::project::states::apply_manifests::{
+    describe_manifest, field_ownership, resource_path, FieldOwnership, FIELD_MANAGER_QUERY_ARG,
+};
+use crate::project::{Project, ProjectOperatorConfig};
+
+/// the kinds (as `(group, kind)`) of the custom resource definitions among `manifests` -- objects
+/// of these kinds can't be checked before the definitions are applied
+pub fn kinds_defined_by_crds(manifests: &[String]) -> Vec<(String, String)> {
+    manifests
+        .iter()
+        .filter_map(|manifest| serde_yaml::from_str::<Value>(manifest).ok())
+        .filter(|yaml| yaml["kind"].as_str() == Some("CustomResourceDefinition"))
+        .filter_map(|yaml| {
+            Some((
+                yaml["spec"]["group"].as_str()?.to_string(),
+                yaml["spec"]["names"]["kind"].as_str()?.to_string(),
+            ))
+        })
+        .collect()
+}
+
+/// the names of the namespaces among `manifests`
+pub fn namespaces_defined_by_manifests(manifests: &[String]) -> Vec<String> {
+    manifests
+        .iter()
+        .filter_map(|manifest| serde_yaml::from_str::<Value>(manifest).ok())
+        .filter(|yaml| yaml["kind"].as_str() == Some("Namespace"))
+        .filter_map(|yaml| yaml["metadata"]["name"].as_str().map(String::from))
+        .collect()
+}
+
+// the group of an api version, e.g. `apps` for `apps/v1` and `` for `v1`
+fn group(api_version: &str) -> &str {
+    api_version
+        .rsplit_once('/')
+        .map(|(group, _)| group)
+        .unwrap_or_default()
+}
+
+/// the outcome of a dry run of the manifests of a project
+#[derive(Debug, Default, PartialEq)]
+pub struct DryRun {
+    /// a description of every manifest the api server would reject
+    pub failures: Vec<String>,
+    /// a description of every manifest that could not be checked because its namespace doesn't
+    /// exist yet
+    pub unchecked: Vec<String>,
+}
+
+/// dry runs the `manifests` of `project` that a reconciliation would apply. As when applying, one
+/// shot resources that are not due and existing `initial` resources are skipped.
+///
+/// Objects in namespaces that will be created for the project (`namespaces`, or namespaces among
+/// the manifests) but don't exist yet can't be checked -- the api server rejects them without
+/// looking at them -- so they are reported as unchecked
+pub async fn dry_run(
+    client: &kube::Client,
+    project: &Project,
+    manifests: &[String],
+    namespaces: &[String],
+    config: &ProjectOperatorConfig,
+) -> DryRun {
+    let records = project
+        .status
+        .as_ref()
+        .map(|status| status.applied_one_shot_resources.clone())
+        .unwrap_or_default();
+
+    let defined_kinds = kinds_defined_by_crds(manifests);
+
+    let mut pending_namespaces = vec![];
+    for namespace in namespaces
+        .iter()
+        .cloned()
+        .chain(namespaces_defined_by_manifests(manifests))
+    {
+        if kube::Api::<Namespace>::all(client.clone())
+            .get(&namespace)
+            .await
+            .is_err()
+        {
+            pending_namespaces.push(namespace);
+        }
+    }
+
+    let mut result = DryRun::default();
+    for manifest in manifests {
+        let yaml: Value = match serde_yaml::from_str(manifest) {
+            Ok(yaml) => yaml,
+            Err(e) => {
+                result
+                    .failures
+                    .push(format!("{}: {}", describe_manifest(manifest), e));
+                continue;
+            }
+        };
+
+        let kind = (
+            group(yaml["apiVersion"].as_str().unwrap_or_default()).to_string(),
+            yaml["kind"].as_str().unwrap_or_default().to_string(),
+        );
+        if defined_kinds.contains(&kind) {
+            continue;
+        }
+
+        if let Some(namespace) = yaml["metadata"]["namespace"].as_str().filter(|namespace| {
+            pending_namespaces
+                .iter()
+                .any(|pending| pending == namespace)
+        }) {
+            result.unchecked.push(format!(
+                "{}: namespace {} doesn't exist yet",
+                describe_manifest(manifest),
+                namespace
+            ));
+            continue;
+        }
+
+        match is_skipped(client, project, manifest, &records, config).await {
+            Ok(false) => {}
+            Ok(true) => continue,
+            Err(e) => {
+                result
+                    .failures
+                    .push(format!("{}: {:#}", describe_manifest(manifest), e));
+                continue;
+            }
+        }
+
+        if let Err(e) = dry_run_apply(client, manifest).await {
+            result
+                .failures
+                .push(format!("{}: {:#}", describe_manifest(manifest), e));
+        }
+    }
+
+    result
+}
+
+// whether a reconciliation would leave the object of a manifest alone: a one shot resource that
+// is not due, or an `initial` resource that exists already
+async fn is_skipped(
+    client: &kube::Client,
+    project: &Project,
+    manifest: &str,
+    records: &[String],
+    config: &ProjectOperatorConfig,
+) -> anyhow::Result<bool> {
+    let path = resource_path(client, manifest).await?;
+
+    if let Some(mode) = one_shot_mode(manifest)? {
+        let record = one_shot_record(mode, &path, manifest, project);
+        if !needs_apply(mode, &record, records) {
+            return Ok(true);
+        }
+    }
+
+    if field_ownership(manifest, config)? != FieldOwnership::Initial {
+        return Ok(false);
+    }
+
+    let request = Request::builder()
+        .uri(path)
+        .method("GET")
+        .body("".into())
+        .unwrap();
+    match client.request_text(request).await {
+        Ok(_) => Ok(true),
+        Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// dry runs a server side apply of a manifest
+async fn dry_run_apply(client: &kube::Client, manifest: &str) -> anyhow::Result<()> {
+    let path = resource_path(client, manifest).await?;
+    let request = Request::builder()
+        .uri(format!("{}?{}&dryRun=All", path, FIELD_MANAGER_QUERY_ARG))
+        .method("PATCH")
+        .header("Content-Type", "application/apply-patch+yaml")
+        .body(manifest.to_string().into())
+        .unwrap();
+
+    client.request_text(request).await?;
+    Ok(())
+}
@@ -0,0 +1,262 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashSet;
+
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context;
+use handlebars::Handlebars;
+use k8s_openapi::api::core::v1::Namespace;
+use kube::api::ListParams;
+use kube::Resource;
+
+use crate::project::{Project, ProjectOperatorConfig};
+
+/// label that marks namespaces with the name of the project they belong to
+pub const PROJECT_LABEL_KEY: &str = "project.selfservice.innoq.io/project";
+/// label that marks namespaces with the environment they were created for
+pub const ENVIRONMENT_LABEL_KEY: &str = "project.selfservice.innoq.io/environment";
+
+// namespace names must be valid DNS labels
+const MAX_NAMESPACE_LENGTH: usize = 63;
+
+/// an environment of a project and the namespace it lives in -- projects without environments
+/// have exactly one environment without a name
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProjectEnvironment {
+    pub name: Option<String>,
+    pub namespace: String,
+}
+
+impl Project {
+    /// returns the name of the project's namespace as defined by the operator's naming template
+    /// -- environments get their own namespaces `<namespace>-<environment>`
+    pub fn namespace_name(&self, config: &ProjectOperatorConfig) -> anyhow::Result<String> {
+        let name = self.metadata.name.clone().unwrap_or_default();
+        let template = match &config.namespace_name_template {
+            Some(template) => template,
+            None => return Ok(name),
+        };
+
+        let mut reg = Handlebars::new();
+        reg.set_strict_mode(true);
+        reg.register_escape_fn(handlebars::no_escape);
+
+        let data = serde_json::json!({
+            "name": name,
+            "labels": self.metadata.labels.clone().unwrap_or_default(),
+        });
+
+        let namespace = reg
+            .render_template(template, &data)
+            .context(format!(
+                "error rendering namespace name template '{}' for project '{}'",
+                template, name
+            ))?
+            .trim()
+            .to_string();
+
+        Ok(namespace)
+    }
+
+    /// returns the environments of this project: one `<namespace>-<env>` namespace per entry in
+    /// `spec.environments` or just the project's namespace if no environments are configured
+    pub fn environments(
+        &self,
+        config: &ProjectOperatorConfig,
+    ) -> anyhow::Result<Vec<ProjectEnvironment>> {
+        let namespace = self.namespace_name(config)?;
+
+        if self.spec.environments.is_empty() {
+            return Ok(vec![ProjectEnvironment {
+                name: None,
+                namespace,
+            }]);
+        }
+
+        Ok(self
+            .spec
+            .environments
+            .iter()
+            .map(|environment| ProjectEnvironment {
+                name: Some(environment.clone()),
+                namespace: format!("{}-{}", namespace, environment),
+            })
+            .collect())
+    }
+
+    /// names of all namespaces that belong to this project
+    pub fn namespaces(&self, config: &ProjectOperatorConfig) -> anyhow::Result<Vec<String>> {
+        Ok(self
+            .environments(config)?
+            .into_iter()
+            .map(|environment| environment.namespace)
+            .collect())
+    }
+
+    /// checks that environment names are unique and that all namespace names are valid
+    pub fn validate_environments(&self, config: &ProjectOperatorConfig) -> anyhow::Result<()> {
+        let mut seen = HashSet::new();
+
+        for environment in self.spec.environments.iter() {
+            ensure!(
+                seen.insert(environment),
+                "Invalid project spec: environment '{}' is listed more than once",
+                environment
+            );
+
+            if !is_dns_label(environment) {
+                bail!(
+                    "Invalid project spec: environment '{}' must consist of lower case alphanumeric characters or '-' and must start and end with an alphanumeric character",
+                    environment
+                );
+            }
+        }
+
+        for namespace in self.namespaces(config)? {
+            ensure!(
+                namespace.len() <= MAX_NAMESPACE_LENGTH,
+                "Invalid project spec: namespace name '{}' is longer than {} characters -- use a shorter project or environment name",
+                namespace,
+                MAX_NAMESPACE_LENGTH
+            );
+
+            ensure!(
+                is_dns_label(&namespace),
+                "namespace name '{}' of project '{}' is not a valid namespace name -- check the operator's namespace name template",
+                namespace,
+                self.metadata.name.clone().unwrap_or_default()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// the namespaces (as `(old name, new name)`) that `environments` would rename: namespaces of the
+/// project (given as their name and environment label) whose environment is still part of the
+/// project, but under a different namespace name -- e.g. because a label used by the namespace
+/// name template changed
+pub fn renamed_namespaces(
+    existing: &[(String, Option<String>)],
+    environments: &[ProjectEnvironment],
+) -> Vec<(String, String)> {
+    existing
+        .iter()
+        .filter_map(|(namespace, environment_name)| {
+            environments
+                .iter()
+                .find(|environment| &environment.name == environment_name)
+                .filter(|environment| &environment.namespace != namespace)
+                .map(|environment| (namespace.clone(), environment.namespace.clone()))
+        })
+        .collect()
+}
+
+/// the namespaces of the project (given as their name and environment label) that don't belong to
+/// any of `environments` -- e.g. because their environment was removed or because the project
+/// switched from its single namespace to environments
+pub fn orphaned_namespaces(
+    existing: &[(String, Option<String>)],
+    environments: &[ProjectEnvironment],
+) -> Vec<String> {
+    existing
+        .iter()
+        .filter(|(namespace, _)| {
+            !environments
+                .iter()
+                .any(|environment| &environment.namespace == namespace)
+        })
+        .map(|(namespace, _)| namespace.clone())
+        .collect()
+}
+
+/// the namespaces that were created for the project, with their environment label
+pub async fn project_namespaces(
+    client: &kube::Client,
+    project: &Project,
+) -> anyhow::Result<Vec<(String, Option<String>)>> {
+    let project_name = project.metadata.name.clone().unwrap_or_default();
+    let lp = ListParams::default().labels(&format!("{}={}", PROJECT_LABEL_KEY, project_name));
+
+    Ok(kube::Api::<Namespace>::all(client.clone())
+        .list(&lp)
+        .await?
+        .items
+        .into_iter()
+        .filter(|namespace| {
+            namespace
+                .metadata
+                .owner_references
+                .iter()
+                .flatten()
+                .any(|owner| owner.kind == Project::kind(&()) && owner.name == project_name)
+        })
+        .map(|namespace| {
+            let environment = namespace
+                .metadata
+                .labels
+                .as_ref()
+                .and_then(|labels| labels.get(ENVIRONMENT_LABEL_KEY))
+                .cloned();
+            (namespace.metadata.name.unwrap_or_default(), environment)
+        })
+        .collect())
+}
+
+/// fails if `environments` would rename namespaces of the project (given as in
+/// `renamed_namespaces()`) -- the old namespace would be orphaned and a new, empty one created
+pub fn ensure_namespaces_are_not_renamed(
+    existing: &[(String, Option<String>)],
+    environments: &[ProjectEnvironment],
+) -> anyhow::Result<()> {
+    if let Some((old, new)) = renamed_namespaces(existing, environments).first() {
+        bail!(
+            "Invalid project: this change would rename namespace '{}' to '{}' -- namespace names can't change once the namespace exists, check the labels used by the operator's namespace name template",
+            old,
+            new
+        );
+    }
+
+    Ok(())
+}
+
+/// fails if `environments` would orphan namespaces of the project (given as in
+/// `orphaned_namespaces()`) -- namespaces hold the data of their environment, so they are never
+/// deleted because of a change of the project
+pub fn ensure_namespaces_are_not_orphaned(
+    existing: &[(String, Option<String>)],
+    environments: &[ProjectEnvironment],
+) -> anyhow::Result<()> {
+    if let Some(namespace) = orphaned_namespaces(existing, environments).first() {
+        bail!(
+            "Invalid project: namespace '{}' would no longer belong to an environment of this project -- namespaces are not deleted when their environment is removed (or when a project without environments gets environments), delete namespace '{}' first if it is not needed anymore",
+            namespace,
+            namespace
+        );
+    }
+
+    Ok(())
+}
+
+fn is_dns_label(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !s.starts_with('-')
+        && !s.ends_with('-')
+}
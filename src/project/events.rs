@@ -0,0 +1,202 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use chrono::Utc;
+use k8s_openapi::api::core::v1::{Event, EventSource, Namespace, ObjectReference};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+use kube::api::{ListParams, Patch, PatchParams, PostParams};
+use kube::Resource;
+
+use crate::project::Project;
+
+pub const EVENT_TYPE_NORMAL: &str = "Normal";
+pub const EVENT_TYPE_WARNING: &str = "Warning";
+
+// event reasons are part of the operator's interface: alerts match on them, so don't change them
+pub const REASON_NAMESPACE_CREATED: &str = "NamespaceCreated";
+pub const REASON_NAMESPACE_ORPHANED: &str = "NamespaceOrphaned";
+pub const REASON_MANIFEST_APPLIED: &str = "ManifestApplied";
+pub const REASON_MANIFEST_APPLY_RETRY: &str = "ManifestApplyRetry";
+pub const REASON_PROJECT_READY: &str = "ProjectReady";
+pub const REASON_RECONCILE_ERROR: &str = "ReconcileError";
+pub const REASON_PROJECT_EXPIRING: &str = "ProjectExpiring";
+pub const REASON_PROJECT_EXPIRED: &str = "ProjectExpired";
+pub const REASON_PROJECT_RELEASED: &str = "ProjectReleased";
+pub const REASON_RESOURCE_PRUNED: &str = "ResourcePruned";
+pub const REASON_DRIFT_CORRECTED: &str = "DriftCorrected";
+pub const REASON_RESOURCES_RETAINED: &str = "ResourcesRetained";
+pub const REASON_FIELD_CONFLICT: &str = "FieldConflict";
+
+const EVENT_SOURCE_COMPONENT: &str = "self-service-project-operator";
+
+// events of cluster scoped objects like projects have to be stored in this namespace
+const PROJECT_EVENT_NAMESPACE: &str = "default";
+
+/// records an event for `project` -- as projects are cluster scoped, the event is stored in
+/// `default`. So owners can see it, a copy is recorded for the first of the project's namespaces
+/// (errors recording the copy are only logged)
+pub async fn publish_event(
+    client: &kube::Client,
+    project: &Project,
+    namespaces: &[String],
+    event_type: &str,
+    reason: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    let involved_object = ObjectReference {
+        api_version: Some(Project::api_version(&()).to_string()),
+        kind: Some(Project::kind(&()).to_string()),
+        name: project.metadata.name.clone(),
+        uid: project.metadata.uid.clone(),
+        resource_version: project.metadata.resource_version.clone(),
+        ..Default::default()
+    };
+
+    create_event(
+        client,
+        PROJECT_EVENT_NAMESPACE,
+        involved_object,
+        event_type,
+        reason,
+        message,
+    )
+    .await?;
+
+    if let Some(namespace) = namespaces.first() {
+        record_namespace_event(client, namespace, event_type, reason, message).await;
+    }
+
+    Ok(())
+}
+
+/// like `publish_event()`, but errors are only logged -- events must not interrupt the
+/// reconciliation of a project
+pub async fn record_event(
+    client: &kube::Client,
+    project: &Project,
+    namespaces: &[String],
+    event_type: &str,
+    reason: &str,
+    message: &str,
+) {
+    if let Err(e) = publish_event(client, project, namespaces, event_type, reason, message).await {
+        warn!(
+            "error recording event {} for project {}: {}",
+            reason,
+            project.metadata.name.clone().unwrap_or_default(),
+            e
+        );
+    }
+}
+
+/// records an event for one of the project's namespaces -- errors are only logged
+pub async fn record_namespace_event(
+    client: &kube::Client,
+    namespace: &str,
+    event_type: &str,
+    reason: &str,
+    message: &str,
+) {
+    let involved_object = ObjectReference {
+        api_version: Some(<Namespace as k8s_openapi::Resource>::API_VERSION.to_string()),
+        kind: Some(<Namespace as k8s_openapi::Resource>::KIND.to_string()),
+        name: Some(namespace.to_string()),
+        ..Default::default()
+    };
+
+    if let Err(e) = create_event(
+        client,
+        namespace,
+        involved_object,
+        event_type,
+        reason,
+        message,
+    )
+    .await
+    {
+        warn!(
+            "error recording event {} for namespace {}: {}",
+            reason, namespace, e
+        );
+    }
+}
+
+// like `kubectl` does, a repeated event (same object, type, reason and message) is not recorded
+// again -- the count and the last timestamp of the recorded event are updated instead
+async fn create_event(
+    client: &kube::Client,
+    namespace: &str,
+    involved_object: ObjectReference,
+    event_type: &str,
+    reason: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    let api = kube::Api::<Event>::namespaced(client.clone(), namespace);
+    let now = Time(Utc::now());
+
+    let lp = ListParams::default().fields(&format!(
+        "involvedObject.name={},reason={}",
+        involved_object.name.clone().unwrap_or_default(),
+        reason
+    ));
+    let recorded = api.list(&lp).await?.items.into_iter().find(|event| {
+        event.involved_object.kind == involved_object.kind
+            && event.involved_object.uid == involved_object.uid
+            && event.type_.as_deref() == Some(event_type)
+            && event.message.as_deref() == Some(message)
+    });
+
+    if let Some(recorded) = recorded {
+        api.patch(
+            &recorded.metadata.name.unwrap_or_default(),
+            &PatchParams::default(),
+            &Patch::Merge(serde_json::json!({
+                "count": recorded.count.unwrap_or(1) + 1,
+                "lastTimestamp": now,
+            })),
+        )
+        .await?;
+
+        return Ok(());
+    }
+
+    let event = Event {
+        metadata: ObjectMeta {
+            generate_name: Some(format!(
+                "{}.",
+                involved_object.name.clone().unwrap_or_default()
+            )),
+            namespace: Some(namespace.to_string()),
+            ..Default::default()
+        },
+        involved_object,
+        type_: Some(event_type.to_string()),
+        reason: Some(reason.to_string()),
+        message: Some(message.to_string()),
+        count: Some(1),
+        first_timestamp: Some(now.clone()),
+        last_timestamp: Some(now),
+        source: Some(EventSource {
+            component: Some(EVENT_SOURCE_COMPONENT.to_string()),
+            host: None,
+        }),
+        ..Default::default()
+    };
+
+    api.create(&PostParams::default(), &event).await?;
+
+    Ok(())
+}
@@ -0,0 +1,129 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use anyhow::bail;
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+
+use crate::project::Project;
+
+impl Project {
+    /// returns the point in time at which this project expires -- either `spec.expiresAt` or
+    /// `spec.ttl` counted from the creation of the project (or from now, if it was not created
+    /// yet)
+    pub fn expiry(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        match (&self.spec.expires_at, &self.spec.ttl) {
+            (Some(_), Some(_)) => {
+                bail!("Invalid project spec: only one of 'expiresAt' and 'ttl' can be set")
+            }
+            (Some(expires_at), None) => {
+                let expires_at = DateTime::parse_from_rfc3339(expires_at).context(format!(
+                    "Invalid project spec: expiresAt '{}' is not a RFC 3339 timestamp (e.g. '2021-12-24T18:00:00Z')",
+                    expires_at
+                ))?;
+
+                Ok(Some(expires_at.with_timezone(&Utc)))
+            }
+            (None, Some(ttl)) => {
+                let ttl = parse_duration(ttl).context("Invalid project spec: invalid ttl")?;
+                let created_at = self
+                    .metadata
+                    .creation_timestamp
+                    .as_ref()
+                    .map(|time| time.0)
+                    .unwrap_or_else(Utc::now);
+
+                let expires_at = created_at.checked_add_signed(ttl).context(format!(
+                    "Invalid project spec: ttl '{}' is too long",
+                    self.spec.ttl.as_deref().unwrap_or_default()
+                ))?;
+
+                Ok(Some(expires_at))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// expiry of this project as RFC 3339 timestamp, as it is shown in the project status
+    pub fn expiry_timestamp(&self) -> Option<String> {
+        self.expiry()
+            .ok()
+            .flatten()
+            .map(|expiry| expiry.to_rfc3339())
+    }
+}
+
+/// parses durations like `90s`, `30m`, `12h`, `7d` or `2w`
+pub fn parse_duration(s: &str) -> anyhow::Result<Duration> {
+    let s = s.trim();
+    let unit_pos = s
+        .find(|c: char| !c.is_ascii_digit())
+        .context(format!("duration '{}' has no unit (s, m, h, d or w)", s))?;
+    let (amount, unit) = s.split_at(unit_pos);
+    let amount: i64 = amount
+        .parse()
+        .context(format!("duration '{}' does not start with a number", s))?;
+
+    let unit_seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => bail!(
+            "duration '{}' has an unknown unit '{}' (expected s, m, h, d or w)",
+            s,
+            unit
+        ),
+    };
+
+    // `Duration` panics if it gets too long
+    match amount.checked_mul(unit_seconds) {
+        Some(seconds) if seconds <= Duration::max_value().num_seconds() => {
+            Ok(Duration::seconds(seconds))
+        }
+        _ => bail!("duration '{}' is too long", s),
+    }
+}
+
+/// formats the time left until expiry in a compact, human readable form, e.g. `2d 4h` or `35m`
+pub fn format_countdown(left: Duration) -> String {
+    let minutes = left.num_minutes().max(0);
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h", days, hours),
+    }
+}
+
+/// the time left until expiry as shown in the status -- `2d 4h`, `5h` or `35m` -- and how long
+/// it stays the same, so the status only needs to be updated when the countdown changes
+pub fn status_countdown(left: Duration) -> (String, Duration) {
+    let left = left.max(Duration::zero());
+    let (countdown, unit) = if left >= Duration::days(1) {
+        (format_countdown(left), Duration::hours(1))
+    } else if left >= Duration::hours(1) {
+        (format!("{}h", left.num_hours()), Duration::hours(1))
+    } else {
+        (format_countdown(left), Duration::minutes(1))
+    };
+
+    let into_unit = Duration::milliseconds(left.num_milliseconds() % unit.num_milliseconds());
+    // the countdown changes right after the time left drops below the next full unit
+    (countdown, into_unit + Duration::seconds(1))
+}
@@ -0,0 +1,136 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context;
+
+#[derive(Debug, PartialEq)]
+enum Requirement {
+    Exists(String),
+    DoesNotExist(String),
+    Equals(String, String),
+    NotEquals(String, String),
+    In(String, Vec<String>),
+    NotIn(String, Vec<String>),
+}
+
+impl Requirement {
+    fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        match self {
+            Requirement::Exists(key) => labels.contains_key(key),
+            Requirement::DoesNotExist(key) => !labels.contains_key(key),
+            Requirement::Equals(key, value) => labels.get(key) == Some(value),
+            Requirement::NotEquals(key, value) => labels.get(key) != Some(value),
+            Requirement::In(key, values) => labels.get(key).is_some_and(|v| values.contains(v)),
+            Requirement::NotIn(key, values) => !labels.get(key).is_some_and(|v| values.contains(v)),
+        }
+    }
+}
+
+/// checks whether `labels` match a label selector in the syntax of `kubectl --selector`, e.g.
+/// `tier=gold,env in (dev, staging),!deprecated` -- an empty selector matches everything
+pub fn matches(selector: &str, labels: &BTreeMap<String, String>) -> anyhow::Result<bool> {
+    Ok(parse(selector)
+        .context(format!("invalid label selector '{}'", selector))?
+        .iter()
+        .all(|requirement| requirement.matches(labels)))
+}
+
+fn parse(selector: &str) -> anyhow::Result<Vec<Requirement>> {
+    split_requirements(selector)
+        .into_iter()
+        .map(|requirement| requirement.trim())
+        .filter(|requirement| !requirement.is_empty())
+        .map(parse_requirement)
+        .collect()
+}
+
+// splits at commas that are not part of a set of values, e.g. `env in (dev, prod)`
+fn split_requirements(selector: &str) -> Vec<&str> {
+    let mut requirements = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in selector.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                requirements.push(&selector[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    requirements.push(&selector[start..]);
+
+    requirements
+}
+
+fn parse_requirement(requirement: &str) -> anyhow::Result<Requirement> {
+    if let Some((head, values)) = requirement.split_once('(') {
+        let values = values
+            .strip_suffix(')')
+            .context(format!("'{}' is missing a closing ')'", requirement))?;
+        let values = values
+            .split(',')
+            .map(|value| value.trim().to_string())
+            .collect();
+
+        let head = head.split_whitespace().collect::<Vec<_>>();
+        return match head.as_slice() {
+            [key, "in"] => Ok(Requirement::In(key_of(key)?, values)),
+            [key, "notin"] => Ok(Requirement::NotIn(key_of(key)?, values)),
+            _ => bail!(
+                "'{}' must have the form '<key> in (<values>)' or '<key> notin (<values>)'",
+                requirement
+            ),
+        };
+    }
+
+    if let Some((key, value)) = requirement.split_once("!=") {
+        return Ok(Requirement::NotEquals(
+            key_of(key)?,
+            value.trim().to_string(),
+        ));
+    }
+
+    if let Some((key, value)) = requirement
+        .split_once("==")
+        .or_else(|| requirement.split_once('='))
+    {
+        return Ok(Requirement::Equals(key_of(key)?, value.trim().to_string()));
+    }
+
+    match requirement.strip_prefix('!') {
+        Some(key) => Ok(Requirement::DoesNotExist(key_of(key)?)),
+        None => Ok(Requirement::Exists(key_of(requirement)?)),
+    }
+}
+
+fn key_of(key: &str) -> anyhow::Result<String> {
+    let key = key.trim();
+    ensure!(
+        !key.is_empty() && !key.contains(char::is_whitespace),
+        "'{}' is not a valid label key",
+        key
+    );
+
+    Ok(key.to_string())
+}
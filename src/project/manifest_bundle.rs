@@ -0,0 +1,78 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! cluster scoped manifest bundles: they describe which manifests they provide, which values
+//! these manifests need and which projects may use them -- projects reference them by name, just
+//! like secrets and config maps holding manifests
+
+use std::collections::BTreeMap;
+
+use kube::CustomResource;
+pub use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::project::project::preserve_unknown_fields;
+
+#[derive(CustomResource, Serialize, Deserialize, PartialEq, Default, Debug, Clone, JsonSchema)]
+/// a bundle of manifest templates that can be applied to the namespaces of projects
+#[serde(rename_all = "camelCase")]
+#[kube(
+    group = "selfservice.innoq.io",
+    version = "v1",
+    kind = "ManifestBundle",
+    shortname = "mb",
+    printcolumn = r#"
+     {"name":"Description", "type":"string", "description":"what this bundle provides", "jsonPath":".spec.description"},
+     {"name":"Project selector", "type":"string", "description":"projects this bundle is applied to automatically", "jsonPath":".spec.projectSelector"},
+     {"name":"Allowed projects", "type":"string", "description":"projects that may use this bundle", "jsonPath":".spec.allowedProjects"},
+     {"name":"Age", "type":"date", "description":"how old this resource is", "jsonPath":".metadata.creationTimestamp"}
+  "#
+)]
+pub struct ManifestBundleSpec {
+    /// what this bundle provides
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// manifest templates of this bundle by item name -- items with the same name as items in
+    /// `templatesFrom` win
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub manifests: BTreeMap<String, String>,
+
+    /// name of a secret or config map in the operator's namespace holding (additional) manifest
+    /// templates -- it needs the annotation `project.selfservice.innoq.io/operator-access: grant`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub templates_from: Option<String>,
+
+    /// json schema the values of projects using this bundle are validated against
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(schema_with = "preserve_unknown_fields")]
+    pub values_schema: Option<serde_json::Value>,
+
+    /// values for the templates of this bundle -- they can be overridden by the project's values
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(schema_with = "preserve_unknown_fields")]
+    pub default_values: Option<BTreeMap<String, serde_json::Value>>,
+
+    /// label selector (e.g. `tier=gold`): this bundle is applied to all projects whose labels
+    /// match it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_selector: Option<String>,
+
+    /// label selector restricting which projects may use this bundle -- all projects may use it
+    /// if it is not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_projects: Option<String>,
+}
@@ -0,0 +1,327 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::BTreeMap;
+
+use anyhow::{ensure, Context};
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use kube::api::ListParams;
+use serde_yaml::Mapping;
+
+use crate::project::label_selector;
+use crate::project::manifest_bundle::ManifestBundle;
+use crate::project::project::{
+    PROJECT_SELECTOR_ANNOTATION_KEY, SECRET_ANNOTATION_KEY, SECRET_ANNOTATION_VALUE,
+    VALUES_SCHEMA_ANNOTATION_KEY,
+};
+
+/// a manifest bundle, or a secret or a config map in the operator's namespace that holds
+/// manifests -- secrets and config maps need the annotation
+/// `project.selfservice.innoq.io/operator-access: grant` to be accessible
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ManifestSource {
+    /// `manifest bundle`, `secret` or `config map`, as used in messages
+    pub kind: &'static str,
+    pub name: String,
+    pub annotations: BTreeMap<String, String>,
+    /// data items, in the order they are stored
+    pub data: BTreeMap<String, String>,
+    /// json schema (as json or yaml) the values of projects using this source are validated against
+    pub values_schema: Option<String>,
+    /// label selector of the projects this source is applied to automatically
+    pub project_selector: Option<String>,
+    /// label selector of the projects that may use this source -- all projects if not set
+    pub allowed_projects: Option<String>,
+    /// values for the templates of this source, overridden by the project's values
+    pub default_values: Mapping,
+    /// why a manifest bundle could not be loaded (e.g. its `templatesFrom` source is missing) --
+    /// only projects using it fail
+    pub load_error: Option<String>,
+}
+
+impl From<Secret> for ManifestSource {
+    fn from(secret: Secret) -> Self {
+        let annotations = secret.metadata.annotations.unwrap_or_default();
+        ManifestSource {
+            kind: "secret",
+            name: secret.metadata.name.unwrap_or_default(),
+            values_schema: annotations.get(VALUES_SCHEMA_ANNOTATION_KEY).cloned(),
+            project_selector: annotations.get(PROJECT_SELECTOR_ANNOTATION_KEY).cloned(),
+            annotations,
+            data: secret
+                .data
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(item, manifest)| {
+                    (
+                        item,
+                        String::from_utf8(manifest.0).unwrap_or_else(|_| String::from("")),
+                    )
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<ConfigMap> for ManifestSource {
+    fn from(config_map: ConfigMap) -> Self {
+        let annotations = config_map.metadata.annotations.unwrap_or_default();
+        ManifestSource {
+            kind: "config map",
+            name: config_map.metadata.name.unwrap_or_default(),
+            values_schema: annotations.get(VALUES_SCHEMA_ANNOTATION_KEY).cloned(),
+            project_selector: annotations.get(PROJECT_SELECTOR_ANNOTATION_KEY).cloned(),
+            annotations,
+            data: config_map.data.unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+}
+
+impl ManifestSource {
+    /// turns a manifest bundle into a manifest source -- the templates it references via
+    /// `templatesFrom` are read from `namespace`
+    pub async fn from_manifest_bundle(
+        client: &kube::Client,
+        bundle: ManifestBundle,
+        namespace: &str,
+    ) -> anyhow::Result<ManifestSource> {
+        let templates = match &bundle.spec.templates_from {
+            Some(templates_from) => Some(
+                ManifestSource::get_secret_or_config_map(client, templates_from, namespace)
+                    .await?
+                    .context(format!(
+                        "manifest bundle '{}' references templates from '{}' in namespace '{}' which do not exist",
+                        bundle.metadata.name.clone().unwrap_or_default(), templates_from, namespace
+                    ))?,
+            ),
+            None => None,
+        };
+
+        ManifestSource::from_manifest_bundle_with_templates(bundle, templates.as_ref())
+    }
+
+    /// turns a manifest bundle into a manifest source with the `templates` of its `templatesFrom`
+    /// source
+    pub fn from_manifest_bundle_with_templates(
+        bundle: ManifestBundle,
+        templates: Option<&ManifestSource>,
+    ) -> anyhow::Result<ManifestSource> {
+        let name = bundle.metadata.name.unwrap_or_default();
+        let spec = bundle.spec;
+
+        let mut data = templates
+            .map(|templates| templates.data.clone())
+            .unwrap_or_default();
+        data.extend(spec.manifests);
+
+        let default_values = match spec.default_values {
+            Some(values) => serde_yaml::to_value(values)?
+                .as_mapping()
+                .cloned()
+                .unwrap_or_default(),
+            None => Mapping::new(),
+        };
+
+        Ok(ManifestSource {
+            kind: "manifest bundle",
+            name,
+            annotations: bundle.metadata.annotations.unwrap_or_default(),
+            data,
+            values_schema: spec
+                .values_schema
+                .map(|schema| serde_json::to_string(&schema))
+                .transpose()?,
+            project_selector: spec.project_selector,
+            allowed_projects: spec.allowed_projects,
+            default_values,
+            load_error: None,
+        })
+    }
+
+    /// checks whether a project with `labels` may use this source
+    pub fn allows(&self, labels: &BTreeMap<String, String>) -> anyhow::Result<bool> {
+        match &self.allowed_projects {
+            Some(selector) => label_selector::matches(selector, labels).context(format!(
+                "error checking the allowed projects of {} '{}'",
+                self.kind, self.name
+            )),
+            None => Ok(true),
+        }
+    }
+
+    /// manifest bundles take precedence over secrets, which take precedence over config maps with
+    /// the same name -- lower values win
+    pub fn precedence(&self) -> u8 {
+        precedence(self.kind)
+    }
+
+    /// replaces the source with the same name in `sources` (as returned by `list()`) with
+    /// `replacement` if it takes precedence, or adds it if there is none
+    pub fn replace(sources: &mut Vec<ManifestSource>, replacement: &ManifestSource) {
+        match sources.iter_mut().find(|s| s.name == replacement.name) {
+            Some(source) if source.precedence() >= replacement.precedence() => {
+                *source = replacement.clone()
+            }
+            Some(_) => {}
+            None => sources.push(replacement.clone()),
+        }
+    }
+
+    /// reads the manifest source `name` -- a manifest bundle with this name takes precedence over a
+    /// secret which takes precedence over a config map with the same name. Returns `None` if none of
+    /// them exists
+    pub async fn get(
+        client: &kube::Client,
+        name: &str,
+        namespace: &str,
+    ) -> anyhow::Result<Option<ManifestSource>> {
+        // a missing manifest bundle crd yields a 404 as well
+        match kube::Api::<ManifestBundle>::all(client.clone())
+            .get(name)
+            .await
+        {
+            Ok(bundle) => {
+                return Ok(Some(
+                    ManifestSource::from_manifest_bundle(client, bundle, namespace).await?,
+                ))
+            }
+            Err(kube::Error::Api(e)) if e.code == 404 => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        ManifestSource::get_secret_or_config_map(client, name, namespace).await
+    }
+
+    async fn get_secret_or_config_map(
+        client: &kube::Client,
+        name: &str,
+        namespace: &str,
+    ) -> anyhow::Result<Option<ManifestSource>> {
+        let source = match kube::Api::<Secret>::namespaced(client.clone(), namespace)
+            .get(name)
+            .await
+        {
+            Ok(secret) => ManifestSource::from(secret),
+            Err(kube::Error::Api(e)) if e.code == 404 => {
+                match kube::Api::<ConfigMap>::namespaced(client.clone(), namespace)
+                    .get(name)
+                    .await
+                {
+                    Ok(config_map) => ManifestSource::from(config_map),
+                    Err(kube::Error::Api(e)) if e.code == 404 => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        ensure!(
+            source.annotations.get(SECRET_ANNOTATION_KEY).map(String::as_str)
+                == Some(SECRET_ANNOTATION_VALUE),
+            "Error accessing {} '{}': only secrets and config maps with the annotation '{}: {}' can be accessed by the project operator",
+            source.kind,
+            name,
+            SECRET_ANNOTATION_KEY,
+            SECRET_ANNOTATION_VALUE
+        );
+
+        Ok(Some(source))
+    }
+
+    /// all manifest bundles and all manifest sources in `namespace` the operator can access --
+    /// bundles that can't be loaded have a `load_error` and no data
+    pub async fn list(
+        client: &kube::Client,
+        namespace: &str,
+    ) -> anyhow::Result<Vec<ManifestSource>> {
+        let lp = ListParams::default();
+        let bundles = match kube::Api::<ManifestBundle>::all(client.clone())
+            .list(&lp)
+            .await
+        {
+            Ok(bundles) => bundles.items,
+            // the manifest bundle crd is not installed
+            Err(kube::Error::Api(e)) if e.code == 404 => vec![],
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut sources = vec![];
+        for bundle in bundles {
+            let broken = ManifestSource {
+                kind: "manifest bundle",
+                name: bundle.metadata.name.clone().unwrap_or_default(),
+                annotations: bundle.metadata.annotations.clone().unwrap_or_default(),
+                project_selector: bundle.spec.project_selector.clone(),
+                allowed_projects: bundle.spec.allowed_projects.clone(),
+                ..Default::default()
+            };
+
+            // a broken bundle must not break the projects that don't use it
+            match ManifestSource::from_manifest_bundle(client, bundle, namespace).await {
+                Ok(source) => sources.push(source),
+                Err(e) => {
+                    warn!("skipping manifest bundle '{}': {:#}", broken.name, e);
+                    sources.push(ManifestSource {
+                        load_error: Some(format!("{:#}", e)),
+                        ..broken
+                    });
+                }
+            }
+        }
+
+        let secrets = kube::Api::<Secret>::namespaced(client.clone(), namespace)
+            .list(&lp)
+            .await?
+            .items
+            .into_iter()
+            .map(ManifestSource::from);
+
+        let config_maps = kube::Api::<ConfigMap>::namespaced(client.clone(), namespace)
+            .list(&lp)
+            .await?
+            .items
+            .into_iter()
+            .map(ManifestSource::from);
+
+        // manifest bundles take precedence over secrets, which take precedence over config maps
+        // with the same name
+        for source in secrets.chain(config_maps).filter(|source| {
+            source
+                .annotations
+                .get(SECRET_ANNOTATION_KEY)
+                .map(String::as_str)
+                == Some(SECRET_ANNOTATION_VALUE)
+        }) {
+            if !sources.iter().any(|s| s.name == source.name) {
+                sources.push(source);
+            }
+        }
+
+        Ok(sources)
+    }
+}
+
+/// the precedence of sources of a kind (`manifest bundle`, `secret` or `config map`) over sources
+/// of other kinds with the same name -- lower values win
+pub fn precedence(kind: &str) -> u8 {
+    match kind {
+        "manifest bundle" => 0,
+        "secret" => 1,
+        _ => 2,
+    }
+}
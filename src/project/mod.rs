@@ -14,13 +14,33 @@
  * limitations under the License.
  */
 
-pub use project::{Project, ProjectSpec, Sample};
-pub use project_status::ProjectStatus;
+pub use config::ProjectOperatorConfig;
+pub use environment::ProjectEnvironment;
+pub use manifest_bundle::{ManifestBundle, ManifestBundleSpec};
+pub use project::{OwnerKind, Project, ProjectBundle, ProjectOwner, ProjectSpec, Sample};
+pub use project_status::{ProjectCondition, ProjectResourceStatus, ProjectStatus};
 
+pub mod config;
+pub mod conversion;
+pub mod diff;
+pub mod drift;
+pub mod dry_run;
+pub mod environment;
+pub mod events;
+pub mod expiry;
+pub mod label_selector;
+pub mod manifest_bundle;
+pub mod manifest_source;
+pub mod one_shot;
 pub mod operator;
 pub mod project;
-mod project_status;
+pub mod project_status;
+pub mod prune;
+pub mod readiness;
+pub mod secret_admission;
+pub mod source_watch;
 pub mod states;
+pub mod v2;
 
 pub fn shorten_string(s: &str) -> String {
     let max_length = 50;
@@ -0,0 +1,172 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! one shot resources: manifests with the annotation `project.selfservice.innoq.io/apply` are not
+//! kept in sync, but applied when their mode says so. What was applied is recorded in
+//! `status.appliedOneShotResources` as `<path>#<revision>`, where the revision is a hash of the
+//! rendered manifest (or of the project's generation) -- plain paths are records of `once`
+//! resources applied by earlier versions. `#created` records that the project was reconciled
+//! completely, so `create` resources are not applied anymore
+
+use std::collections::HashSet;
+
+use anyhow::bail;
+use serde_yaml::Value;
+
+use crate::project::project::{
+    ONE_SHOT_MANIFEST_ANNOTATION_KEY, ONE_SHOT_MANIFEST_ANNOTATION_VALUE_CREATE,
+    ONE_SHOT_MANIFEST_ANNOTATION_VALUE_ONCE, ONE_SHOT_MANIFEST_ANNOTATION_VALUE_ON_CHANGE,
+    ONE_SHOT_MANIFEST_ANNOTATION_VALUE_ON_GENERATION, ONE_SHOT_MANIFEST_ANNOTATION_VALUE_RECREATE,
+};
+use crate::project::states::ProjectPhase;
+use crate::project::Project;
+
+/// when a one shot resource is applied
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OneShotMode {
+    /// the first time it is part of the project's manifests
+    Once,
+    /// only during the first reconciliation of the project
+    Create,
+    /// whenever its rendered manifest changes
+    OnChange,
+    /// whenever the project's generation (i.e. its spec) changes
+    OnGeneration,
+    /// like `OnChange`, but the resource is deleted and created again -- for immutable resources
+    /// like jobs
+    Recreate,
+}
+
+/// the one shot mode of a manifest -- `None` for manifests that are kept in sync
+pub fn one_shot_mode(yaml_manifest: &str) -> anyhow::Result<Option<OneShotMode>> {
+    let yaml: Value = serde_yaml::from_str(yaml_manifest)?;
+
+    let mode = match yaml["metadata"]["annotations"][ONE_SHOT_MANIFEST_ANNOTATION_KEY].as_str() {
+        None => return Ok(None),
+        Some(mode) => mode,
+    };
+
+    Ok(Some(match mode {
+        ONE_SHOT_MANIFEST_ANNOTATION_VALUE_ONCE => OneShotMode::Once,
+        ONE_SHOT_MANIFEST_ANNOTATION_VALUE_CREATE => OneShotMode::Create,
+        ONE_SHOT_MANIFEST_ANNOTATION_VALUE_ON_CHANGE => OneShotMode::OnChange,
+        ONE_SHOT_MANIFEST_ANNOTATION_VALUE_ON_GENERATION => OneShotMode::OnGeneration,
+        ONE_SHOT_MANIFEST_ANNOTATION_VALUE_RECREATE => OneShotMode::Recreate,
+        _ => bail!(
+            "unknown value '{}' for annotation '{}' -- allowed are '{}', '{}', '{}', '{}' and '{}'",
+            mode,
+            ONE_SHOT_MANIFEST_ANNOTATION_KEY,
+            ONE_SHOT_MANIFEST_ANNOTATION_VALUE_ONCE,
+            ONE_SHOT_MANIFEST_ANNOTATION_VALUE_CREATE,
+            ONE_SHOT_MANIFEST_ANNOTATION_VALUE_ON_CHANGE,
+            ONE_SHOT_MANIFEST_ANNOTATION_VALUE_ON_GENERATION,
+            ONE_SHOT_MANIFEST_ANNOTATION_VALUE_RECREATE
+        ),
+    }))
+}
+
+/// the record of a project that was reconciled completely
+pub const CREATED_RECORD: &str = "#created";
+
+/// a hash of some content (64 bit FNV-1a, hex encoded) -- unlike the std hasher it is stable
+/// across releases, so records in the status stay valid
+pub fn content_hash(content: &str) -> String {
+    let hash = content
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+
+    format!("{:016x}", hash)
+}
+
+/// the record of an applied one shot resource
+pub fn one_shot_record(
+    mode: OneShotMode,
+    path: &str,
+    yaml_manifest: &str,
+    project: &Project,
+) -> String {
+    match mode {
+        OneShotMode::Once | OneShotMode::Create | OneShotMode::OnChange | OneShotMode::Recreate => {
+            format!("{}#{}", path, content_hash(yaml_manifest))
+        }
+        OneShotMode::OnGeneration => format!(
+            "{}#{}",
+            path,
+            content_hash(&format!(
+                "generation:{}",
+                project.metadata.generation.unwrap_or_default()
+            ))
+        ),
+    }
+}
+
+// the path a record is about
+fn record_path(record: &str) -> &str {
+    record.split('#').next().unwrap_or_default()
+}
+
+/// whether a one shot resource with the record `record` has to be applied, given the `records`
+/// of the resources applied so far
+pub fn needs_apply(mode: OneShotMode, record: &str, records: &[String]) -> bool {
+    let path = record_path(record);
+    let applied_before = records.iter().any(|r| record_path(r) == path);
+
+    match mode {
+        // whatever the content was
+        OneShotMode::Once => !applied_before,
+        OneShotMode::Create => !applied_before && !records.iter().any(|r| r == CREATED_RECORD),
+        OneShotMode::OnChange | OneShotMode::OnGeneration | OneShotMode::Recreate => {
+            !records.iter().any(|r| r == record)
+        }
+    }
+}
+
+/// whether a project was reconciled completely before `CREATED_RECORD` was introduced -- its
+/// `create` resources were applied already
+pub fn was_reconciled_before(project: &Project) -> bool {
+    project.status.as_ref().is_some_and(|status| {
+        status.inventory.is_some() || matches!(status.phase, Some(ProjectPhase::WaitingForChanges))
+    })
+}
+
+/// adds the record of an applied one shot resource, replacing earlier records of the resource
+pub fn insert_one_shot_record(records: &mut HashSet<String>, record: String) {
+    let path = record_path(&record).to_string();
+    records.retain(|r| record_path(r) != path);
+    records.insert(record);
+}
+
+/// the records after a reconciliation: the `applied` records replace the previous records of the
+/// same resources
+pub fn merge_one_shot_records(previous: &[String], applied: &HashSet<String>) -> Vec<String> {
+    let applied_paths = applied
+        .iter()
+        .map(|record| record_path(record))
+        .collect::<HashSet<_>>();
+
+    let mut records = previous
+        .iter()
+        .filter(|record| !applied_paths.contains(record_path(record)))
+        .chain(applied.iter())
+        .cloned()
+        .collect::<Vec<_>>();
+    records.sort();
+    records.dedup();
+
+    records
+}
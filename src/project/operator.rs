@@ -14,7 +14,7 @@
  * limitations under the License.
  */
 
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -28,6 +28,13 @@ use krator::{Manifest, Operator};
 use kube::{Api, Resource};
 use tokio::sync::RwLock;
 
+use crate::project::config::ProjectOperatorConfig;
+use crate::project::dry_run::dry_run;
+use crate::project::environment::{
+    ensure_namespaces_are_not_orphaned, ensure_namespaces_are_not_renamed, project_namespaces,
+};
+use crate::project::manifest_source::ManifestSource;
+use crate::project::one_shot::{was_reconciled_before, CREATED_RECORD};
 use crate::project::project::{
     DEFAULT_MANIFESTS_SECRET, SECRET_ANNOTATION_KEY, SECRET_ANNOTATION_VALUE,
 };
@@ -46,15 +53,24 @@ impl ProjectOperator {
         default_ns: &str,
         default_manifests_secret: &str,
         manifest_retry_delay: Duration,
+        config: ProjectOperatorConfig,
     ) -> anyhow::Result<Self> {
         let shared = Arc::new(RwLock::new(ProjectOperatorState {
             client: client.clone(),
             default_ns: default_ns.to_string(),
             default_manifests_secret: default_manifests_secret.to_string(),
             manifest_retry_delay,
+            config,
         }));
 
+        // the default manifests can be stored in a config map as well
         if let Err(e) = get_manifests_secret(&client, default_manifests_secret, default_ns).await {
+            if let Ok(Some(_)) =
+                ManifestSource::get(&client, default_manifests_secret, default_ns).await
+            {
+                return Ok(ProjectOperator { shared });
+            }
+
             bail!(
                     "no Secret with name '{}' in namespace '{}' found (this secret should hold default manifests that get applied in each new namespace): {} -- aborting",
                     default_manifests_secret, default_ns, e);
@@ -77,10 +93,29 @@ impl Operator for ProjectOperator {
         manifest: &Self::Manifest,
     ) -> anyhow::Result<Self::ObjectState> {
         let name = manifest.meta().name.clone().unwrap();
+
+        // `create` resources of projects that were reconciled by earlier versions were applied
+        let mut applied_one_shot_resources = HashSet::new();
+        if was_reconciled_before(manifest) {
+            applied_one_shot_resources.insert(CREATED_RECORD.to_string());
+        }
+
         Ok(ProjectState {
             name,
             error: "".to_string(),
-            applied_one_shot_resources: HashSet::new(),
+            applied_one_shot_resources,
+            expiry_warning_sent: false,
+            reported_error: None,
+            namespaces: vec![],
+            orphaned_namespaces: vec![],
+            resources: vec![],
+            inventory: vec![],
+            retained_resources: vec![],
+            field_conflicts: vec![],
+            applied_manifests: BTreeMap::new(),
+            synced_at: None,
+            revision: None,
+            drift_watch: None,
         })
     }
 
@@ -118,40 +153,115 @@ impl Operator for ProjectOperator {
             })
         };
 
-        if let Ok(project_namespace) = Api::<Namespace>::all(client.clone())
-            .get(&project_name)
-            .await
+        for owner in project.spec.owners.iter() {
+            if let Err(e) = owner.validate() {
+                return deny(format!("Invalid project spec: {}", e));
+            }
+        }
+
+        if let Err(e) = shared.config.namespace_metadata(&project) {
+            return deny(e.to_string());
+        }
+
+        if let Err(e) = shared.config.validate_expiry(&project) {
+            return deny(format!("{:#}", e));
+        }
+
+        if let Err(e) = project.validate_environments(&shared.config) {
+            return deny(format!("{:#}", e));
+        }
+
+        let environments = match project.environments(&shared.config) {
+            Ok(environments) => environments,
+            Err(e) => return deny(format!("{:#}", e)),
+        };
+
+        let existing_namespaces = match project_namespaces(&client, &project).await {
+            Ok(namespaces) => namespaces,
+            Err(e) => {
+                return deny(format!(
+                    "error reading the namespaces of the project: {}",
+                    e
+                ))
+            }
+        };
+
+        if let Err(e) = ensure_namespaces_are_not_renamed(&existing_namespaces, &environments)
+            .and_then(|_| ensure_namespaces_are_not_orphaned(&existing_namespaces, &environments))
         {
-            if let Some(owner_references) = project_namespace.metadata.owner_references {
-                let ns_owned_by_this_project =
-                    owner_references.into_iter().any(|owner_reference| {
-                        owner_reference.kind == Project::kind(&())
-                            && owner_reference.name == *project_name
-                    });
-
-                if !ns_owned_by_this_project {
+            return deny(format!("{:#}", e));
+        }
+
+        let namespaces = environments
+            .into_iter()
+            .map(|environment| environment.namespace)
+            .collect::<Vec<_>>();
+
+        for namespace in namespaces.iter() {
+            if let Ok(project_namespace) =
+                Api::<Namespace>::all(client.clone()).get(namespace).await
+            {
+                if let Some(owner_references) = project_namespace.metadata.owner_references {
+                    let ns_owned_by_this_project =
+                        owner_references.into_iter().any(|owner_reference| {
+                            owner_reference.kind == Project::kind(&())
+                                && owner_reference.name == *project_name
+                        });
+
+                    if !ns_owned_by_this_project {
+                        return deny(format!(
+                            "can't create/update project: a namespace with name '{}' already exists but is not owned by this project",
+                            namespace
+                        ));
+                    }
+                } else {
                     return deny(format!(
-                        "can't create/update project: a namespace with name '{}' already exists but is not owned by this project",
-                        project_name
+                        "can't create project: a namespace with name '{}' already exists",
+                        namespace
                     ));
                 }
-            } else {
-                return deny(format!(
-                    "can't create project: a namespace with name '{}' already exists",
-                    project_name
-                ));
             }
         }
 
-        if let Err(e) = project
+        let manifests = match project
             .associated_manifests(
                 &client,
                 &shared.default_manifests_secret,
                 &default_namespace,
+                &shared.config,
             )
             .await
         {
-            return deny(e.to_string());
+            Ok(manifests) => manifests,
+            Err(e) => return deny(e.to_string()),
+        };
+
+        // the api server checks the rendered manifests, so invalid projects are rejected right
+        // away instead of failing when they get reconciled
+        let result = dry_run(&client, &project, &manifests, &namespaces, &shared.config).await;
+        let unchecked = if result.unchecked.is_empty() {
+            String::new()
+        } else {
+            format!(
+                "\nnot checked, as their namespace doesn't exist yet:\n{}",
+                bullet_points(&result.unchecked)
+            )
+        };
+
+        if !result.failures.is_empty() {
+            return deny(format!(
+                "Invalid project: the api server would reject {} of its manifests:\n{}{}",
+                result.failures.len(),
+                bullet_points(&result.failures),
+                unchecked
+            ));
+        }
+
+        if !result.unchecked.is_empty() {
+            info!(
+                "accepting project {} without checking all of its manifests:{}",
+                project_name, unchecked
+            );
         }
 
         AdmissionResult::Allow(project)
@@ -216,6 +326,7 @@ pub struct ProjectOperatorState {
     pub(crate) default_manifests_secret: String,
     pub(crate) default_ns: String,
     pub(crate) manifest_retry_delay: Duration,
+    pub(crate) config: ProjectOperatorConfig,
 }
 
 impl Default for ProjectOperatorState {
@@ -233,3 +344,12 @@ impl ProjectOperatorState {
         self.client.clone()
     }
 }
+
+// one line per entry, as listed in admission denials
+fn bullet_points(failures: &[String]) -> String {
+    failures
+        .iter()
+        .map(|failure| format!("  - {}", failure))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -18,18 +18,22 @@ use std::cmp::PartialEq;
 use std::collections::BTreeMap;
 
 use anyhow::bail;
+use anyhow::ensure;
 use anyhow::Context;
 use handlebars::Handlebars;
-use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::api::rbac::v1::Subject;
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
 use krator_derive::AdmissionWebhook;
 use kube::Client;
 use kube::CustomResource;
 pub use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_yaml::Mapping;
 
-use crate::project::ProjectStatus;
+use crate::project::label_selector;
+use crate::project::manifest_source::ManifestSource;
+use crate::project::states::apply_manifests::split_yaml_manifest;
+use crate::project::{ProjectEnvironment, ProjectOperatorConfig, ProjectStatus};
 
 pub const SECRET_ANNOTATION_KEY: &str = "project.selfservice.innoq.io/operator-access";
 pub const SECRET_ANNOTATION_VALUE: &str = "grant";
@@ -41,6 +45,50 @@ pub const COPY_ANNOTATION_SKIP_VALUE: &str = "skip";
 
 pub const ONE_SHOT_MANIFEST_ANNOTATION_KEY: &str = "project.selfservice.innoq.io/apply";
 pub const ONE_SHOT_MANIFEST_ANNOTATION_VALUE_ONCE: &str = "once";
+pub const ONE_SHOT_MANIFEST_ANNOTATION_VALUE_CREATE: &str = "create";
+pub const ONE_SHOT_MANIFEST_ANNOTATION_VALUE_ON_CHANGE: &str = "on-change";
+pub const ONE_SHOT_MANIFEST_ANNOTATION_VALUE_ON_GENERATION: &str = "on-generation";
+pub const ONE_SHOT_MANIFEST_ANNOTATION_VALUE_RECREATE: &str = "recreate";
+
+/// manifests are applied in waves, in ascending order of this annotation's (integer) value -- each
+/// wave is applied completely before the next one starts
+pub const SYNC_WAVE_ANNOTATION_KEY: &str = "project.selfservice.innoq.io/sync-wave";
+
+/// resources with this annotation are not deleted when they are no longer part of a project's
+/// manifests
+pub const PRUNE_ANNOTATION_KEY: &str = "project.selfservice.innoq.io/prune";
+pub const PRUNE_ANNOTATION_VALUE_DISABLED: &str = "disabled";
+
+/// resources with the deletion policy `retain` don't get the project as owner, so they survive the
+/// deletion of the project (and are never pruned)
+pub const DELETION_POLICY_ANNOTATION_KEY: &str = "project.selfservice.innoq.io/deletion-policy";
+pub const DELETION_POLICY_ANNOTATION_VALUE_RETAIN: &str = "retain";
+pub const DELETION_POLICY_ANNOTATION_VALUE_DELETE: &str = "delete";
+
+/// the fields of `authoritative` resources are always owned by the operator, even if other field
+/// managers changed them; `initial` resources are only created and left alone afterwards
+pub const FIELD_OWNERSHIP_ANNOTATION_KEY: &str = "project.selfservice.innoq.io/field-ownership";
+pub const FIELD_OWNERSHIP_ANNOTATION_VALUE_AUTHORITATIVE: &str = "authoritative";
+pub const FIELD_OWNERSHIP_ANNOTATION_VALUE_INITIAL: &str = "initial";
+
+/// how long an applied resource can take to become ready, e.g. `10m` -- overrides the operator's
+/// `--readiness-timeout`
+pub const READINESS_TIMEOUT_ANNOTATION_KEY: &str = "project.selfservice.innoq.io/readiness-timeout";
+
+/// the operator sets this annotation to the current time to reconcile a project again, e.g. when
+/// the manifest sources it uses changed
+pub const MANIFESTS_CHANGED_AT_ANNOTATION_KEY: &str =
+    "project.selfservice.innoq.io/manifests-changed-at";
+
+/// all resources that are applied for a project carry its name in this label, so they can be
+/// watched for drift
+pub const APPLIED_FOR_LABEL_KEY: &str = "project.selfservice.innoq.io/applied-for";
+
+pub const VALUES_SCHEMA_ANNOTATION_KEY: &str = "project.selfservice.innoq.io/values-schema";
+
+/// bundles with this annotation are applied to all projects whose labels match its value, a label
+/// selector like `tier=gold`
+pub const PROJECT_SELECTOR_ANNOTATION_KEY: &str = "project.selfservice.innoq.io/project-selector";
 
 pub trait Sample {
     fn sample() -> Self;
@@ -69,39 +117,254 @@ pub trait Sample {
     status = "ProjectStatus",
     shortname = "ssp",
     printcolumn = r#"
-     {"name":"Owner", "type":"string", "description":"owner of this project", "jsonPath":".spec.owner"},
-     {"name":"Private", "type":"string", "description":"whether the project's namespace is private", "jsonPath":".spec.private"},
+     {"name":"Owners", "type":"string", "description":"owners of this project", "jsonPath":".spec.owners"},
+     {"name":"Private", "type":"boolean", "description":"whether the project's namespace is private", "jsonPath":".spec.private"},
      {"name":"Age", "type":"date", "description":"how old this resource is", "jsonPath":".metadata.creationTimestamp"},
+     {"name":"Expires", "type":"string", "description":"when this project expires", "jsonPath":".status.expiresAt"},
      {"name":"Phase", "type":"string", "description":"current phase of this resource", "jsonPath":".status.phase"}, {"name":"Status summary", "type":"string", "description":"current status", "jsonPath":".status.summary"}
   "#
 )]
 pub struct ProjectSpec {
-    /// Owner of this project -- this user will have cluster-admin rights within the created namespace
-    /// it must be the user name of this user
-    pub owners: Vec<String>,
+    /// Owners of this project -- they will have cluster-admin rights within the created namespace.
+    /// Each entry has a `kind` (`User`, `Group` or `ServiceAccount`), a `name` and -- for service
+    /// accounts -- a `namespace`. A plain string is accepted as the name of a `User`
+    #[schemars(schema_with = "owners_schema")]
+    pub owners: Vec<ProjectOwner>,
 
     /// a map of values that should be templated into manifests that get created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(schema_with = "preserve_unknown_fields")]
+    pub values: Option<BTreeMap<String, serde_json::Value>>,
+
+    /// deprecated: a string containing a yaml mapping of values that should be templated into
+    /// manifests that get created -- use `values` instead. If both are set, entries in `values` win
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub manifest_values: Option<String>,
+
+    /// labels that should be set on the project's namespace -- only keys that are allowed by the
+    /// operator's configuration can be set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace_labels: Option<BTreeMap<String, String>>,
+
+    /// annotations that should be set on the project's namespace -- only keys that are allowed by
+    /// the operator's configuration can be set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace_annotations: Option<BTreeMap<String, String>>,
+
+    /// a private project's namespace does not accept network traffic from outside by default and
+    /// the project and its namespace are only visible to its owners
+    #[serde(default)]
+    pub private: bool,
+
+    /// point in time (RFC 3339, e.g. `2021-12-24T18:00:00Z`) at which the project -- and with it
+    /// its namespace -- gets deleted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+
+    /// time to live of the project, counted from its creation (e.g. `12h`, `7d` or `2w`) -- can't
+    /// be combined with `expiresAt`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<String>,
+
+    /// environments of this project (e.g. `dev`, `staging` and `prod`) -- a namespace
+    /// `<project>-<environment>` is created for each of them and manifests are applied once per
+    /// environment. Without environments, a single namespace `<project>` is created
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub environments: Vec<String>,
+
+    /// manifest bundles (secrets or config maps in the operator's namespace) whose manifests get
+    /// applied in addition to the default manifests -- or skipped, if `skip` is set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bundles: Vec<ProjectBundle>,
+}
+
+// kubernetes only accepts free form objects in structural schemas if they are explicitly marked
+pub(crate) fn preserve_unknown_fields(
+    _: &mut schemars::gen::SchemaGenerator,
+) -> schemars::schema::Schema {
+    let mut schema = schemars::schema::SchemaObject {
+        instance_type: Some(schemars::schema::InstanceType::Object.into()),
+        ..Default::default()
+    };
+    schema
+        .extensions
+        .insert("nullable".to_string(), serde_json::Value::Bool(true));
+    schema.extensions.insert(
+        "x-kubernetes-preserve-unknown-fields".to_string(),
+        serde_json::Value::Bool(true),
+    );
+
+    schemars::schema::Schema::Object(schema)
+}
+
+// owners can be given as plain strings (users) or as typed entries, therefore the items can't
+// have a structural type
+fn owners_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+    let mut items = schemars::schema::SchemaObject::default();
+    items.extensions.insert(
+        "x-kubernetes-preserve-unknown-fields".to_string(),
+        serde_json::Value::Bool(true),
+    );
+
+    schemars::schema::Schema::Object(schemars::schema::SchemaObject {
+        instance_type: Some(schemars::schema::InstanceType::Array.into()),
+        array: Some(Box::new(schemars::schema::ArrayValidation {
+            items: Some(schemars::schema::Schema::Object(items).into()),
+            ..Default::default()
+        })),
+        ..Default::default()
+    })
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Default, Debug, Clone, JsonSchema)]
+pub enum OwnerKind {
+    #[default]
+    User,
+    Group,
+    ServiceAccount,
+}
+
+#[derive(Serialize, PartialEq, Default, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectOwner {
+    pub kind: OwnerKind,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+}
+
+/// a reference to a manifest bundle -- unlike the copy/skip annotations, bundle names can contain
+/// dots
+#[derive(Serialize, Deserialize, PartialEq, Default, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectBundle {
+    /// name of the secret or config map
+    pub name: String,
+    /// data items of the bundle that should be applied (or skipped) -- all items if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<String>,
+    /// skip these manifests instead of applying them, e.g. to skip default manifests
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skip: bool,
+}
+
+impl ProjectOwner {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            !self.name.is_empty(),
+            "owner of kind {:?} must have a name",
+            self.kind
+        );
+
+        match self.kind {
+            OwnerKind::ServiceAccount => ensure!(
+                matches!(&self.namespace, Some(namespace) if !namespace.is_empty()),
+                "owner '{}' of kind ServiceAccount must have a namespace",
+                self.name
+            ),
+            _ => ensure!(
+                self.namespace.is_none(),
+                "owner '{}' of kind {:?} must not have a namespace",
+                self.name,
+                self.kind
+            ),
+        }
+
+        Ok(())
+    }
+}
+
+impl From<&str> for ProjectOwner {
+    fn from(name: &str) -> ProjectOwner {
+        ProjectOwner {
+            kind: OwnerKind::User,
+            name: name.to_string(),
+            namespace: None,
+        }
+    }
+}
+
+// owners used to be plain user names -- keep accepting them
+impl<'de> Deserialize<'de> for ProjectOwner {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum OwnerEntry {
+            Name(String),
+            Typed {
+                #[serde(default)]
+                kind: OwnerKind,
+                name: String,
+                namespace: Option<String>,
+            },
+        }
+
+        Ok(match OwnerEntry::deserialize(deserializer)? {
+            OwnerEntry::Name(name) => ProjectOwner::from(name.as_str()),
+            OwnerEntry::Typed {
+                kind,
+                name,
+                namespace,
+            } => ProjectOwner {
+                kind,
+                name,
+                namespace,
+            },
+        })
+    }
+}
+
+impl From<&ProjectOwner> for Subject {
+    fn from(owner: &ProjectOwner) -> Subject {
+        let (kind, api_group) = match owner.kind {
+            OwnerKind::User => ("User", Some("rbac.authorization.k8s.io".to_string())),
+            OwnerKind::Group => ("Group", Some("rbac.authorization.k8s.io".to_string())),
+            OwnerKind::ServiceAccount => ("ServiceAccount", None),
+        };
+
+        Subject {
+            api_group,
+            kind: kind.to_string(),
+            name: owner.name.clone(),
+            namespace: owner.namespace.clone(),
+        }
+    }
 }
 
 impl Sample for ProjectSpec {
     fn sample() -> Self {
-        let manifest_values = r#"
-project_repo: github.com/innoq/self-service-operators
-project_name: self-service-project
-"#;
+        let mut values = BTreeMap::new();
+        values.insert(
+            "project_repo".to_string(),
+            serde_json::json!("github.com/innoq/self-service-operators"),
+        );
+        values.insert(
+            "project_name".to_string(),
+            serde_json::json!("self-service-project"),
+        );
 
         ProjectSpec {
             owners: vec![
-                "superdev@example.com".to_string(),
-                "supradev@example.com".to_string(),
+                ProjectOwner::from("superdev@example.com"),
+                ProjectOwner::from("supradev@example.com"),
             ],
-            manifest_values: Some(manifest_values.into()),
+            values: Some(values),
+            manifest_values: None,
+            namespace_labels: None,
+            namespace_annotations: None,
+            private: false,
+            expires_at: None,
+            ttl: None,
+            environments: vec![],
+            bundles: vec![],
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 struct ManifestReference {
     secret_name: String,
     data_item: Option<String>,
@@ -122,12 +385,190 @@ impl Project {
     // project.selfservice.innoq.io/default-project-manifests: copy
     //
     // for all project
+    //
+    // a secret can publish a json schema (as json or yaml) for the values it expects in the
+    // annotation
+    //
+    // project.selfservice.innoq.io/values-schema: <schema>
+    //
+    // the values of projects using this secret are validated against this schema
+    //
+    // bundles can also be referenced in `spec.bundles` (which allows dots in their names) and a
+    // bundle with the annotation
+    //
+    // project.selfservice.innoq.io/project-selector: <label selector>
+    //
+    // is copied to all projects whose labels match this selector
+    //
+    // instead of a secret or config map, a cluster scoped `ManifestBundle` with the same name can
+    // provide the manifests -- it takes precedence and can additionally set default values and
+    // restrict the projects that may use it
     pub async fn associated_manifests(
         &self,
         client: &Client,
         default_manifests_secret: &str,
         namespace: &str,
+        config: &ProjectOperatorConfig,
+    ) -> anyhow::Result<Vec<String>> {
+        let sources = ManifestSource::list(client, namespace).await?;
+        self.associated_manifests_with(default_manifests_secret, config, None, &sources)
+    }
+
+    // like `associated_manifests`, but the manifests are taken from `sources` (all manifest sources,
+    // see `ManifestSource::list()`), so they can be listed once for many projects -- and
+    // `replacement` is used instead of the stored manifest source with the same name (if it takes
+    // precedence), which allows to check changes of manifest sources before they are stored
+    pub fn associated_manifests_with(
+        &self,
+        default_manifests_secret: &str,
+        config: &ProjectOperatorConfig,
+        replacement: Option<&ManifestSource>,
+        sources: &[ManifestSource],
+    ) -> anyhow::Result<Vec<String>> {
+        let (copy_manifests_references, skip_manifests_references) =
+            self.manifest_references(default_manifests_secret, sources, replacement)?;
+        let labels = self.metadata.labels.clone().unwrap_or_default();
+        let skip = |reference: &ManifestReference| -> bool {
+            is_skipped(reference, &skip_manifests_references)
+        };
+
+        let mut sources = sources.to_vec();
+        if let Some(replacement) = replacement {
+            ManifestSource::replace(&mut sources, replacement);
+        }
+
+        let mut manifest_templates = vec![];
+        for reference in copy_manifests_references.iter() {
+            if skip(reference) {
+                continue;
+            }
+
+            let source = sources
+                .iter()
+                .find(|source| source.name == reference.secret_name)
+                .context(format!(
+                    "annotation '{}/{}.{}: copy' not possible: there is no manifest bundle, secret or config map with name '{}' (secrets and config maps need the annotation '{}: {}')",
+                    COPY_ANNOTATION_BASE,
+                    reference.secret_name,
+                    reference.data_item.as_ref().unwrap_or(&"".to_string()),
+                    reference.secret_name,
+                    SECRET_ANNOTATION_KEY,
+                    SECRET_ANNOTATION_VALUE
+                ))?;
+
+            if let Some(e) = &source.load_error {
+                bail!("error loading {} '{}': {}", source.kind, source.name, e);
+            }
+
+            ensure!(
+                source.allows(&labels)?,
+                "project '{}' is not allowed to use {} '{}' (only projects matching '{}' are)",
+                self.metadata.name.as_ref().unwrap(),
+                source.kind,
+                reference.secret_name,
+                source.allowed_projects.as_ref().unwrap()
+            );
+
+            if let Some(schema) = &source.values_schema {
+                self.validate_values_with_defaults(
+                    schema,
+                    &reference.secret_name,
+                    &source.default_values,
+                )?;
+            }
+
+            if let Some(data_item) = &reference.data_item {
+                let manifest = source.data.get(data_item).context(format!(
+                        "annotation '{}/{}.{}: copy' not possible: {} '{}' does not contain a data item named '{}'",
+                        COPY_ANNOTATION_BASE,
+                        reference.secret_name,
+                        data_item,
+                        source.kind,
+                        reference.secret_name,
+                        data_item
+                    ))?;
+
+                manifest_templates.push((
+                    data_item.to_string(),
+                    manifest.to_owned(),
+                    source.default_values.clone(),
+                    Some(format!(
+                        "error rendering '{}' from {} '{}':",
+                        data_item, source.kind, reference.secret_name
+                    )),
+                ));
+            } else {
+                // copy all data items (if any) of this secret or config map
+                for (data_item, manifest) in source.data.iter() {
+                    if skip(&ManifestReference {
+                        secret_name: reference.secret_name.clone(),
+                        data_item: Some(data_item.to_owned()),
+                    }) {
+                        continue;
+                    }
+
+                    manifest_templates.push((
+                        format!("{}/{}", reference.secret_name, data_item),
+                        manifest.to_owned(),
+                        source.default_values.clone(),
+                        None,
+                    ));
+                }
+            }
+        }
+
+        // manifests are rendered -- and later applied -- once per environment
+        let mut manifest_yaml_sources = vec![];
+        for environment in self.environments(config)? {
+            for (name, manifest, default_values, error_context) in manifest_templates.iter() {
+                let rendered_manifest =
+                    self.render_with_defaults(manifest, name, &environment, default_values);
+                let rendered_manifest = match error_context {
+                    Some(error_context) => rendered_manifest.context(error_context.clone())?,
+                    None => rendered_manifest?,
+                };
+                manifest_yaml_sources.append(
+                    &mut split_yaml_manifest(&rendered_manifest)
+                        .context(format!("error reading manifest '{}'", name))?,
+                );
+            }
+        }
+
+        Ok(manifest_yaml_sources)
+    }
+
+    /// names of the manifest sources (bundles, secrets or config maps) this project uses, given all
+    /// manifest `sources` -- with `replacement` instead of the stored manifest source with the same
+    /// name
+    pub fn associated_sources(
+        &self,
+        default_manifests_secret: &str,
+        sources: &[ManifestSource],
+        replacement: Option<&ManifestSource>,
     ) -> anyhow::Result<Vec<String>> {
+        let (copy_manifests_references, skip_manifests_references) =
+            self.manifest_references(default_manifests_secret, sources, replacement)?;
+
+        let mut sources: Vec<String> = vec![];
+        for reference in copy_manifests_references {
+            if !is_skipped(&reference, &skip_manifests_references)
+                && !sources.contains(&reference.secret_name)
+            {
+                sources.push(reference.secret_name);
+            }
+        }
+
+        Ok(sources)
+    }
+
+    // returns the (unique) references to manifests that should be copied and the ones that should
+    // be skipped
+    fn manifest_references(
+        &self,
+        default_manifests_secret: &str,
+        sources: &[ManifestSource],
+        replacement: Option<&ManifestSource>,
+    ) -> anyhow::Result<(Vec<ManifestReference>, Vec<ManifestReference>)> {
         // always copy the default manifests
         let mut copy_manifests_references = vec![ManifestReference {
             secret_name: default_manifests_secret.to_string(),
@@ -146,83 +587,75 @@ impl Project {
                 get_annotated_manifests(annotations, COPY_ANNOTATION_SKIP_VALUE);
         }
 
-        let api: kube::Api<Secret> = kube::Api::namespaced(client.to_owned(), namespace);
-
-        let skip = |reference: &ManifestReference| -> bool {
-            skip_manifests_references
-                .iter()
-                .any(|skip_manifest_reference| {
-                    reference.secret_name == skip_manifest_reference.secret_name
-                        && (reference.data_item == skip_manifest_reference.data_item
-                            || skip_manifest_reference.data_item == None) // no data item == skip all data items of this secret
-                })
-        };
-
-        let mut manifest_yaml_sources = vec![];
-        for reference in copy_manifests_references.iter() {
-            if skip(reference) {
-                continue;
+        for bundle in self.spec.bundles.iter() {
+            let references = if bundle.items.is_empty() {
+                vec![ManifestReference {
+                    secret_name: bundle.name.clone(),
+                    data_item: None,
+                }]
+            } else {
+                bundle
+                    .items
+                    .iter()
+                    .map(|item| ManifestReference {
+                        secret_name: bundle.name.clone(),
+                        data_item: Some(item.clone()),
+                    })
+                    .collect()
+            };
+
+            if bundle.skip {
+                skip_manifests_references.extend(references);
+            } else {
+                copy_manifests_references.extend(references);
             }
+        }
 
-            let secret = api.get(&reference.secret_name).await.context(format!(
-                "annotation '{}/{}.{}: copy' not possible: secret with name '{}' does not exist",
-                COPY_ANNOTATION_BASE,
-                reference.secret_name,
-                reference.data_item.as_ref().unwrap_or(&"".to_string()),
-                reference.secret_name
-            ))?;
+        let labels = self.metadata.labels.clone().unwrap_or_default();
+        let mut sources = sources.to_vec();
+        if let Some(replacement) = replacement {
+            ManifestSource::replace(&mut sources, replacement);
+        }
 
-            if let Some(data_item) = &reference.data_item {
-                let missing_item_message = format!(
-                        "annotation '{}/{}.{}: copy' not possible: secret '{}' does not contain a data item named '{}'",
-                        COPY_ANNOTATION_BASE,
-                        reference.secret_name,
-                        data_item,
-                        reference.secret_name,
-                        data_item
-                    );
-
-                let manifest = secret.data.context(missing_item_message.clone())?;
-                let manifest = manifest
-                    .get(data_item)
-                    .context(missing_item_message.clone())?
-                    .to_owned();
-
-                let manifest =
-                    String::from_utf8(manifest.to_owned().0).unwrap_or_else(|_| String::from(""));
-                let rendered_manifest = self.render(&manifest, data_item).context(format!(
-                    "error rendering '{}' from secret '{}':",
-                    data_item, reference.secret_name
+        for source in sources {
+            if let Some(selector) = &source.project_selector {
+                let selected = label_selector::matches(selector, &labels).context(format!(
+                    "error selecting projects for {} '{}'",
+                    source.kind, source.name
                 ))?;
-                manifest_yaml_sources.push(rendered_manifest);
-            } else {
-                // copy all data items (if any) of this secret
-                if let Some(manifests) = secret.data {
-                    for (data_item, manifest) in manifests.iter() {
-                        if skip(&ManifestReference {
-                            secret_name: reference.secret_name.clone(),
-                            data_item: Some(data_item.to_owned()),
-                        }) {
-                            continue;
-                        }
 
-                        let manifest = String::from_utf8(manifest.to_owned().0)
-                            .unwrap_or_else(|_| String::from(""));
-
-                        let rendered_manifest = self.render(
-                            &manifest,
-                            &format!("{}/{}", reference.secret_name, data_item),
-                        )?;
-                        manifest_yaml_sources.push(rendered_manifest);
+                if selected && source.allows(&labels)? {
+                    if let Some(e) = &source.load_error {
+                        bail!(
+                            "error loading {} '{}' which selects this project: {}",
+                            source.kind,
+                            source.name,
+                            e
+                        );
                     }
+                    copy_manifests_references.push(ManifestReference {
+                        secret_name: source.name,
+                        data_item: None,
+                    });
                 }
             }
         }
-        Ok(manifest_yaml_sources)
+
+        // a bundle that is referenced more than once is only applied once
+        let mut unique_references: Vec<ManifestReference> = vec![];
+        for reference in copy_manifests_references {
+            if !unique_references.contains(&reference) {
+                unique_references.push(reference);
+            }
+        }
+
+        Ok((unique_references, skip_manifests_references))
     }
 
-    pub fn render(&self, template: &str, name: &str) -> anyhow::Result<String> {
-        let mut template_data = match &self.spec.manifest_values {
+    // returns the values of this project: the (deprecated) manifestValues string merged with the
+    // structured values
+    pub fn values(&self) -> anyhow::Result<Mapping> {
+        let mut values = match &self.spec.manifest_values {
             Some(values) => {
                 match serde_yaml::from_str(values) {
                     Ok(yaml) => {
@@ -248,13 +681,147 @@ impl Project {
             _ => Mapping::new(),
         };
 
+        if let Some(structured_values) = &self.spec.values {
+            for (key, value) in structured_values.iter() {
+                values.insert(serde_yaml::to_value(key)?, serde_yaml::to_value(value)?);
+            }
+        }
+
+        Ok(values)
+    }
+
+    // returns the values of this project merged over the default values of a manifest source
+    fn values_with_defaults(&self, default_values: &Mapping) -> anyhow::Result<Mapping> {
+        let mut values = default_values.clone();
+        for (key, value) in self.values()? {
+            values.insert(key, value);
+        }
+
+        Ok(values)
+    }
+
+    // validates the values of this project against a json schema (given as json or yaml) that
+    // was published by the manifest secret `source`
+    pub fn validate_values(&self, schema: &str, source: &str) -> anyhow::Result<()> {
+        self.validate_values_with_defaults(schema, source, &Mapping::new())
+    }
+
+    // like `validate_values`, but the values of this project are merged over the default values
+    // of the manifest source first
+    fn validate_values_with_defaults(
+        &self,
+        schema: &str,
+        source: &str,
+        default_values: &Mapping,
+    ) -> anyhow::Result<()> {
+        let schema: serde_json::Value = serde_yaml::from_str(schema).context(format!(
+            "error parsing values schema of manifest secret '{}'",
+            source
+        ))?;
+
+        let schema = match jsonschema::JSONSchema::compile(&schema) {
+            Ok(schema) => schema,
+            Err(e) => bail!(
+                "values schema of manifest secret '{}' is not a valid json schema: {}",
+                source,
+                e
+            ),
+        };
+
+        let values = serde_json::to_value(self.values_with_defaults(default_values)?)
+            .context("error converting values to json for schema validation")?;
+
+        if let Err(errors) = schema.validate(&values) {
+            let errors = errors
+                .map(|e| {
+                    let path = e.instance_path.to_string();
+                    format!(
+                        "  - {}: {}",
+                        if path.is_empty() {
+                            "/".to_string()
+                        } else {
+                            path
+                        },
+                        e
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            bail!(
+                "Invalid project spec: values do not match the schema of manifest secret '{}':\n{}",
+                source,
+                errors.join("\n")
+            );
+        }
+
+        Ok(())
+    }
+
+    // renders a manifest template for the first environment of this project (which is the only
+    // one for projects without environments) -- its namespace is named as configured in `config`
+    pub fn render(
+        &self,
+        template: &str,
+        name: &str,
+        config: &ProjectOperatorConfig,
+    ) -> anyhow::Result<String> {
+        let environments = self.environments(config)?;
+        self.render_for_environment(template, name, &environments[0])
+    }
+
+    pub fn render_for_environment(
+        &self,
+        template: &str,
+        name: &str,
+        environment: &ProjectEnvironment,
+    ) -> anyhow::Result<String> {
+        self.render_with_defaults(template, name, environment, &Mapping::new())
+    }
+
+    fn render_with_defaults(
+        &self,
+        template: &str,
+        name: &str,
+        environment: &ProjectEnvironment,
+        default_values: &Mapping,
+    ) -> anyhow::Result<String> {
+        let mut template_data = self.values_with_defaults(default_values)?;
+
         template_data.insert(
             serde_yaml::to_value("__PROJECT_NAME__").unwrap(),
             serde_yaml::to_value(self.metadata.name.as_ref().unwrap()).unwrap(),
         );
+        template_data.insert(
+            serde_yaml::to_value("__PROJECT_NAMESPACE__").unwrap(),
+            serde_yaml::to_value(&environment.namespace).unwrap(),
+        );
+        template_data.insert(
+            serde_yaml::to_value("__ENVIRONMENT__").unwrap(),
+            serde_yaml::to_value(environment.name.clone().unwrap_or_default()).unwrap(),
+        );
+        // owners are exposed by their names (as they always were) and as rbac subjects (kind,
+        // name, apiGroup and namespace), so they can be used directly in role bindings
         template_data.insert(
             serde_yaml::to_value("__PROJECT_OWNERS__").unwrap(),
-            serde_yaml::to_value(&self.spec.owners).unwrap(),
+            serde_yaml::to_value(
+                self.spec
+                    .owners
+                    .iter()
+                    .map(|owner| owner.name.clone())
+                    .collect::<Vec<_>>(),
+            )
+            .unwrap(),
+        );
+        template_data.insert(
+            serde_yaml::to_value("__PROJECT_OWNER_SUBJECTS__").unwrap(),
+            serde_yaml::to_value(
+                self.spec
+                    .owners
+                    .iter()
+                    .map(Subject::from)
+                    .collect::<Vec<_>>(),
+            )
+            .unwrap(),
         );
 
         let mut reg = Handlebars::new();
@@ -316,6 +883,19 @@ impl PartialEq for Project {
     }
 }
 
+fn is_skipped(
+    reference: &ManifestReference,
+    skip_manifests_references: &[ManifestReference],
+) -> bool {
+    skip_manifests_references
+        .iter()
+        .any(|skip_manifest_reference| {
+            reference.secret_name == skip_manifest_reference.secret_name
+                && (reference.data_item == skip_manifest_reference.data_item
+                    || skip_manifest_reference.data_item.is_none()) // no data item == skip all data items of this secret
+        })
+}
+
 fn get_annotated_manifests(
     annotations: &BTreeMap<String, String>,
     annotation_value: &str,
@@ -14,14 +14,29 @@
  * limitations under the License.
  */
 
+use chrono::Utc;
 use handlebars::JsonValue;
 use krator::ObjectStatus;
 
 pub use schemars::JsonSchema;
 
 use crate::project::states::ProjectPhase;
+use crate::project::Project;
 use serde::{Deserialize, Serialize};
 
+/// the project's namespaces exist
+pub const CONDITION_NAMESPACE_READY: &str = "NamespaceReady";
+/// all manifests were applied to the project's namespaces
+pub const CONDITION_MANIFESTS_APPLIED: &str = "ManifestsApplied";
+/// the project is fully reconciled
+pub const CONDITION_READY: &str = "Ready";
+/// the last reconciliation of the project failed
+pub const CONDITION_DEGRADED: &str = "Degraded";
+/// all applied resources are ready
+pub const CONDITION_RESOURCES_READY: &str = "ResourcesReady";
+/// the project is being reconciled
+pub const CONDITION_PROGRESSING: &str = "Progressing";
+
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 #[doc = "Reflects the status of the current self service project"]
@@ -29,15 +44,150 @@ pub struct ProjectStatus {
     pub phase: Option<ProjectPhase>,
     pub message: Option<String>,
     pub summary: Option<String>,
+    pub private: Option<bool>,
+    pub expires_at: Option<String>,
+    pub namespaces: Option<Vec<String>>,
+    /// namespaces of the project that no longer belong to one of its environments -- they are not
+    /// deleted automatically (but along with the project)
+    pub orphaned_namespaces: Option<Vec<String>>,
+    pub conditions: Option<Vec<ProjectCondition>>,
+    pub observed_generation: Option<i64>,
+    /// readiness of the resources that were applied during the last reconciliation
+    pub resources: Option<Vec<ProjectResourceStatus>>,
+    /// api paths of all resources that were applied for this project -- resources that are no
+    /// longer part of the project's manifests are pruned
+    pub inventory: Option<Vec<String>>,
+    /// api paths of the applied resources with the deletion policy `retain` -- they are not owned
+    /// by the project and survive its deletion
+    pub retained_resources: Option<Vec<String>>,
+    /// resources that were not applied because other field managers changed fields of them
+    pub field_conflicts: Option<Vec<ProjectFieldConflict>>,
     pub applied_one_shot_resources: Vec<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[doc = "A standard Kubernetes condition of a self service project"]
+pub struct ProjectCondition {
+    #[serde(rename = "type")]
+    pub type_: String,
+    /// one of `True`, `False` or `Unknown`
+    pub status: String,
+    /// machine readable reason for the last transition in CamelCase
+    pub reason: String,
+    pub message: String,
+    /// RFC 3339 timestamp of the last time the status of this condition changed
+    pub last_transition_time: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[doc = "The readiness of a resource that was applied for a self service project"]
+pub struct ProjectResourceStatus {
+    pub kind: String,
+    pub namespace: Option<String>,
+    pub name: String,
+    pub ready: bool,
+    /// why the resource is not ready
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[doc = "A resource of a self service project that could not be applied because of field conflicts"]
+pub struct ProjectFieldConflict {
+    pub kind: String,
+    pub namespace: Option<String>,
+    pub name: String,
+    /// the conflicting fields and their managers, as reported by the api server
+    pub message: String,
+}
+
+impl ProjectCondition {
+    pub fn new(type_: &str, status: bool, reason: &str, message: &str) -> Self {
+        ProjectCondition {
+            type_: type_.to_string(),
+            status: if status { "True" } else { "False" }.to_string(),
+            reason: reason.to_string(),
+            message: message.to_string(),
+            last_transition_time: Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+impl ProjectStatus {
+    /// returns the conditions of the project's current status updated with `updates` --
+    /// conditions that are not updated are kept and the transition time of a condition only
+    /// changes if its status changes
+    pub fn conditions(project: &Project, updates: Vec<ProjectCondition>) -> Vec<ProjectCondition> {
+        let mut conditions = project
+            .status
+            .as_ref()
+            .and_then(|status| status.conditions.clone())
+            .unwrap_or_default();
+
+        for update in updates {
+            match conditions.iter_mut().find(|c| c.type_ == update.type_) {
+                Some(condition) if condition.status == update.status => {
+                    condition.reason = update.reason;
+                    condition.message = update.message;
+                }
+                Some(condition) => *condition = update,
+                None => conditions.push(update),
+            }
+        }
+
+        conditions
+    }
+
+    /// like `conditions()`, for a project that is being reconciled (`reason` and `message` tell
+    /// what is going on): `Progressing` is set and the conditions of the types in `pending` (e.g.
+    /// `Ready`) become `False` -- but only for a new generation of the project, so re-reconciling
+    /// an unchanged project doesn't make it look unready for a moment
+    pub fn progressing_conditions(
+        project: &Project,
+        reason: &str,
+        message: &str,
+        pending: &[&str],
+        mut updates: Vec<ProjectCondition>,
+    ) -> Vec<ProjectCondition> {
+        let observed_generation = project
+            .status
+            .as_ref()
+            .and_then(|status| status.observed_generation);
+
+        if observed_generation.is_none() || observed_generation != project.metadata.generation {
+            for type_ in pending {
+                updates.push(ProjectCondition::new(type_, false, reason, message));
+            }
+        }
+        updates.push(ProjectCondition::new(
+            CONDITION_PROGRESSING,
+            true,
+            reason,
+            message,
+        ));
+
+        ProjectStatus::conditions(project, updates)
+    }
+}
+
 impl Default for ProjectStatus {
     fn default() -> Self {
         ProjectStatus {
             phase: None,
             message: None,
             summary: None,
+            private: None,
+            expires_at: None,
+            namespaces: None,
+            orphaned_namespaces: None,
+            conditions: None,
+            observed_generation: None,
+            resources: None,
+            inventory: None,
+            retained_resources: None,
+            field_conflicts: None,
             applied_one_shot_resources: vec![],
         }
     }
@@ -65,6 +215,71 @@ impl ObjectStatus for ProjectStatus {
             status.insert("summary".to_string(), serde_json::Value::String(summary));
         };
 
+        if let Some(private) = self.private {
+            debug!("private: {}", private);
+            status.insert("private".to_string(), serde_json::Value::Bool(private));
+        };
+
+        if let Some(expires_at) = self.expires_at.clone() {
+            debug!("expires_at: {}", expires_at);
+            status.insert(
+                "expiresAt".to_string(),
+                serde_json::Value::String(expires_at),
+            );
+        };
+
+        if let Some(namespaces) = self.namespaces.clone() {
+            debug!("namespaces: {:?}", namespaces);
+            status.insert("namespaces".to_string(), serde_json::json!(namespaces));
+        };
+
+        if let Some(orphaned_namespaces) = self.orphaned_namespaces.clone() {
+            debug!("orphaned_namespaces: {:?}", orphaned_namespaces);
+            status.insert(
+                "orphanedNamespaces".to_string(),
+                serde_json::json!(orphaned_namespaces),
+            );
+        };
+
+        if let Some(conditions) = self.conditions.clone() {
+            debug!("conditions: {:?}", conditions);
+            status.insert("conditions".to_string(), serde_json::json!(conditions));
+        };
+
+        if let Some(observed_generation) = self.observed_generation {
+            debug!("observed_generation: {}", observed_generation);
+            status.insert(
+                "observedGeneration".to_string(),
+                serde_json::json!(observed_generation),
+            );
+        };
+
+        if let Some(resources) = self.resources.clone() {
+            debug!("resources: {:?}", resources);
+            status.insert("resources".to_string(), serde_json::json!(resources));
+        };
+
+        if let Some(inventory) = self.inventory.clone() {
+            debug!("inventory: {:?}", inventory);
+            status.insert("inventory".to_string(), serde_json::json!(inventory));
+        };
+
+        if let Some(retained_resources) = self.retained_resources.clone() {
+            debug!("retained_resources: {:?}", retained_resources);
+            status.insert(
+                "retainedResources".to_string(),
+                serde_json::json!(retained_resources),
+            );
+        };
+
+        if let Some(field_conflicts) = self.field_conflicts.clone() {
+            debug!("field_conflicts: {:?}", field_conflicts);
+            status.insert(
+                "fieldConflicts".to_string(),
+                serde_json::json!(field_conflicts),
+            );
+        };
+
         status.insert(
             "appliedOneShotResources".to_string(),
             serde_json::Value::Array(
@@ -93,6 +308,16 @@ impl ObjectStatus for ProjectStatus {
             summary: Some(crate::project::shorten_string(&message)),
             message: Some(message),
             phase: None,
+            private: None,
+            expires_at: None,
+            namespaces: None,
+            orphaned_namespaces: None,
+            conditions: None,
+            observed_generation: None,
+            resources: None,
+            inventory: None,
+            retained_resources: None,
+            field_conflicts: None,
             applied_one_shot_resources: vec![],
         }
     }
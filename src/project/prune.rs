@@ -0,0 +1,144 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! pruning of resources that were applied for a project but are no longer part of its manifests,
+//! e.g. because a data item is skipped or a bundle was removed
+
+use http::Request;
+use kube::Resource;
+use serde_json::Value;
+
+use crate::project::events::{
+    record_event, EVENT_TYPE_NORMAL, EVENT_TYPE_WARNING, REASON_RESOURCE_PRUNED,
+};
+use crate::project::project::{PRUNE_ANNOTATION_KEY, PRUNE_ANNOTATION_VALUE_DISABLED};
+use crate::project::Project;
+
+/// the inventory entries of `previous` that are not part of `current` (in the order of `previous`)
+pub fn stale_resources(previous: &[String], current: &[String]) -> Vec<String> {
+    previous
+        .iter()
+        .filter(|path| !current.contains(path))
+        .cloned()
+        .collect()
+}
+
+/// whether a resource (as returned by the api server) may be pruned: it has to be owned by the
+/// project and must not have the annotation `project.selfservice.innoq.io/prune: disabled`
+pub fn is_prunable(resource: &Value, project: &Project) -> bool {
+    let owned_by_project = resource["metadata"]["ownerReferences"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .any(|owner| {
+            owner["kind"] == Project::kind(&()).as_ref()
+                && owner["name"].as_str() == project.metadata.name.as_deref()
+        });
+
+    let pruning_disabled = resource["metadata"]["annotations"][PRUNE_ANNOTATION_KEY]
+        == PRUNE_ANNOTATION_VALUE_DISABLED;
+
+    owned_by_project && !pruning_disabled
+}
+
+/// deletes the stale resources of a project (only records events in dry run mode) -- returns the
+/// resources that have to stay in the inventory: the ones that were not deleted because they are
+/// not prunable (e.g. retained), because of the dry run or because of an error
+pub async fn prune(
+    client: &kube::Client,
+    project: &Project,
+    namespaces: &[String],
+    stale_resources: Vec<String>,
+    dry_run: bool,
+) -> Vec<String> {
+    let mut kept = vec![];
+
+    for path in stale_resources {
+        let get_request = Request::builder()
+            .uri(&path)
+            .method("GET")
+            .body("".into())
+            .unwrap();
+
+        let resource = match client.request::<Value>(get_request).await {
+            Ok(resource) => resource,
+            // already gone, e.g. because its namespace was deleted
+            Err(kube::Error::Api(e)) if e.code == 404 => continue,
+            Err(e) => {
+                warn!("error reading stale resource {}: {}", path, e);
+                kept.push(path);
+                continue;
+            }
+        };
+
+        if !is_prunable(&resource, project) {
+            info!(
+                "not pruning {} as it is not owned by project or has pruning disabled",
+                path
+            );
+            kept.push(path);
+            continue;
+        }
+
+        if dry_run {
+            record_event(
+                client,
+                project,
+                namespaces,
+                EVENT_TYPE_NORMAL,
+                REASON_RESOURCE_PRUNED,
+                &format!("would prune {} (dry run)", path),
+            )
+            .await;
+            kept.push(path);
+            continue;
+        }
+
+        let delete_request = Request::builder()
+            .uri(format!("{}?propagationPolicy=Background", &path))
+            .method("DELETE")
+            .body("".into())
+            .unwrap();
+
+        match client.request_text(delete_request).await {
+            Ok(_) | Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {
+                record_event(
+                    client,
+                    project,
+                    namespaces,
+                    EVENT_TYPE_NORMAL,
+                    REASON_RESOURCE_PRUNED,
+                    &format!("pruned {}", path),
+                )
+                .await;
+            }
+            Err(e) => {
+                record_event(
+                    client,
+                    project,
+                    namespaces,
+                    EVENT_TYPE_WARNING,
+                    REASON_RESOURCE_PRUNED,
+                    &format!("error pruning {}: {}", path, e),
+                )
+                .await;
+                kept.push(path);
+            }
+        }
+    }
+
+    kept
+}
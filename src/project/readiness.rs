@@ -0,0 +1,97 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use serde_json::Value;
+
+/// readiness of an applied resource
+#[derive(Debug, Clone, PartialEq)]
+pub enum Readiness {
+    Ready,
+    /// not ready (yet), with the reason
+    Pending(String),
+    /// will never become ready, e.g. a failed job
+    Failed(String),
+}
+
+/// checks the readiness of a resource (as returned by the api server) by its kind:
+///
+/// - `CustomResourceDefinition`s need the condition `Established`
+/// - `Job`s need the condition `Complete` -- they fail with the condition `Failed`
+/// - `Deployment`s need the condition `Available` for their current generation
+/// - `Pod`s need the condition `Ready` unless they succeeded -- they fail if they failed
+/// - all other resources need the condition `Ready` if they have it
+pub fn readiness(resource: &Value) -> Readiness {
+    match resource["kind"].as_str().unwrap_or_default() {
+        "CustomResourceDefinition" => required_condition(resource, "Established"),
+        "Job" => match condition(resource, "Failed") {
+            Some((true, message)) => Readiness::Failed(format!("job failed: {}", message)),
+            _ => required_condition(resource, "Complete"),
+        },
+        "Deployment" => {
+            let generation = resource["metadata"]["generation"].as_i64().unwrap_or(0);
+            let observed_generation = resource["status"]["observedGeneration"]
+                .as_i64()
+                .unwrap_or(0);
+
+            if observed_generation < generation {
+                Readiness::Pending(format!("generation {} was not observed yet", generation))
+            } else {
+                required_condition(resource, "Available")
+            }
+        }
+        "Pod" => match resource["status"]["phase"].as_str() {
+            Some("Succeeded") => Readiness::Ready,
+            Some("Failed") => Readiness::Failed(format!(
+                "pod failed: {}",
+                resource["status"]["message"]
+                    .as_str()
+                    .or_else(|| resource["status"]["reason"].as_str())
+                    .unwrap_or_default()
+            )),
+            _ => required_condition(resource, "Ready"),
+        },
+        _ => match condition(resource, "Ready") {
+            Some((false, message)) => Readiness::Pending(format!("not Ready: {}", message)),
+            _ => Readiness::Ready,
+        },
+    }
+}
+
+fn required_condition(resource: &Value, type_: &str) -> Readiness {
+    match condition(resource, type_) {
+        Some((true, _)) => Readiness::Ready,
+        Some((false, message)) => Readiness::Pending(format!("not {}: {}", type_, message)),
+        None => Readiness::Pending(format!("not {} yet", type_)),
+    }
+}
+
+// the status and message of the condition `type_` of a resource, if it has one
+fn condition(resource: &Value, type_: &str) -> Option<(bool, String)> {
+    resource["status"]["conditions"]
+        .as_array()?
+        .iter()
+        .find(|condition| condition["type"] == type_)
+        .map(|condition| {
+            (
+                condition["status"] == "True",
+                condition["message"]
+                    .as_str()
+                    .or_else(|| condition["reason"].as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+            )
+        })
+}
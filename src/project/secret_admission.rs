@@ -0,0 +1,488 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! validating admission webhook for manifest secrets and config maps: a changed source is rendered
+//! for every project using it (directly or via the `templatesFrom` of a manifest bundle), so broken
+//! templates are noticed before they are stored and not only when the projects get reconciled
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::time::Duration;
+
+use anyhow::Context;
+use k8s_openapi::api::admissionregistration::v1::{
+    RuleWithOperations, ServiceReference, ValidatingWebhook, ValidatingWebhookConfiguration,
+    WebhookClientConfig,
+};
+use k8s_openapi::api::core::v1::{ConfigMap, Secret, Service, ServicePort, ServiceSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, ListMeta, ObjectMeta, Status};
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use krator::admission::AdmissionTls;
+use kube::api::{ListParams, Patch, PatchParams};
+use serde::{Deserialize, Serialize};
+
+use crate::project::conversion::admission_webhook_ca_bundle;
+use crate::project::manifest_source::ManifestSource;
+use crate::project::project::{SECRET_ANNOTATION_KEY, SECRET_ANNOTATION_VALUE};
+use crate::project::{ManifestBundle, Project, ProjectOperatorConfig};
+
+/// port the secret admission webhook listens on -- like the conversion webhook, it is exposed as
+/// an additional port of the admission webhook service
+pub const SECRET_ADMISSION_WEBHOOK_PORT: u16 = 8445;
+pub const SECRET_ADMISSION_WEBHOOK_PATH: &str = "validate-manifest-secrets";
+
+const FIELD_MANAGER: &str = "self-service-operator-secret-admission";
+
+/// how long the api server waits for the webhook -- as it fails open, secrets that can't be
+/// validated in time are admitted
+const WEBHOOK_TIMEOUT_SECONDS: i32 = 30;
+
+/// how long a secret is validated -- less than `WEBHOOK_TIMEOUT_SECONDS`, so a validation that
+/// takes too long is reported instead of being ignored silently by the api server
+const VALIDATION_TIMEOUT: Duration = Duration::from_secs(25);
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionReview {
+    pub api_version: String,
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request: Option<AdmissionRequest>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<AdmissionResponse>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionRequest {
+    pub uid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionResponse {
+    pub uid: String,
+    pub allowed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// a project whose manifests can't be rendered with a changed manifest secret or config map
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderFailure {
+    pub project: String,
+    pub error: String,
+}
+
+/// renders the manifests of all projects that use `source` (a secret or config map) with its new
+/// version -- directly or via manifest bundles that take their templates from it. Returns the
+/// projects for which this fails. Projects whose manifests can't be rendered with the stored
+/// version either are not held against the change
+pub async fn render_failures(
+    client: &kube::Client,
+    namespace: &str,
+    default_manifests_secret: &str,
+    config: &ProjectOperatorConfig,
+    source: ManifestSource,
+) -> anyhow::Result<Vec<RenderFailure>> {
+    if source
+        .annotations
+        .get(SECRET_ANNOTATION_KEY)
+        .map(String::as_str)
+        != Some(SECRET_ANNOTATION_VALUE)
+    {
+        return Ok(vec![]);
+    }
+
+    // the sources are the same for all projects
+    let sources = ManifestSource::list(client, namespace).await?;
+
+    let mut changed_sources = sources.clone();
+    let mut changed = vec![source.name.clone()];
+    ManifestSource::replace(&mut changed_sources, &source);
+    for bundle in bundles_with_templates_from(client, namespace, &source).await? {
+        // a bundle that can't be loaded is broken with or without the change
+        if let Ok(bundle) =
+            ManifestSource::from_manifest_bundle_with_templates(bundle, Some(&source))
+        {
+            changed.push(bundle.name.clone());
+            ManifestSource::replace(&mut changed_sources, &bundle);
+        }
+    }
+
+    let mut failures = vec![];
+    for project in kube::Api::<Project>::all(client.clone())
+        .list(&ListParams::default())
+        .await
+        .context("error listing projects")?
+        .items
+    {
+        let uses_source =
+            match project.associated_sources(default_manifests_secret, &changed_sources, None) {
+                Ok(used) => used.iter().any(|used| changed.contains(used)),
+                // rendering tells whether the change is to blame
+                Err(_) => true,
+            };
+
+        if !uses_source {
+            continue;
+        }
+
+        if let Err(e) = project.associated_manifests_with(
+            default_manifests_secret,
+            config,
+            None,
+            &changed_sources,
+        ) {
+            if project
+                .associated_manifests_with(default_manifests_secret, config, None, &sources)
+                .is_ok()
+            {
+                failures.push(RenderFailure {
+                    project: project.metadata.name.unwrap_or_default(),
+                    error: format!("{:#}", e),
+                });
+            }
+        }
+    }
+
+    Ok(failures)
+}
+
+// the manifest bundles that take their templates from `source` -- a config map is only used if
+// there is no secret with the same name
+async fn bundles_with_templates_from(
+    client: &kube::Client,
+    namespace: &str,
+    source: &ManifestSource,
+) -> anyhow::Result<Vec<ManifestBundle>> {
+    if source.kind == "config map" {
+        match kube::Api::<Secret>::namespaced(client.clone(), namespace)
+            .get(&source.name)
+            .await
+        {
+            Ok(_) => return Ok(vec![]),
+            Err(kube::Error::Api(e)) if e.code == 404 => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let bundles = match kube::Api::<ManifestBundle>::all(client.clone())
+        .list(&ListParams::default())
+        .await
+    {
+        Ok(bundles) => bundles.items,
+        // the manifest bundle crd is not installed
+        Err(kube::Error::Api(e)) if e.code == 404 => vec![],
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(bundles
+        .into_iter()
+        .filter(|bundle| bundle.spec.templates_from.as_ref() == Some(&source.name))
+        .collect())
+}
+
+/// the answer to the admission request `uid` for a change of `source` (e.g. `secret 'foo'`) -- it
+/// is denied if it breaks the manifests of a project, unless `warn_only` is set: then the affected
+/// projects are only named in warnings
+pub fn response(
+    uid: String,
+    source: &str,
+    failures: &[RenderFailure],
+    warn_only: bool,
+) -> AdmissionResponse {
+    if failures.is_empty() {
+        return AdmissionResponse {
+            uid,
+            allowed: true,
+            status: None,
+            warnings: vec![],
+        };
+    }
+
+    if warn_only {
+        return AdmissionResponse {
+            uid,
+            allowed: true,
+            status: None,
+            warnings: failures
+                .iter()
+                .map(|failure| {
+                    format!(
+                        "manifest {} breaks the manifests of project '{}': {}",
+                        source,
+                        failure.project,
+                        failure.error.replace("\n", " ")
+                    )
+                })
+                .collect(),
+        };
+    }
+
+    let message = format!(
+        "manifest {} breaks the manifests of the projects {}:\n{}",
+        source,
+        failures
+            .iter()
+            .map(|failure| format!("'{}'", failure.project))
+            .collect::<Vec<_>>()
+            .join(", "),
+        failures
+            .iter()
+            .map(|failure| format!("  - {}: {}", failure.project, failure.error))
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+
+    AdmissionResponse {
+        uid,
+        allowed: false,
+        status: Some(Status {
+            code: Some(409),
+            details: None,
+            message: Some(message),
+            metadata: ListMeta::default(),
+            reason: None,
+            status: Some("Failure".to_string()),
+        }),
+        warnings: vec![],
+    }
+}
+
+/// answers an admission review for a secret or config map -- sources that can't be checked are
+/// allowed
+pub async fn review(
+    client: &kube::Client,
+    namespace: &str,
+    default_manifests_secret: &str,
+    config: &ProjectOperatorConfig,
+    review: AdmissionReview,
+) -> AdmissionReview {
+    let request = match review.request {
+        Some(request) => request,
+        None => {
+            return AdmissionReview {
+                response: None,
+                ..review
+            }
+        }
+    };
+
+    let source = request
+        .object
+        .map(|object| match object["kind"].as_str() {
+            Some("ConfigMap") => {
+                serde_json::from_value::<ConfigMap>(object).map(ManifestSource::from)
+            }
+            _ => serde_json::from_value::<Secret>(object).map(ManifestSource::from),
+        })
+        .transpose();
+
+    let response = match source {
+        Ok(Some(source)) => {
+            let name = format!("{} '{}'", source.kind, source.name);
+            let failures = tokio::time::timeout(
+                VALIDATION_TIMEOUT,
+                render_failures(client, namespace, default_manifests_secret, config, source),
+            )
+            .await
+            .unwrap_or_else(|_| {
+                Err(anyhow::anyhow!(
+                    "validation took longer than {} seconds",
+                    VALIDATION_TIMEOUT.as_secs()
+                ))
+            });
+
+            match failures {
+                Ok(failures) => response(
+                    request.uid,
+                    &name,
+                    &failures,
+                    config.manifest_validation_warn_only,
+                ),
+                Err(e) => {
+                    warn!("error validating manifest {}: {:#}", name, e);
+                    AdmissionResponse {
+                        uid: request.uid,
+                        allowed: true,
+                        status: None,
+                        warnings: vec![format!(
+                            "manifest {} could not be validated: {:#}",
+                            name, e
+                        )],
+                    }
+                }
+            }
+        }
+        Ok(None) => response(request.uid, "", &[], false),
+        Err(e) => {
+            warn!(
+                "error reading manifest source from admission request: {}",
+                e
+            );
+            response(request.uid, "", &[], false)
+        }
+    };
+
+    AdmissionReview {
+        api_version: review.api_version,
+        kind: review.kind,
+        request: None,
+        response: Some(response),
+    }
+}
+
+/// serves admission reviews for secrets and config maps on `SECRET_ADMISSION_WEBHOOK_PORT` with the certificate of
+/// the admission webhook
+pub async fn serve(
+    tls: AdmissionTls,
+    client: kube::Client,
+    namespace: String,
+    default_manifests_secret: String,
+    config: ProjectOperatorConfig,
+) {
+    use warp::Filter;
+    let routes = warp::post()
+        .and(warp::path(SECRET_ADMISSION_WEBHOOK_PATH))
+        .and(warp::body::json())
+        .and_then(move |admission_review: AdmissionReview| {
+            let client = client.clone();
+            let namespace = namespace.clone();
+            let default_manifests_secret = default_manifests_secret.clone();
+            let config = config.clone();
+            async move {
+                let review = review(
+                    &client,
+                    &namespace,
+                    &default_manifests_secret,
+                    &config,
+                    admission_review,
+                )
+                .await;
+                Ok::<_, Infallible>(warp::reply::json(&review))
+            }
+        });
+
+    warp::serve(routes)
+        .tls()
+        .cert(tls.cert)
+        .key(tls.private_key)
+        .run(([0, 0, 0, 0], SECRET_ADMISSION_WEBHOOK_PORT))
+        .await;
+}
+
+/// exposes the secret admission webhook on the admission webhook service and registers it for
+/// the secrets and config maps in `namespace` -- the admission webhook resources must have been installed before
+pub async fn install_secret_admission_webhook(
+    client: &kube::Client,
+    namespace: &str,
+) -> anyhow::Result<()> {
+    let service_name = Project::admission_webhook_service_name();
+
+    let service = Service {
+        metadata: ObjectMeta {
+            name: Some(service_name.clone()),
+            namespace: Some(namespace.to_string()),
+            ..Default::default()
+        },
+        spec: Some(ServiceSpec {
+            ports: Some(vec![ServicePort {
+                name: Some("manifest-secrets".to_string()),
+                protocol: Some("TCP".to_string()),
+                port: SECRET_ADMISSION_WEBHOOK_PORT.into(),
+                target_port: Some(IntOrString::Int(SECRET_ADMISSION_WEBHOOK_PORT.into())),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }),
+        status: None,
+    };
+
+    kube::Api::<Service>::namespaced(client.clone(), namespace)
+        .patch(
+            &service_name,
+            &PatchParams::apply(FIELD_MANAGER).force(),
+            &Patch::Apply(&service),
+        )
+        .await
+        .context(format!(
+            "error adding the secret admission port to service {}/{}",
+            namespace, service_name
+        ))?;
+
+    let mut namespace_labels = BTreeMap::new();
+    namespace_labels.insert(
+        "kubernetes.io/metadata.name".to_string(),
+        namespace.to_string(),
+    );
+
+    let name = format!("{}-manifest-secrets", service_name);
+    let webhook_configuration = ValidatingWebhookConfiguration {
+        metadata: ObjectMeta {
+            name: Some(name.clone()),
+            ..Default::default()
+        },
+        webhooks: Some(vec![ValidatingWebhook {
+            name: "manifest-secrets.selfservice.innoq.io".to_string(),
+            admission_review_versions: vec!["v1".to_string()],
+            client_config: WebhookClientConfig {
+                ca_bundle: Some(admission_webhook_ca_bundle(client, namespace).await?),
+                service: Some(ServiceReference {
+                    name: service_name,
+                    namespace: namespace.to_string(),
+                    path: Some(format!("/{}", SECRET_ADMISSION_WEBHOOK_PATH)),
+                    port: Some(SECRET_ADMISSION_WEBHOOK_PORT.into()),
+                }),
+                url: None,
+            },
+            // secrets and config maps can still be changed while the operator is not available
+            failure_policy: Some("Ignore".to_string()),
+            namespace_selector: Some(LabelSelector {
+                match_labels: Some(namespace_labels),
+                ..Default::default()
+            }),
+            rules: Some(vec![RuleWithOperations {
+                api_groups: Some(vec!["".to_string()]),
+                api_versions: Some(vec!["v1".to_string()]),
+                operations: Some(vec!["CREATE".to_string(), "UPDATE".to_string()]),
+                resources: Some(vec!["secrets".to_string(), "configmaps".to_string()]),
+                scope: Some("Namespaced".to_string()),
+            }]),
+            side_effects: "None".to_string(),
+            timeout_seconds: Some(WEBHOOK_TIMEOUT_SECONDS),
+            ..Default::default()
+        }]),
+    };
+
+    kube::Api::<ValidatingWebhookConfiguration>::all(client.clone())
+        .patch(
+            &name,
+            &PatchParams::apply(FIELD_MANAGER).force(),
+            &Patch::Apply(&webhook_configuration),
+        )
+        .await
+        .context(format!(
+            "error installing validating webhook configuration {}",
+            name
+        ))?;
+
+    Ok(())
+}
@@ -0,0 +1,496 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! re-reconciliation of projects when the manifest sources (manifest bundles, secrets and config
+//! maps) they use change
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use kube::api::{ListParams, Patch, PatchParams};
+use kube::Resource;
+use kube_runtime::watcher;
+use kube_runtime::watcher::Event;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::project::manifest_source::{precedence, ManifestSource};
+use crate::project::project::{
+    MANIFESTS_CHANGED_AT_ANNOTATION_KEY, SECRET_ANNOTATION_KEY, SECRET_ANNOTATION_VALUE,
+};
+use crate::project::project_status::CONDITION_PROGRESSING;
+use crate::project::states::ProjectPhase;
+use crate::project::{ManifestBundle, Project, ProjectOperatorConfig};
+
+const WATCH_ERROR_DELAY: Duration = Duration::from_secs(5);
+/// how long a rollout waits for a re-reconciled project to be reconciled -- long enough for the
+/// readiness waits of a few sync waves
+const RECONCILE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+const RECONCILE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// the resource versions of the manifest sources by their kind and name -- secrets and config maps
+/// the operator can't access are left out
+#[derive(Debug, Default)]
+pub struct SourceVersions {
+    versions: BTreeMap<(&'static str, String), String>,
+    listed: BTreeSet<&'static str>,
+}
+
+impl SourceVersions {
+    /// records the current version of a source (`None` if it was deleted or can't be accessed) --
+    /// returns whether it changed
+    pub fn update(&mut self, kind: &'static str, name: &str, version: Option<String>) -> bool {
+        let key = (kind, name.to_string());
+        let previous = match version {
+            Some(version) => self.versions.insert(key, version.clone()) != Some(version),
+            None => self.versions.remove(&key).is_some(),
+        };
+
+        previous && self.listed.contains(kind)
+    }
+
+    /// replaces all sources of a kind after a (re-)list -- returns the kinds and names of the
+    /// sources that changed. Nothing changed for the first list of a kind
+    pub fn replace(
+        &mut self,
+        kind: &'static str,
+        sources: Vec<(String, Option<String>)>,
+    ) -> Vec<(&'static str, String)> {
+        let mut changed = vec![];
+
+        let mut removed = self
+            .versions
+            .keys()
+            .filter(|(k, _)| *k == kind)
+            .map(|(_, name)| name.clone())
+            .collect::<BTreeSet<_>>();
+
+        for (name, version) in sources {
+            removed.remove(&name);
+            if self.update(kind, &name, version) {
+                changed.push((kind, name));
+            }
+        }
+
+        for name in removed {
+            if self.update(kind, &name, None) {
+                changed.push((kind, name));
+            }
+        }
+
+        self.listed.insert(kind);
+        changed
+    }
+}
+
+// the kind, name and version of a manifest source -- secrets and config maps without the
+// operator-access annotation don't have a version
+fn source_version<K: Resource>(
+    kind: &'static str,
+    source: &K,
+) -> (&'static str, String, Option<String>) {
+    let meta = source.meta();
+    let accessible = kind == "manifest bundle"
+        || meta
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(SECRET_ANNOTATION_KEY))
+            .map(String::as_str)
+            == Some(SECRET_ANNOTATION_VALUE);
+
+    (
+        kind,
+        meta.name.clone().unwrap_or_default(),
+        meta.resource_version.clone().filter(|_| accessible),
+    )
+}
+
+enum SourceEvent {
+    Changed(&'static str, String, Option<String>),
+    Listed(&'static str, Vec<(String, Option<String>)>),
+    Error(String),
+}
+
+fn source_events<K>(
+    kind: &'static str,
+    api: kube::Api<K>,
+) -> futures::stream::BoxStream<'static, SourceEvent>
+where
+    K: Resource + Clone + serde::de::DeserializeOwned + std::fmt::Debug + Send + 'static,
+{
+    watcher(api, ListParams::default())
+        .map(move |event| match event {
+            Ok(Event::Applied(source)) => {
+                let (kind, name, version) = source_version(kind, &source);
+                SourceEvent::Changed(kind, name, version)
+            }
+            Ok(Event::Deleted(source)) => {
+                let (kind, name, _) = source_version(kind, &source);
+                SourceEvent::Changed(kind, name, None)
+            }
+            Ok(Event::Restarted(sources)) => SourceEvent::Listed(
+                kind,
+                sources
+                    .iter()
+                    .map(|source| {
+                        let (_, name, version) = source_version(kind, source);
+                        (name, version)
+                    })
+                    .collect(),
+            ),
+            Err(e) => SourceEvent::Error(format!("error watching {}s: {}", kind, e)),
+        })
+        .boxed()
+}
+
+/// watches the manifest sources and re-reconciles all projects that use a source that changed --
+/// projects are re-reconciled one after the other, `config.manifest_rollout_interval()` apart, so
+/// a bad change doesn't hit all projects at once. The rollout runs in its own task: sources that
+/// change while it runs replace it with one that continues with the projects it didn't reach yet
+pub async fn reconcile_on_source_changes(
+    client: kube::Client,
+    namespace: String,
+    default_manifests_secret: String,
+    config: ProjectOperatorConfig,
+) {
+    let mut events = futures::stream::select_all(vec![
+        source_events(
+            "manifest bundle",
+            kube::Api::<ManifestBundle>::all(client.clone()),
+        ),
+        source_events(
+            "secret",
+            kube::Api::<Secret>::namespaced(client.clone(), &namespace),
+        ),
+        source_events(
+            "config map",
+            kube::Api::<ConfigMap>::namespaced(client.clone(), &namespace),
+        ),
+    ]);
+
+    let interval = config
+        .manifest_rollout_interval()
+        .to_std()
+        .unwrap_or_default();
+    let mut versions = SourceVersions::default();
+    let mut rollout: Option<Rollout> = None;
+    while let Some(event) = events.next().await {
+        let changed = match event {
+            SourceEvent::Changed(kind, name, version) => {
+                if versions.update(kind, &name, version) {
+                    vec![(kind, name)]
+                } else {
+                    vec![]
+                }
+            }
+            SourceEvent::Listed(kind, sources) => versions.replace(kind, sources),
+            SourceEvent::Error(e) => {
+                // e.g. the manifest bundle crd is not installed
+                debug!("{}", e);
+                tokio::time::sleep(WATCH_ERROR_DELAY).await;
+                vec![]
+            }
+        };
+
+        if changed.is_empty() {
+            continue;
+        }
+
+        info!("manifest sources {:?} changed", changed);
+        let projects =
+            match projects_using(&client, &namespace, &default_manifests_secret, changed).await {
+                Ok(projects) => projects,
+                Err(e) => {
+                    warn!(
+                        "error re-reconciling projects after manifest sources changed: {}",
+                        e
+                    );
+                    continue;
+                }
+            };
+
+        let progress = rollout.take().map(Rollout::stop).unwrap_or_default();
+        rollout = Some(Rollout::start(client.clone(), interval, progress, projects));
+    }
+}
+
+// the names of all projects that use one of the `changed` sources (as `(kind, name)`, directly or
+// via the `templatesFrom` of a manifest bundle)
+async fn projects_using(
+    client: &kube::Client,
+    namespace: &str,
+    default_manifests_secret: &str,
+    mut changed: Vec<(&'static str, String)>,
+) -> anyhow::Result<Vec<String>> {
+    if let Ok(bundles) = kube::Api::<ManifestBundle>::all(client.clone())
+        .list(&ListParams::default())
+        .await
+    {
+        for bundle in bundles.items {
+            let templates_changed =
+                bundle
+                    .spec
+                    .templates_from
+                    .as_ref()
+                    .is_some_and(|templates_from| {
+                        changed.iter().any(|(kind, name)| {
+                            *kind != "manifest bundle" && name == templates_from
+                        })
+                    });
+            if templates_changed {
+                changed.push(("manifest bundle", bundle.metadata.name.unwrap_or_default()));
+            }
+        }
+    }
+
+    let sources = ManifestSource::list(client, namespace).await?;
+    let mut projects = vec![];
+    for project in kube::Api::<Project>::all(client.clone())
+        .list(&ListParams::default())
+        .await?
+        .items
+    {
+        // projects whose sources can't be determined are re-reconciled as well -- the change
+        // might fix them
+        let uses_changed_source =
+            match project.associated_sources(default_manifests_secret, &sources, None) {
+                Ok(used) => used
+                    .iter()
+                    .any(|used| is_affected_by(used, &sources, &changed)),
+                Err(_) => true,
+            };
+
+        if uses_changed_source {
+            projects.push(project.metadata.name.unwrap_or_default());
+        }
+    }
+
+    Ok(projects)
+}
+
+// whether a project using the source with name `used` is affected by the `changed` sources: a
+// changed source with this name matters unless a source of another kind takes precedence over it
+// (e.g. a config map with the same name as a secret). A source that takes precedence and was
+// deleted matters as well, as it no longer hides the others
+fn is_affected_by(
+    used: &str,
+    sources: &[ManifestSource],
+    changed: &[(&'static str, String)],
+) -> bool {
+    let used_precedence = sources
+        .iter()
+        .find(|source| source.name == used)
+        .map(ManifestSource::precedence);
+
+    changed.iter().any(|(kind, name)| {
+        name == used && used_precedence.is_none_or(|used| precedence(kind) <= used)
+    })
+}
+
+// the projects a rollout still has to re-reconcile
+#[derive(Debug, Default)]
+struct RolloutProgress {
+    projects: VecDeque<String>,
+    // when the next project is due
+    next_at: Option<Instant>,
+    // the project that was re-reconciled last -- the rollout only continues once it is reconciled
+    last: Option<Reconciliation>,
+}
+
+// a re-reconciliation the rollout triggered
+#[derive(Debug, Clone)]
+struct Reconciliation {
+    project: String,
+    // whether the project had failed before
+    failed_before: bool,
+    triggered_at: DateTime<Utc>,
+    // the resource version of the project right after the reconciliation was triggered
+    resource_version: Option<String>,
+}
+
+// a rollout of changed manifest sources to the projects using them
+struct Rollout {
+    task: JoinHandle<()>,
+    progress: Arc<Mutex<RolloutProgress>>,
+}
+
+impl Rollout {
+    // starts a rollout that continues with `progress` (of a rollout it replaces) and then
+    // re-reconciles the `projects` that aren't pending yet
+    fn start(
+        client: kube::Client,
+        interval: Duration,
+        mut progress: RolloutProgress,
+        projects: Vec<String>,
+    ) -> Self {
+        for project in projects {
+            if !progress.projects.contains(&project) {
+                progress.projects.push_back(project);
+            }
+        }
+
+        let progress = Arc::new(Mutex::new(progress));
+        let task = tokio::spawn(roll_out(client, interval, progress.clone()));
+        Rollout { task, progress }
+    }
+
+    // cancels the rollout -- returns what it didn't finish
+    fn stop(self) -> RolloutProgress {
+        self.task.abort();
+        std::mem::take(&mut *self.progress.lock().unwrap())
+    }
+}
+
+// re-reconciles the pending projects one after the other, `interval` apart. A project is only
+// removed once it was re-reconciled, so a replacing rollout picks it up if this one is cancelled
+// before. Before the next project is due, the rollout waits until the last one is reconciled and
+// stops if it failed (and was fine before) or didn't finish in time -- the change is likely broken
+// and shouldn't reach the remaining projects
+async fn roll_out(client: kube::Client, interval: Duration, progress: Arc<Mutex<RolloutProgress>>) {
+    let api = kube::Api::<Project>::all(client);
+    loop {
+        let last = progress.lock().unwrap().last.clone();
+        if let Some(last) = last {
+            match reconciliation_outcome(&api, &last).await {
+                Some(failed) if failed && !last.failed_before => {
+                    return stop_rollout(
+                        &progress,
+                        &format!("project {} failed after it was re-reconciled", last.project),
+                    );
+                }
+                Some(_) => progress.lock().unwrap().last = None,
+                None => {
+                    return stop_rollout(
+                        &progress,
+                        &format!(
+                            "project {} was not reconciled within {} minutes",
+                            last.project,
+                            RECONCILE_TIMEOUT.as_secs() / 60
+                        ),
+                    );
+                }
+            }
+        }
+
+        let next_at = progress.lock().unwrap().next_at;
+        if let Some(next_at) = next_at {
+            tokio::time::sleep_until(next_at).await;
+        }
+
+        let name = match progress.lock().unwrap().projects.front().cloned() {
+            Some(name) => name,
+            None => return,
+        };
+
+        let failed_before = api
+            .get(&name)
+            .await
+            .map(|project| has_failed(&project))
+            .unwrap_or_default();
+        let triggered_at = Utc::now();
+        info!("re-reconciling project {} as its manifests changed", name);
+        // changes of the metadata trigger a reconciliation, status updates don't
+        let patch = serde_json::json!({
+            "metadata": {
+                "annotations": { MANIFESTS_CHANGED_AT_ANNOTATION_KEY: triggered_at.to_rfc3339() }
+            }
+        });
+        let patched = api
+            .patch(&name, &PatchParams::default(), &Patch::Merge(&patch))
+            .await;
+
+        let mut progress = progress.lock().unwrap();
+        progress.projects.pop_front();
+        progress.next_at = Some(Instant::now() + interval);
+        progress.last = match patched {
+            Ok(project) => Some(Reconciliation {
+                project: name,
+                failed_before,
+                triggered_at,
+                resource_version: project.metadata.resource_version,
+            }),
+            Err(e) => {
+                warn!("error re-reconciling project {}: {}", name, e);
+                None
+            }
+        };
+    }
+}
+
+// stops a rollout and tells which projects it didn't re-reconcile
+fn stop_rollout(progress: &Mutex<RolloutProgress>, reason: &str) {
+    let skipped = std::mem::take(&mut progress.lock().unwrap().projects);
+    warn!(
+        "stopping the rollout of changed manifest sources: {} -- not re-reconciling {:?}",
+        reason, skipped
+    );
+}
+
+// waits until a triggered re-reconciliation finished -- returns whether the project failed, or
+// `None` if it didn't finish within `RECONCILE_TIMEOUT`. Deleted projects count as reconciled
+async fn reconciliation_outcome(
+    api: &kube::Api<Project>,
+    reconciliation: &Reconciliation,
+) -> Option<bool> {
+    let deadline = Instant::now() + RECONCILE_TIMEOUT;
+    loop {
+        match api.get(&reconciliation.project).await {
+            Ok(project) if is_reconciled(&project, reconciliation) => {
+                return Some(has_failed(&project))
+            }
+            Ok(_) => {}
+            Err(kube::Error::Api(e)) if e.code == 404 => return Some(false),
+            Err(e) => debug!("error reading project {}: {}", reconciliation.project, e),
+        }
+
+        if Instant::now() >= deadline {
+            return None;
+        }
+        tokio::time::sleep(RECONCILE_POLL_INTERVAL).await;
+    }
+}
+
+// whether a project was reconciled since the re-reconciliation was triggered: it changed since,
+// and its `Progressing` condition turned `False` afterwards -- this happens when it waits for
+// changes again or failed
+fn is_reconciled(project: &Project, reconciliation: &Reconciliation) -> bool {
+    project.metadata.resource_version != reconciliation.resource_version
+        && project
+            .status
+            .as_ref()
+            .and_then(|status| status.conditions.as_ref())
+            .is_some_and(|conditions| {
+                conditions.iter().any(|condition| {
+                    condition.type_ == CONDITION_PROGRESSING
+                        && condition.status == "False"
+                        && DateTime::parse_from_rfc3339(&condition.last_transition_time)
+                            .is_ok_and(|at| at >= reconciliation.triggered_at)
+                })
+            })
+}
+
+// whether a project is in the error state
+fn has_failed(project: &Project) -> bool {
+    project
+        .status
+        .as_ref()
+        .and_then(|status| status.phase.as_ref())
+        .is_some_and(|phase| matches!(phase, ProjectPhase::FailedDueToError))
+}
@@ -14,28 +14,46 @@
  * limitations under the License.
  */
 
-use std::path::Path;
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::bail;
 use anyhow::ensure;
 use anyhow::Context;
+use chrono::Utc;
 use http::Request;
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference};
 use krator::{Manifest, State, Transition};
 use serde::Deserialize;
 use tokio::sync::RwLock;
 
+use crate::project::events::{
+    record_event, EVENT_TYPE_NORMAL, EVENT_TYPE_WARNING, REASON_FIELD_CONFLICT,
+    REASON_MANIFEST_APPLIED, REASON_MANIFEST_APPLY_RETRY, REASON_PROJECT_READY,
+};
+use crate::project::expiry::{format_countdown, parse_duration};
+use crate::project::one_shot::{
+    insert_one_shot_record, merge_one_shot_records, needs_apply, one_shot_mode, one_shot_record,
+    OneShotMode, CREATED_RECORD,
+};
 use crate::project::operator::ProjectOperatorState;
 use crate::project::project::{
-    ONE_SHOT_MANIFEST_ANNOTATION_KEY, ONE_SHOT_MANIFEST_ANNOTATION_VALUE_ONCE,
+    APPLIED_FOR_LABEL_KEY, DELETION_POLICY_ANNOTATION_KEY, DELETION_POLICY_ANNOTATION_VALUE_DELETE,
+    DELETION_POLICY_ANNOTATION_VALUE_RETAIN, FIELD_OWNERSHIP_ANNOTATION_KEY,
+    FIELD_OWNERSHIP_ANNOTATION_VALUE_AUTHORITATIVE, FIELD_OWNERSHIP_ANNOTATION_VALUE_INITIAL,
+    READINESS_TIMEOUT_ANNOTATION_KEY, SYNC_WAVE_ANNOTATION_KEY,
+};
+use crate::project::project_status::{
+    ProjectCondition, ProjectFieldConflict, ProjectResourceStatus, ProjectStatus,
+    CONDITION_MANIFESTS_APPLIED, CONDITION_NAMESPACE_READY, CONDITION_READY,
 };
-use crate::project::project_status::ProjectStatus;
+use crate::project::prune::{prune, stale_resources};
+use crate::project::readiness::{readiness, Readiness};
 use crate::project::states::Error;
 use crate::project::states::{ProjectPhase, ProjectState, WaitForChanges};
-use crate::project::Project;
+use crate::project::{Project, ProjectOperatorConfig};
 use serde_yaml::Value;
-use std::ops::Mul;
 
 #[derive(Debug, Default)]
 pub(crate) struct ApplyManifests;
@@ -52,11 +70,15 @@ impl State<ProjectState> for ApplyManifests {
         let project = manifest.latest();
         let delay = shared.manifest_retry_delay;
 
+        // the resources are about to change, their watches are started again afterwards
+        state.drift_watch = None;
+
         let manifests = project
             .associated_manifests(
                 &shared.client,
                 &shared.default_manifests_secret,
                 &shared.default_ns,
+                &shared.config,
             )
             .await;
 
@@ -67,40 +89,145 @@ impl State<ProjectState> for ApplyManifests {
             }
 
             Ok(manifests) => {
-                let mut manifests = manifests
-                    .iter()
-                    .map(|s| (0, s))
-                    .collect::<Vec<(u16, &String)>>();
-
-                let mut iteration_counter = 0;
-                let max_retries = 5;
-                while let Some((i, manifest)) = manifests.pop() {
-                    if let Err(e) =
-                        apply_yaml_manifest(&shared.client, &manifest, &project, state).await
-                    {
-                        if i >= max_retries {
-                            state.error = format!(
-                                "error installing manifest: giving up after {} retries: {}\nmanifest was:\n{}",
-                                i,
-                                e.to_string(),
-                                &manifest
-                            );
-                            return Transition::next(self, Error);
+                let waves = match sync_waves(&manifests) {
+                    Ok(waves) => waves,
+                    Err(e) => {
+                        state.error = e.to_string();
+                        return Transition::next(self, Error);
+                    }
+                };
+
+                let owned_namespaces = match owned_namespaces(&state.namespaces, &manifests) {
+                    Ok(namespaces) => namespaces,
+                    Err(e) => {
+                        state.error = e.to_string();
+                        return Transition::next(self, Error);
+                    }
+                };
+
+                let mut inventory = vec![];
+                let mut retained_resources = vec![];
+                let mut applied_manifests = BTreeMap::new();
+                state.resources = vec![];
+                state.field_conflicts = vec![];
+                for (wave, manifests) in waves {
+                    // the paths are resolved wave by wave: kinds can be defined by the custom
+                    // resource definitions of earlier waves
+                    for manifest in manifests.iter() {
+                        let path = match resource_path(&shared.client, manifest).await {
+                            Ok(path) => path,
+                            Err(e) => {
+                                state.error = e.to_string();
+                                return Transition::next(self, Error);
+                            }
+                        };
+
+                        if !inventory.contains(&path) {
+                            inventory.push(path.clone());
+                        }
+
+                        match ensure_retainable(manifest, &owned_namespaces)
+                            .and_then(|_| is_retained_resource(manifest))
+                        {
+                            Ok(true) if !retained_resources.contains(&path) => {
+                                retained_resources.push(path.clone())
+                            }
+                            Ok(_) => {}
+                            Err(e) => {
+                                state.error = e.to_string();
+                                return Transition::next(self, Error);
+                            }
                         }
 
-                        // pause between each iteration with backoff factor, so resources that were
-                        // applied can get available -- this might be necessary if some resources
-                        // depend on others
-                        if i != iteration_counter {
-                            iteration_counter = i;
-                            tokio::time::sleep(delay.mul(i.into())).await;
+                        // one shot resources may be changed, so they are not watched for drift
+                        if !is_one_shot_resource(manifest).unwrap_or(false) {
+                            applied_manifests.insert(path, manifest.clone());
                         }
-                        manifests.insert(0, (i + 1, &manifest));
+                    }
+
+                    debug!("applying sync wave {} of project {}", wave, state.name);
+                    let applied = apply_sync_wave(
+                        &shared.client,
+                        manifests,
+                        &project,
+                        state,
+                        &shared.config,
+                        delay,
+                    )
+                    .await;
+
+                    // the resources of a wave have to be ready before the next wave is applied
+                    let ready = match applied {
+                        Ok(applied) => {
+                            wait_for_readiness(
+                                &shared.client,
+                                &applied,
+                                state,
+                                shared.config.readiness_timeout(),
+                            )
+                            .await
+                        }
+                        Err(e) => Err(e),
+                    };
+
+                    if let Err(e) = ready {
+                        state.error = e.to_string();
+                        return Transition::next(self, Error);
+                    }
+                }
+
+                // resources are only pruned once all manifests were applied successfully
+                let previous_inventory = project
+                    .status
+                    .as_ref()
+                    .and_then(|status| status.inventory.clone())
+                    .unwrap_or_default();
+                let mut kept = prune(
+                    &shared.client,
+                    &project,
+                    &state.namespaces,
+                    stale_resources(&previous_inventory, &inventory),
+                    shared.config.prune_dry_run,
+                )
+                .await;
+                // retained resources that are no longer rendered survive the project as well
+                let previous_retained_resources = project
+                    .status
+                    .as_ref()
+                    .and_then(|status| status.retained_resources.clone())
+                    .unwrap_or_default();
+                for path in kept.iter() {
+                    if previous_retained_resources.contains(path)
+                        && !retained_resources.contains(path)
+                    {
+                        retained_resources.push(path.clone());
                     }
                 }
+                inventory.append(&mut kept);
+                state.inventory = inventory;
+                state.retained_resources = retained_resources;
+                state.applied_manifests = applied_manifests;
+                state.synced_at = Some(Utc::now());
+                insert_one_shot_record(
+                    &mut state.applied_one_shot_resources,
+                    CREATED_RECORD.to_string(),
+                );
             }
         }
 
+        // re-reconciling a project that is ready already is not worth an event
+        if !is_ready(&project) {
+            record_event(
+                &shared.client,
+                &project,
+                &state.namespaces,
+                EVENT_TYPE_NORMAL,
+                REASON_PROJECT_READY,
+                &format!("all manifests of project {} were applied", state.name),
+            )
+            .await;
+        }
+
         Transition::next(self, WaitForChanges)
     }
 
@@ -113,83 +240,526 @@ impl State<ProjectState> for ApplyManifests {
 
         let applied_one_shot_resources = (project.status.clone() as Option<ProjectStatus>)
             .unwrap_or_else(ProjectStatus::default)
-            .applied_one_shot_resources
-            .into_iter()
-            .collect();
+            .applied_one_shot_resources;
 
         Ok(ProjectStatus {
             phase: Some(ProjectPhase::ApplyingManifests),
             message: Some("applying configured manifests".to_string()),
             summary: Some("applying configured manifests".to_string()),
-            applied_one_shot_resources: (&applied_one_shot_resources
-                | &state.applied_one_shot_resources)
-                .into_iter()
-                .collect(),
+            private: Some(project.spec.private),
+            expires_at: project.expiry_timestamp(),
+            namespaces: state.namespaces_status(),
+            orphaned_namespaces: Some(state.orphaned_namespaces.clone()),
+            conditions: Some(ProjectStatus::progressing_conditions(
+                project,
+                "ApplyingManifests",
+                "applying configured manifests",
+                &[CONDITION_MANIFESTS_APPLIED, CONDITION_READY],
+                vec![ProjectCondition::new(
+                    CONDITION_NAMESPACE_READY,
+                    true,
+                    "NamespaceCreated",
+                    "all namespaces exist",
+                )],
+            )),
+            observed_generation: project.metadata.generation,
+            resources: None,
+            inventory: None,
+            retained_resources: None,
+            field_conflicts: None,
+            applied_one_shot_resources: merge_one_shot_records(
+                &applied_one_shot_resources,
+                &state.applied_one_shot_resources,
+            ),
+        })
+    }
+}
+
+// whether the `Ready` condition of the project is `True`
+fn is_ready(project: &Project) -> bool {
+    project
+        .status
+        .as_ref()
+        .and_then(|status| status.conditions.as_ref())
+        .map(|conditions| {
+            conditions
+                .iter()
+                .any(|condition| condition.type_ == CONDITION_READY && condition.status == "True")
+        })
+        .unwrap_or(false)
+}
+
+const MAX_RETRIES: u32 = 5;
+
+// applies all manifests of a sync wave: manifests that fail with a transient error are retried
+// (with backoff, so the resources they depend on can become available) -- any other error aborts.
+// Returns the manifests that were applied (one shot resources that were applied before are not)
+async fn apply_sync_wave(
+    client: &kube::Client,
+    manifests: Vec<String>,
+    project: &Project,
+    state: &mut ProjectState,
+    config: &ProjectOperatorConfig,
+    delay: Duration,
+) -> anyhow::Result<Vec<String>> {
+    let mut manifests = manifests;
+    let mut applied_manifests = vec![];
+
+    for retry in 0..=MAX_RETRIES {
+        if retry > 0 {
+            tokio::time::sleep(delay * retry).await;
+        }
+
+        let mut failed_manifests = vec![];
+        for manifest in manifests {
+            match apply_yaml_manifest(client, &manifest, project, state, config).await {
+                Ok(Applied::Changed) => {
+                    record_event(
+                        client,
+                        project,
+                        &state.namespaces,
+                        EVENT_TYPE_NORMAL,
+                        REASON_MANIFEST_APPLIED,
+                        &format!("applied {}", describe_manifest(&manifest)),
+                    )
+                    .await;
+                    applied_manifests.push(manifest);
+                }
+                Ok(Applied::Unchanged) => applied_manifests.push(manifest),
+                Ok(Applied::Skipped) => {}
+                Err(e) if is_transient_error(&e) && retry < MAX_RETRIES => {
+                    record_event(
+                        client,
+                        project,
+                        &state.namespaces,
+                        EVENT_TYPE_WARNING,
+                        REASON_MANIFEST_APPLY_RETRY,
+                        &format!(
+                            "error applying {} (retry {} of {}): {}",
+                            describe_manifest(&manifest),
+                            retry + 1,
+                            MAX_RETRIES,
+                            e
+                        ),
+                    )
+                    .await;
+                    failed_manifests.push((manifest, e));
+                }
+                Err(e) if is_transient_error(&e) => bail!(
+                    "error installing manifest: giving up after {} retries: {}\nmanifest was:\n{}",
+                    MAX_RETRIES,
+                    e,
+                    &manifest
+                ),
+                Err(e) => bail!(
+                    "error installing manifest: {}\nmanifest was:\n{}",
+                    e,
+                    &manifest
+                ),
+            }
+        }
+
+        if failed_manifests.is_empty() {
+            return Ok(applied_manifests);
+        }
+
+        manifests = failed_manifests
+            .into_iter()
+            .map(|(manifest, _)| manifest)
+            .collect();
+    }
+
+    unreachable!("the last retry either succeeds or bails")
+}
+
+const READINESS_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// waits until all applied resources are ready (see `readiness()`) and records their readiness in
+// the project state -- fails if a resource fails or does not become ready within its timeout
+async fn wait_for_readiness(
+    client: &kube::Client,
+    manifests: &[String],
+    state: &mut ProjectState,
+    default_timeout: chrono::Duration,
+) -> anyhow::Result<()> {
+    let started = Instant::now();
+
+    let mut pending = vec![];
+    for manifest in manifests {
+        let yaml: Value = serde_yaml::from_str(manifest)?;
+        let field = |value: &Value| value.as_str().map(String::from);
+
+        let timeout =
+            match yaml["metadata"]["annotations"][READINESS_TIMEOUT_ANNOTATION_KEY].as_str() {
+                Some(timeout) => parse_duration(timeout).context(format!(
+                    "annotation '{}' of {} is not a valid duration",
+                    READINESS_TIMEOUT_ANNOTATION_KEY,
+                    describe_manifest(manifest)
+                ))?,
+                None => default_timeout,
+            };
+
+        state.resources.push(ProjectResourceStatus {
+            kind: field(&yaml["kind"]).unwrap_or_default(),
+            namespace: field(&yaml["metadata"]["namespace"]),
+            name: field(&yaml["metadata"]["name"]).unwrap_or_default(),
+            ready: false,
+            message: None,
+        });
+
+        pending.push((
+            state.resources.len() - 1,
+            resource_path(client, manifest).await?,
+            timeout.to_std().unwrap_or_default(),
+            describe_manifest(manifest),
+        ));
+    }
+
+    loop {
+        let mut still_pending = vec![];
+        for (index, path, timeout, description) in pending {
+            let request = Request::builder()
+                .uri(&path)
+                .method("GET")
+                .body("".into())
+                .unwrap();
+
+            let readiness = match client.request::<serde_json::Value>(request).await {
+                Ok(resource) => readiness(&resource),
+                Err(e) => Readiness::Pending(format!("error reading resource: {}", e)),
+            };
+
+            let resource = &mut state.resources[index];
+            match readiness {
+                Readiness::Ready => {
+                    resource.ready = true;
+                    resource.message = None;
+                }
+                Readiness::Failed(message) => {
+                    resource.message = Some(message.clone());
+                    bail!("{} failed: {}", description, message);
+                }
+                Readiness::Pending(message) if started.elapsed() >= timeout => {
+                    resource.message = Some(message.clone());
+                    bail!(
+                        "{} did not become ready within {}: {}",
+                        description,
+                        format_countdown(chrono::Duration::from_std(timeout)?),
+                        message
+                    );
+                }
+                Readiness::Pending(message) => {
+                    resource.message = Some(message);
+                    still_pending.push((index, path, timeout, description));
+                }
+            }
+        }
+
+        if still_pending.is_empty() {
+            return Ok(());
+        }
+
+        pending = still_pending;
+        tokio::time::sleep(READINESS_POLL_INTERVAL).await;
+    }
+}
+
+/// groups manifests by their sync wave (in ascending order) -- the order of manifests within a
+/// wave is kept
+pub fn sync_waves(manifests: &[String]) -> anyhow::Result<BTreeMap<i32, Vec<String>>> {
+    let mut waves: BTreeMap<i32, Vec<String>> = BTreeMap::new();
+    for manifest in manifests {
+        waves
+            .entry(sync_wave(manifest)?)
+            .or_default()
+            .push(manifest.clone());
+    }
+
+    Ok(waves)
+}
+
+/// the sync wave of a manifest: the value of its sync wave annotation or -- if it is not set --
+/// an early wave for kinds other resources usually depend on
+pub fn sync_wave(yaml_manifest: &str) -> anyhow::Result<i32> {
+    let yaml: Value = serde_yaml::from_str(yaml_manifest)?;
+
+    if let Some(wave) = yaml["metadata"]["annotations"][SYNC_WAVE_ANNOTATION_KEY].as_str() {
+        return wave.trim().parse().context(format!(
+            "annotation '{}' of {} must be an integer, got '{}'",
+            SYNC_WAVE_ANNOTATION_KEY,
+            describe_manifest(yaml_manifest),
+            wave
+        ));
+    }
+
+    Ok(match yaml["kind"].as_str().unwrap_or_default() {
+        "CustomResourceDefinition" | "Namespace" => -2,
+        "ServiceAccount" | "Role" | "ClusterRole" | "RoleBinding" | "ClusterRoleBinding" => -1,
+        _ => 0,
+    })
+}
+
+/// errors that might go away by just trying again: the resource a manifest depends on does not
+/// exist (yet), conflicts, rate limiting, unavailable api servers and connection problems
+pub fn is_transient_error(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|e| e.downcast_ref::<kube::Error>())
+        .any(|e| match e {
+            kube::Error::Api(response) => {
+                matches!(response.code, 404 | 409 | 429 | 500 | 502 | 503 | 504)
+            }
+            kube::Error::HyperError(_) | kube::Error::Service(_) => true,
+            _ => false,
         })
+}
+
+pub(crate) const FIELD_MANAGER_QUERY_ARG: &str = "fieldManager=self-service-operator&force=true";
+
+// the query args of a server side apply -- with `force`, fields owned by other field managers are
+// taken over instead of failing with a conflict
+pub(crate) fn field_manager_query_args(force: bool) -> &'static str {
+    if force {
+        FIELD_MANAGER_QUERY_ARG
+    } else {
+        "fieldManager=self-service-operator"
     }
 }
 
-const FIELD_MANAGER_QUERY_ARG: &str = "fieldManager=self-service-operator&force=true";
+// describes a manifest as `<kind> <namespace>/<name>` for events
+pub(crate) fn describe_manifest(yaml_manifest: &str) -> String {
+    let yaml: Value = serde_yaml::from_str(yaml_manifest).unwrap_or_default();
+    let field = |value: &Value| value.as_str().unwrap_or("?").to_string();
+
+    format!(
+        "{} {}/{}",
+        field(&yaml["kind"]),
+        field(&yaml["metadata"]["namespace"]),
+        field(&yaml["metadata"]["name"])
+    )
+}
+
+/// the outcome of applying a manifest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Applied {
+    /// the manifest was not applied: it is a one shot resource that was applied before, an
+    /// `initial` resource that exists already or it has field conflicts (which are recorded in the
+    /// project state and reported with an event)
+    Skipped,
+    /// the manifest was applied, but the api server did not change the resource
+    Unchanged,
+    /// the resource was created or changed
+    Changed,
+}
 
+/// applies a manifest -- see `Applied` for the possible outcomes
 pub async fn apply_yaml_manifest(
     client: &kube::Client,
     yaml_manifest: &str,
     project: &Project,
     state: &mut ProjectState,
-) -> anyhow::Result<()> {
+    config: &ProjectOperatorConfig,
+) -> anyhow::Result<Applied> {
     let path = resource_path(&client, yaml_manifest).await?;
 
-    let is_one_shot_resource = is_one_shot_resource(yaml_manifest)?;
+    let one_shot = match one_shot_mode(yaml_manifest)? {
+        Some(mode) => {
+            let record = one_shot_record(mode, &path, yaml_manifest, project);
+            let records = merge_one_shot_records(
+                &project
+                    .status
+                    .clone()
+                    .unwrap_or_default()
+                    .applied_one_shot_resources,
+                &state.applied_one_shot_resources,
+            );
+            if !needs_apply(mode, &record, &records) {
+                info!("one shot resource {} already applied, skipping", &path);
+                return Ok(Applied::Skipped);
+            }
+            Some((mode, record))
+        }
+        None => None,
+    };
 
-    let applied_one_shot_resources = (project.status.clone() as Option<ProjectStatus>)
-        .unwrap_or_else(ProjectStatus::default)
-        .applied_one_shot_resources;
+    let manifest = add_owner_to_yaml_manifest(yaml_manifest, project)?;
 
-    if is_one_shot_resource && applied_one_shot_resources.contains(&path) {
-        info!("one shot resource {} already applied, skipping", &path);
-        return Ok(());
+    if let Some((OneShotMode::Recreate, _)) = one_shot {
+        delete_and_wait(client, &path).await?;
     }
 
-    let manifest = add_owner_to_yaml_manifest(yaml_manifest, &project)?;
-
     let get_request = Request::builder()
         .uri(&path)
         .method("GET")
         .body("".into())
         .unwrap();
 
-    let request;
-    if client.request_text(get_request).await.is_ok() {
-        // update resource
-        request = Request::builder()
-            .uri(format!("{}?{}", &path, FIELD_MANAGER_QUERY_ARG))
-            .method("PATCH")
-            .header("Content-Type", "application/apply-patch+yaml")
-            .body(manifest.into())
-            .unwrap();
-    } else {
-        // create resource
-        let path = Path::new(&path).parent().unwrap();
-        request = Request::builder()
-            .uri(format!(
-                "{}?{}",
-                &path.display().to_string(),
-                FIELD_MANAGER_QUERY_ARG
-            ))
-            .method("POST")
-            .header("Content-Type", "application/yaml")
-            .body(manifest.clone().into())
-            .unwrap();
+    let ownership = field_ownership(yaml_manifest, config)?;
+    // a server side apply that changes nothing doesn't change the resource version either
+    let resource_version_before = match client.request_text(get_request).await {
+        Ok(resource) => Some(resource_version(&resource)),
+        Err(_) => None,
+    };
+    let exists = resource_version_before.is_some();
+
+    if exists && ownership == FieldOwnership::Initial {
+        debug!(
+            "{} only holds initial defaults and exists already, skipping",
+            &path
+        );
+        return Ok(Applied::Skipped);
     }
 
+    // resources are created with a server side apply as well, so the operator is their only
+    // `Apply` field manager and later non-forced applies don't conflict with its own changes
+    let request = Request::builder()
+        .uri(format!(
+            "{}?{}",
+            &path,
+            field_manager_query_args(ownership == FieldOwnership::Authoritative)
+        ))
+        .method("PATCH")
+        .header("Content-Type", "application/apply-patch+yaml")
+        .body(manifest.into())
+        .unwrap();
+
     match client.request_text(request).await {
-        Ok(_) => {
-            if is_one_shot_resource {
-                state.applied_one_shot_resources.insert(path.to_string());
+        Ok(resource) => {
+            if let Some((_, record)) = one_shot {
+                insert_one_shot_record(&mut state.applied_one_shot_resources, record);
             }
-            Ok(())
+
+            if resource_version_before == Some(resource_version(&resource)) {
+                Ok(Applied::Unchanged)
+            } else {
+                Ok(Applied::Changed)
+            }
+        }
+        // a non-forced apply fails as a whole if other field managers own some of its fields
+        Err(kube::Error::Api(e)) if e.code == 409 && e.reason == "Conflict" => {
+            let yaml: Value = serde_yaml::from_str(yaml_manifest)?;
+            let conflict = ProjectFieldConflict {
+                kind: yaml["kind"].as_str().unwrap_or_default().to_string(),
+                namespace: yaml["metadata"]["namespace"].as_str().map(String::from),
+                name: yaml["metadata"]["name"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                message: e.message,
+            };
+            warn!(
+                "not applying {} of project {}: {}",
+                &path, state.name, conflict.message
+            );
+            record_event(
+                client,
+                project,
+                &state.namespaces,
+                EVENT_TYPE_WARNING,
+                REASON_FIELD_CONFLICT,
+                &format!(
+                    "not applying {}: {}",
+                    describe_manifest(yaml_manifest),
+                    conflict.message
+                ),
+            )
+            .await;
+            state.field_conflicts.retain(|c| {
+                (&c.kind, &c.namespace, &c.name)
+                    != (&conflict.kind, &conflict.namespace, &conflict.name)
+            });
+            state.field_conflicts.push(conflict);
+            Ok(Applied::Skipped)
+        }
+        Err(e) => {
+            let message = format!("error applying manifest: {}", e);
+            Err(anyhow::Error::new(e).context(message))
+        }
+    }
+}
+
+// the resource version of a resource, as returned by the api server
+fn resource_version(resource: &str) -> Option<String> {
+    serde_json::from_str::<serde_json::Value>(resource)
+        .ok()?
+        .pointer("/metadata/resourceVersion")?
+        .as_str()
+        .map(String::from)
+}
+
+/// who owns the fields of a resource
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldOwnership {
+    /// the operator: changes of other field managers are overwritten
+    Authoritative,
+    /// the operator and others: changes of other field managers are reported as conflicts
+    Shared,
+    /// the operator only creates the resource, afterwards it belongs to others
+    Initial,
+}
+
+/// the field ownership of a manifest: set with the annotation
+/// `project.selfservice.innoq.io/field-ownership`, defaults to `Shared` if the operator reports
+/// field conflicts and to `Authoritative` otherwise
+pub fn field_ownership(
+    yaml_manifest: &str,
+    config: &ProjectOperatorConfig,
+) -> anyhow::Result<FieldOwnership> {
+    let yaml: serde_yaml::Value = serde_yaml::from_str(yaml_manifest)?;
+
+    match yaml["metadata"]["annotations"][FIELD_OWNERSHIP_ANNOTATION_KEY].as_str() {
+        None if config.report_field_conflicts => Ok(FieldOwnership::Shared),
+        None => Ok(FieldOwnership::Authoritative),
+        Some(FIELD_OWNERSHIP_ANNOTATION_VALUE_AUTHORITATIVE) => Ok(FieldOwnership::Authoritative),
+        Some(FIELD_OWNERSHIP_ANNOTATION_VALUE_INITIAL) => Ok(FieldOwnership::Initial),
+        Some(ownership) => bail!(
+            "unknown value '{}' for annotation '{}' -- allowed are '{}' and '{}'",
+            ownership,
+            FIELD_OWNERSHIP_ANNOTATION_KEY,
+            FIELD_OWNERSHIP_ANNOTATION_VALUE_AUTHORITATIVE,
+            FIELD_OWNERSHIP_ANNOTATION_VALUE_INITIAL
+        ),
+    }
+}
+
+const RECREATE_TIMEOUT: Duration = Duration::from_secs(120);
+
+// deletes a resource (including its dependents, e.g. the pods of a job) and waits until it is gone
+async fn delete_and_wait(client: &kube::Client, path: &str) -> anyhow::Result<()> {
+    let request = Request::builder()
+        .uri(format!("{}?propagationPolicy=Foreground", path))
+        .method("DELETE")
+        .body("".into())
+        .unwrap();
+
+    match client.request_text(request).await {
+        Ok(_) => {}
+        Err(kube::Error::Api(e)) if e.code == 404 => return Ok(()),
+        Err(e) => {
+            let message = format!("error deleting {} to recreate it: {}", path, e);
+            return Err(anyhow::Error::new(e).context(message));
+        }
+    }
+
+    let started = Instant::now();
+    loop {
+        let request = Request::builder()
+            .uri(path)
+            .method("GET")
+            .body("".into())
+            .unwrap();
+        match client.request_text(request).await {
+            Err(kube::Error::Api(e)) if e.code == 404 => return Ok(()),
+            _ => {}
         }
-        Err(e) => bail!("error applying manifest: {}", e.to_string()),
+
+        ensure!(
+            started.elapsed() < RECREATE_TIMEOUT,
+            "{} was not deleted within {} seconds, so it can't be recreated",
+            path,
+            RECREATE_TIMEOUT.as_secs()
+        );
+        tokio::time::sleep(Duration::from_secs(1)).await;
     }
 }
 
@@ -201,27 +771,113 @@ pub fn add_owner_to_yaml_manifest(
 
     let mut yaml: serde_yaml::Value = serde_yaml::from_str(yaml_manifest)?;
 
-    yaml["metadata"]["ownerReferences"] = serde_yaml::Value::Sequence(vec![owner.unwrap()]);
+    // retained resources must not be garbage collected along with the project
+    if !is_retained_resource(yaml_manifest)? {
+        yaml["metadata"]["ownerReferences"] = serde_yaml::Value::Sequence(vec![owner.unwrap()]);
+    }
+
+    if !yaml["metadata"]["labels"].is_mapping() {
+        yaml["metadata"]["labels"] = serde_yaml::Value::Mapping(Default::default());
+    }
+    yaml["metadata"]["labels"][APPLIED_FOR_LABEL_KEY] =
+        serde_yaml::Value::String(project.metadata.name.clone().unwrap_or_default());
 
     let owned_manifest_as_string = serde_yaml::to_string(&yaml)?;
 
     Ok(owned_manifest_as_string)
 }
 
-pub fn is_one_shot_resource(yaml_manifest: &str) -> anyhow::Result<bool> {
+/// splits a data item into the kubernetes objects it contains: it can hold several `---`
+/// separated documents, each of which can be a single object or a `kind: List` of objects --
+/// every object gets applied (and tracked) on its own
+pub fn split_yaml_manifest(yaml_manifest: &str) -> anyhow::Result<Vec<String>> {
+    let mut objects = vec![];
+
+    for document in serde_yaml::Deserializer::from_str(yaml_manifest) {
+        let yaml = Value::deserialize(document).context("error parsing yaml document")?;
+
+        match &yaml {
+            // empty documents, e.g. a leading '---'
+            Value::Null => {}
+            Value::Mapping(_) if yaml["kind"] == Value::String("List".to_string()) => {
+                let items = yaml["items"]
+                    .as_sequence()
+                    .context("manifest of kind 'List' has no 'items'")?;
+                for item in items {
+                    objects.push(serde_yaml::to_string(item)?);
+                }
+            }
+            Value::Mapping(_) => objects.push(serde_yaml::to_string(&yaml)?),
+            _ => bail!(
+                "manifest must be a kubernetes object, got '{}'",
+                crate::project::shorten_string(&serde_yaml::to_string(&yaml)?)
+            ),
+        }
+    }
+
+    Ok(objects)
+}
+
+/// whether a manifest has the deletion policy `retain` (the default is `delete`)
+pub fn is_retained_resource(yaml_manifest: &str) -> anyhow::Result<bool> {
     let yaml: serde_yaml::Value = serde_yaml::from_str(yaml_manifest)?;
 
-    if let serde_yaml::Value::Mapping(annotations) = &yaml["metadata"]["annotations"] {
-        if annotations.get(&Value::String(ONE_SHOT_MANIFEST_ANNOTATION_KEY.to_string()))
-            == Some(&Value::String(
-                ONE_SHOT_MANIFEST_ANNOTATION_VALUE_ONCE.to_string(),
-            ))
-        {
-            return Ok(true);
+    match yaml["metadata"]["annotations"][DELETION_POLICY_ANNOTATION_KEY].as_str() {
+        None | Some(DELETION_POLICY_ANNOTATION_VALUE_DELETE) => Ok(false),
+        Some(DELETION_POLICY_ANNOTATION_VALUE_RETAIN) => Ok(true),
+        Some(policy) => bail!(
+            "unknown value '{}' for annotation '{}' -- allowed are '{}' and '{}'",
+            policy,
+            DELETION_POLICY_ANNOTATION_KEY,
+            DELETION_POLICY_ANNOTATION_VALUE_RETAIN,
+            DELETION_POLICY_ANNOTATION_VALUE_DELETE
+        ),
+    }
+}
+
+/// the namespaces that are deleted along with the project: its own namespaces and the namespaces
+/// among its manifests that are not retained
+pub fn owned_namespaces(
+    project_namespaces: &[String],
+    manifests: &[String],
+) -> anyhow::Result<Vec<String>> {
+    let mut namespaces = project_namespaces.to_vec();
+    for manifest in manifests {
+        let yaml: serde_yaml::Value = serde_yaml::from_str(manifest)?;
+        if yaml["kind"].as_str() == Some("Namespace") && !is_retained_resource(manifest)? {
+            if let Some(name) = yaml["metadata"]["name"].as_str() {
+                namespaces.push(name.to_string());
+            }
         }
     }
 
-    Ok(false)
+    Ok(namespaces)
+}
+
+/// fails for retained resources in namespaces that are deleted along with the project
+/// (`owned_namespaces`) -- they would be deleted in spite of their deletion policy
+pub fn ensure_retainable(yaml_manifest: &str, owned_namespaces: &[String]) -> anyhow::Result<()> {
+    if !is_retained_resource(yaml_manifest)? {
+        return Ok(());
+    }
+
+    let yaml: serde_yaml::Value = serde_yaml::from_str(yaml_manifest)?;
+    if let Some(namespace) = yaml["metadata"]["namespace"].as_str() {
+        ensure!(
+            !owned_namespaces.iter().any(|owned| owned == namespace),
+            "{} has the deletion policy '{}', but namespace '{}' is deleted along with the project",
+            describe_manifest(yaml_manifest),
+            DELETION_POLICY_ANNOTATION_VALUE_RETAIN,
+            namespace
+        );
+    }
+
+    Ok(())
+}
+
+/// whether a manifest is a one shot resource (see `one_shot_mode()`)
+pub fn is_one_shot_resource(yaml_manifest: &str) -> anyhow::Result<bool> {
+    Ok(one_shot_mode(yaml_manifest)?.is_some())
 }
 
 pub async fn resource_path(client: &kube::Client, yaml_manifest: &str) -> anyhow::Result<String> {
@@ -261,7 +917,7 @@ pub async fn resource_path(client: &kube::Client, yaml_manifest: &str) -> anyhow
     let namespace_sub_path = if resource.namespaced {
         ensure!(
             resource_info.metadata.namespace.is_some(),
-            "setting namespace is required: resource {}/{} with name '{}' has no namespace set ... in most cases you want to set it to {{{{ __PROJECT_NAME__ }}}}\nManifest is: {}",
+            "setting namespace is required: resource {}/{} with name '{}' has no namespace set ... in most cases you want to set it to {{{{ __PROJECT_NAMESPACE__ }}}}\nManifest is: {}",
             resource_info.api_version,
             resource_info.kind,
             resource_info.metadata.name.unwrap(),
@@ -0,0 +1,271 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::sync::Arc;
+
+use k8s_openapi::api::networking::v1::{NetworkPolicy, NetworkPolicySpec};
+use k8s_openapi::api::rbac::v1::{
+    ClusterRole, ClusterRoleBinding, PolicyRule, RoleBinding, RoleRef, Subject,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, ObjectMeta, OwnerReference};
+use krator::{Manifest, State, Transition};
+use kube::api::{DeleteParams, Patch, PatchParams};
+use kube::Resource;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::RwLock;
+
+use crate::project::operator::ProjectOperatorState;
+use crate::project::project_status::{
+    ProjectCondition, ProjectStatus, CONDITION_NAMESPACE_READY, CONDITION_READY,
+};
+use crate::project::states::{ApplyManifests, Error, ProjectPhase, ProjectState};
+use crate::project::{Project, ProjectOperatorConfig};
+
+pub const DEFAULT_DENY_NETWORK_POLICY: &str = "selfservice-project-default-deny";
+pub const PROJECT_VIEWER_RESOURCE_PREFIX: &str = "selfservice:project:viewer";
+pub const PROJECT_OWNER_RESOURCE_PREFIX: &str = "selfservice:project:owner";
+
+const FIELD_MANAGER: &str = "self-service-operator";
+
+#[derive(Debug, Default)]
+/// Project is configuring who can see and reach its namespace
+pub struct ConfigureVisibility;
+
+#[async_trait::async_trait]
+impl State<ProjectState> for ConfigureVisibility {
+    async fn next(
+        self: Box<Self>,
+        shared: Arc<RwLock<ProjectOperatorState>>,
+        state: &mut ProjectState,
+        manifest: Manifest<Project>,
+    ) -> Transition<ProjectState> {
+        let shared = shared.read().await;
+        let project = manifest.latest();
+
+        if let Err(e) = configure_visibility(&shared.client, &project, &shared.config).await {
+            state.error = format!(
+                "error configuring visibility of project {}: {}",
+                state.name, e
+            );
+            return Transition::next(self, Error);
+        }
+
+        Transition::next(self, ApplyManifests)
+    }
+
+    async fn status(
+        &self,
+        state: &mut ProjectState,
+        project: &Project,
+    ) -> anyhow::Result<ProjectStatus> {
+        debug!("status() in ConfigureVisibility");
+        Ok(ProjectStatus {
+            phase: Some(ProjectPhase::ConfiguringVisibility),
+            message: Some(format!("configuring visibility of project {}", state.name)),
+            summary: Some(format!("configuring visibility of project {}", state.name)),
+            private: Some(project.spec.private),
+            expires_at: project.expiry_timestamp(),
+            namespaces: state.namespaces_status(),
+            orphaned_namespaces: Some(state.orphaned_namespaces.clone()),
+            conditions: Some(ProjectStatus::progressing_conditions(
+                project,
+                "ConfiguringVisibility",
+                "configuring visibility",
+                &[CONDITION_READY],
+                vec![ProjectCondition::new(
+                    CONDITION_NAMESPACE_READY,
+                    true,
+                    "NamespaceCreated",
+                    "all namespaces exist",
+                )],
+            )),
+            observed_generation: project.metadata.generation,
+            resources: None,
+            inventory: None,
+            retained_resources: None,
+            field_conflicts: None,
+            applied_one_shot_resources: project
+                .status
+                .clone()
+                .unwrap_or_default()
+                .applied_one_shot_resources,
+        })
+    }
+}
+
+// private projects get a network policy that denies all incoming traffic by default -- if a
+// viewer group is configured, non-private projects (and their namespaces) can be viewed by the
+// members of this group. Owners can always get their project.
+//
+// rbac can't restrict `list` and `watch` to single objects, so only `get` is granted on projects
+async fn configure_visibility(
+    client: &kube::Client,
+    project: &Project,
+    config: &ProjectOperatorConfig,
+) -> anyhow::Result<()> {
+    let name = project.metadata.name.clone().unwrap();
+    let viewer_resource_name = format!("{}:{}", PROJECT_VIEWER_RESOURCE_PREFIX, name);
+    let owner_resource_name = format!("{}:{}", PROJECT_OWNER_RESOURCE_PREFIX, name);
+
+    let cluster_role_api = kube::Api::<ClusterRole>::all(client.clone());
+    let cluster_role_binding_api = kube::Api::<ClusterRoleBinding>::all(client.clone());
+
+    let viewer_subjects = config
+        .project_viewer_group
+        .as_deref()
+        .filter(|_| !project.spec.private)
+        .map(|group| {
+            vec![Subject {
+                api_group: Some("rbac.authorization.k8s.io".to_string()),
+                kind: "Group".to_string(),
+                name: group.to_string(),
+                namespace: None,
+            }]
+        });
+
+    for namespace in project.namespaces(config)? {
+        let network_policy_api = kube::Api::<NetworkPolicy>::namespaced(client.clone(), &namespace);
+        let role_binding_api = kube::Api::<RoleBinding>::namespaced(client.clone(), &namespace);
+
+        if project.spec.private {
+            apply(
+                &network_policy_api,
+                &NetworkPolicy {
+                    metadata: owned_metadata(
+                        DEFAULT_DENY_NETWORK_POLICY,
+                        Some(&namespace),
+                        project,
+                    ),
+                    spec: Some(NetworkPolicySpec {
+                        pod_selector: LabelSelector::default(),
+                        policy_types: Some(vec!["Ingress".to_string()]),
+                        ..Default::default()
+                    }),
+                },
+            )
+            .await?;
+        } else {
+            delete(&network_policy_api, DEFAULT_DENY_NETWORK_POLICY).await?;
+        }
+
+        match &viewer_subjects {
+            Some(subjects) => {
+                apply(
+                    &role_binding_api,
+                    &RoleBinding {
+                        metadata: owned_metadata(
+                            PROJECT_VIEWER_RESOURCE_PREFIX,
+                            Some(&namespace),
+                            project,
+                        ),
+                        role_ref: RoleRef {
+                            api_group: "rbac.authorization.k8s.io".to_string(),
+                            kind: "ClusterRole".to_string(),
+                            name: "view".to_string(),
+                        },
+                        subjects: Some(subjects.clone()),
+                    },
+                )
+                .await?;
+            }
+            None => delete(&role_binding_api, PROJECT_VIEWER_RESOURCE_PREFIX).await?,
+        }
+    }
+
+    let owner_subjects = Some(project.spec.owners.iter().map(Subject::from).collect())
+        .filter(|subjects: &Vec<Subject>| !subjects.is_empty());
+
+    for (resource_name, subjects) in [
+        (viewer_resource_name, viewer_subjects),
+        (owner_resource_name, owner_subjects),
+    ] {
+        match subjects {
+            Some(subjects) => {
+                apply(
+                    &cluster_role_api,
+                    &ClusterRole {
+                        metadata: owned_metadata(&resource_name, None, project),
+                        rules: Some(vec![PolicyRule {
+                            api_groups: Some(vec![Project::group(&()).to_string()]),
+                            resources: Some(vec![Project::plural(&()).to_string()]),
+                            resource_names: Some(vec![name.clone()]),
+                            verbs: vec!["get".to_string()],
+                            ..Default::default()
+                        }]),
+                        ..Default::default()
+                    },
+                )
+                .await?;
+
+                apply(
+                    &cluster_role_binding_api,
+                    &ClusterRoleBinding {
+                        metadata: owned_metadata(&resource_name, None, project),
+                        role_ref: RoleRef {
+                            api_group: "rbac.authorization.k8s.io".to_string(),
+                            kind: "ClusterRole".to_string(),
+                            name: resource_name.clone(),
+                        },
+                        subjects: Some(subjects),
+                    },
+                )
+                .await?;
+            }
+            None => {
+                delete(&cluster_role_binding_api, &resource_name).await?;
+                delete(&cluster_role_api, &resource_name).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn owned_metadata(name: &str, namespace: Option<&str>, project: &Project) -> ObjectMeta {
+    ObjectMeta {
+        name: Some(name.to_string()),
+        namespace: namespace.map(String::from),
+        owner_references: Some(vec![OwnerReference::from(project)]),
+        ..Default::default()
+    }
+}
+
+async fn apply<K>(api: &kube::Api<K>, resource: &K) -> anyhow::Result<()>
+where
+    K: Resource + Clone + Serialize + DeserializeOwned + std::fmt::Debug,
+{
+    let name = resource.meta().name.clone().unwrap();
+    api.patch(
+        &name,
+        &PatchParams::apply(FIELD_MANAGER).force(),
+        &Patch::Apply(resource),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn delete<K>(api: &kube::Api<K>, name: &str) -> anyhow::Result<()>
+where
+    K: Resource + Clone + DeserializeOwned + std::fmt::Debug,
+{
+    match api.delete(name, &DeleteParams::default()).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(e)) if e.code == 404 => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -14,19 +14,30 @@
  * limitations under the License.
  */
 
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use crate::project::operator::ProjectOperatorState;
+use anyhow::ensure;
+use anyhow::Context;
 use k8s_openapi::api::core::v1::Namespace;
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference};
 use krator::{Manifest, State, Transition};
-use kube::api::PostParams;
+use kube::api::{Patch, PatchParams, PostParams};
 use tokio::sync::RwLock;
 
-use crate::project::project_status::ProjectStatus;
+use crate::project::environment::{
+    ensure_namespaces_are_not_renamed, orphaned_namespaces, project_namespaces,
+    ENVIRONMENT_LABEL_KEY, PROJECT_LABEL_KEY,
+};
+use crate::project::events::{
+    record_event, record_namespace_event, EVENT_TYPE_NORMAL, EVENT_TYPE_WARNING,
+    REASON_NAMESPACE_CREATED, REASON_NAMESPACE_ORPHANED,
+};
+use crate::project::project_status::{ProjectStatus, CONDITION_NAMESPACE_READY, CONDITION_READY};
 use crate::project::states::error::Error;
-use crate::project::states::{ApplyManifests, ProjectPhase, ProjectState};
-use crate::project::Project;
+use crate::project::states::{ConfigureVisibility, ProjectPhase, ProjectRevision, ProjectState};
+use crate::project::{Project, ProjectEnvironment};
 
 #[derive(Debug, Default)]
 /// Project is creating a namespace
@@ -40,39 +51,110 @@ impl State<ProjectState> for CreateNamespace {
         state: &mut ProjectState,
         manifest: Manifest<Project>,
     ) -> Transition<ProjectState> {
-        info!("creating namespace {}", &state.name);
+        info!("creating namespaces of project {}", &state.name);
 
-        let api: kube::Api<Namespace> = kube::Api::all(shared.read().await.client.clone());
+        let shared = shared.read().await;
+        let api: kube::Api<Namespace> = kube::Api::all(shared.client.clone());
         let project = manifest.latest();
-        let name = project.clone().metadata.name.unwrap();
-
-        if let Ok(namespace) = api.get(&name).await {
-            if is_owned_by_project(&project, &namespace) {
-                return Transition::next(self, ApplyManifests);
-            } else {
-                state.error = format!(
-                    "namespace '{}' exists but does not belong to project '{}'",
-                    &name, &name
-                );
+        state.revision = Some(ProjectRevision::from(&project));
+
+        let (labels, annotations) = match shared.config.namespace_metadata(&project) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                state.error = e.to_string();
                 return Transition::next(self, Error);
             }
-        }
+        };
 
-        let namespace = Namespace {
-            metadata: ObjectMeta {
-                name: Some(name),
-                owner_references: Some(vec![OwnerReference::from(&project)]),
-                ..Default::default()
-            },
-            ..Default::default()
+        let environments = match project.environments(&shared.config) {
+            Ok(environments) => environments,
+            Err(e) => {
+                state.error = e.to_string();
+                return Transition::next(self, Error);
+            }
+        };
+
+        let existing_namespaces = match project_namespaces(&shared.client, &project).await {
+            Ok(namespaces) => namespaces,
+            Err(e) => {
+                state.error = format!("error reading the namespaces of the project: {}", e);
+                return Transition::next(self, Error);
+            }
         };
 
-        if let Err(e) = api.create(&PostParams::default(), &namespace).await {
-            state.error = format!("error creating namespace {}: {}", state.name, e.to_string());
-            Transition::next(self, Error)
-        } else {
-            Transition::next(self, ApplyManifests)
+        // a renamed namespace would be orphaned and replaced by an empty one -- the admission hook
+        // denies such changes, but it might have been bypassed
+        if let Err(e) = ensure_namespaces_are_not_renamed(&existing_namespaces, &environments) {
+            state.error = e.to_string();
+            return Transition::next(self, Error);
+        }
+
+        let mut created_namespaces = vec![];
+        for environment in environments.iter() {
+            match ensure_namespace(&api, &project, environment, &labels, &annotations).await {
+                Ok(true) => created_namespaces.push(environment.namespace.clone()),
+                Ok(false) => {}
+                Err(e) => {
+                    state.error = e.to_string();
+                    return Transition::next(self, Error);
+                }
+            }
+        }
+
+        state.namespaces = environments
+            .iter()
+            .map(|environment| environment.namespace.clone())
+            .collect();
+
+        for namespace in created_namespaces {
+            let message = format!("created namespace {} for project {}", namespace, state.name);
+            record_event(
+                &shared.client,
+                &project,
+                &state.namespaces,
+                EVENT_TYPE_NORMAL,
+                REASON_NAMESPACE_CREATED,
+                &message,
+            )
+            .await;
+            record_namespace_event(
+                &shared.client,
+                &namespace,
+                EVENT_TYPE_NORMAL,
+                REASON_NAMESPACE_CREATED,
+                &message,
+            )
+            .await;
+        }
+
+        // namespaces of removed environments hold their data, so they are only reported -- they
+        // are deleted along with the project
+        let orphaned_namespaces = orphaned_namespaces(&existing_namespaces, &environments);
+        for namespace in orphaned_namespaces.iter() {
+            if state.orphaned_namespaces.contains(namespace) {
+                continue;
+            }
+
+            warn!(
+                "namespace {} no longer belongs to an environment of project {}",
+                namespace, state.name
+            );
+            record_event(
+                &shared.client,
+                &project,
+                &state.namespaces,
+                EVENT_TYPE_WARNING,
+                REASON_NAMESPACE_ORPHANED,
+                &format!(
+                    "namespace {} no longer belongs to an environment of project {} -- it is not deleted automatically, delete it if it is not needed anymore",
+                    namespace, state.name
+                ),
+            )
+            .await;
         }
+        state.orphaned_namespaces = orphaned_namespaces;
+
+        Transition::next(self, ConfigureVisibility)
     }
 
     async fn status(
@@ -83,8 +165,24 @@ impl State<ProjectState> for CreateNamespace {
         debug!("status() in CreateNamespace");
         Ok(ProjectStatus {
             phase: Some(ProjectPhase::CreatingNamespace),
-            message: Some(format!("creating namespace {}", state.name)),
-            summary: Some(format!("creating namespace {}", state.name)),
+            message: Some(format!("creating namespaces of project {}", state.name)),
+            summary: Some(format!("creating namespaces of project {}", state.name)),
+            private: Some(project.spec.private),
+            expires_at: project.expiry_timestamp(),
+            namespaces: state.namespaces_status(),
+            orphaned_namespaces: Some(state.orphaned_namespaces.clone()),
+            conditions: Some(ProjectStatus::progressing_conditions(
+                project,
+                "CreatingNamespace",
+                "creating namespaces",
+                &[CONDITION_NAMESPACE_READY, CONDITION_READY],
+                vec![],
+            )),
+            observed_generation: project.metadata.generation,
+            resources: None,
+            inventory: None,
+            retained_resources: None,
+            field_conflicts: None,
             applied_one_shot_resources: project
                 .status
                 .clone()
@@ -94,6 +192,95 @@ impl State<ProjectState> for CreateNamespace {
     }
 }
 
+// creates the namespace of an environment (if it does not exist yet) and keeps its labels and
+// annotations up to date -- returns whether the namespace was created
+async fn ensure_namespace(
+    api: &kube::Api<Namespace>,
+    project: &Project,
+    environment: &ProjectEnvironment,
+    labels: &BTreeMap<String, String>,
+    annotations: &BTreeMap<String, String>,
+) -> anyhow::Result<bool> {
+    let name = &environment.namespace;
+    let project_name = project.metadata.name.clone().unwrap();
+
+    let mut labels = labels.clone();
+    labels.insert(PROJECT_LABEL_KEY.to_string(), project_name.clone());
+    if let Some(environment) = &environment.name {
+        labels.insert(ENVIRONMENT_LABEL_KEY.to_string(), environment.clone());
+    }
+
+    if let Ok(namespace) = api.get(name).await {
+        ensure!(
+            is_owned_by_project(project, &namespace),
+            "namespace '{}' exists but does not belong to project '{}'",
+            name,
+            project_name
+        );
+
+        apply_namespace_metadata(api, name, labels, annotations.clone())
+            .await
+            .context(format!(
+                "error updating labels and annotations of namespace {}",
+                name
+            ))?;
+
+        return Ok(false);
+    }
+
+    let namespace = Namespace {
+        metadata: ObjectMeta {
+            name: Some(name.clone()),
+            owner_references: Some(vec![OwnerReference::from(project)]),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    api.create(&PostParams::default(), &namespace)
+        .await
+        .context(format!("error creating namespace {}", name))?;
+
+    apply_namespace_metadata(api, name, labels, annotations.clone())
+        .await
+        .context(format!(
+            "error setting labels and annotations of namespace {}",
+            name
+        ))?;
+
+    Ok(true)
+}
+
+// labels and annotations are set with server side apply, so labels and annotations that are
+// removed from the project spec or the operator config are removed from the namespace as well
+async fn apply_namespace_metadata(
+    api: &kube::Api<Namespace>,
+    name: &str,
+    labels: BTreeMap<String, String>,
+    annotations: BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    let namespace = Namespace {
+        metadata: ObjectMeta {
+            name: Some(name.to_string()),
+            labels: Some(labels),
+            annotations: Some(annotations),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    api.patch(
+        name,
+        &PatchParams::apply(NAMESPACE_METADATA_FIELD_MANAGER).force(),
+        &Patch::Apply(&namespace),
+    )
+    .await?;
+
+    Ok(())
+}
+
+const NAMESPACE_METADATA_FIELD_MANAGER: &str = "self-service-operator-namespace-metadata";
+
 pub fn is_owned_by_project<R>(project: &Project, resource: &R) -> bool
 where
     R: kube::Resource + k8s_openapi::Resource,
@@ -23,8 +23,14 @@ use krator::{Manifest, State, Transition};
 use kube::api::{ListParams, WatchEvent};
 use tokio::sync::RwLock;
 
-use crate::project::project_status::ProjectStatus;
-use crate::project::states::{CreateNamespace, ProjectPhase, ProjectState};
+use crate::project::events::{record_event, EVENT_TYPE_WARNING, REASON_RECONCILE_ERROR};
+use crate::project::one_shot::merge_one_shot_records;
+use crate::project::project_status::{
+    ProjectCondition, ProjectStatus, CONDITION_DEGRADED, CONDITION_PROGRESSING, CONDITION_READY,
+    CONDITION_RESOURCES_READY,
+};
+use crate::project::states::wait_for_changes::{handle_expiry, Expiry};
+use crate::project::states::{CreateNamespace, ProjectPhase, ProjectRevision, ProjectState};
 use crate::project::Project;
 
 #[derive(Debug, Default)]
@@ -41,43 +47,102 @@ impl State<ProjectState> for Error {
     ) -> Transition<ProjectState> {
         info!("error {}", &state.name);
 
+        if state.reported_error.as_ref() != Some(&state.error) {
+            record_event(
+                &shared.read().await.client,
+                &manifest.latest(),
+                &state.namespaces,
+                EVENT_TYPE_WARNING,
+                REASON_RECONCILE_ERROR,
+                &state.error,
+            )
+            .await;
+            state.reported_error = Some(state.error.clone());
+        }
+
+        let (client, expiry_warning) = {
+            let shared = shared.read().await;
+            (
+                shared.client.clone(),
+                shared.config.project_expiry_warning(),
+            )
+        };
+
+        // failed projects expire as well
+        let wake_up_in =
+            match handle_expiry(&client, &manifest.latest(), state, expiry_warning).await {
+                Ok(Expiry::Deleted) => return Transition::next(self, Error),
+                Ok(Expiry::WakeUpIn(wake_up_in)) => wake_up_in,
+                Err(e) => {
+                    warn!("error handling the expiry of project {}: {}", state.name, e);
+                    None
+                }
+            };
+
+        // the project changed while it was reconciled
+        if state.revision.as_ref() != Some(&ProjectRevision::from(&manifest.latest())) {
+            info!("project {} modified", state.name);
+            return Transition::next(self, CreateNamespace);
+        }
+
         let lp = &ListParams::default().fields(&format!("metadata.name={}", state.name));
-        let mut stream = kube::Api::<Project>::all(shared.read().await.client.clone())
+        let mut stream = kube::Api::<Project>::all(client.clone())
             .watch(lp, &(manifest.latest().metadata.resource_version.unwrap()))
             .await
             .unwrap()
             .boxed();
 
-        match stream.try_next().await {
-            Ok(Some(status)) => match status.clone() {
-                WatchEvent::Modified(_resource) => {
-                    info!("project {} modified", state.name);
-                    return Transition::next(self, CreateNamespace);
+        let wake_up = async {
+            match wake_up_in.and_then(|duration| duration.to_std().ok()) {
+                Some(wake_up_in) => tokio::time::sleep(wake_up_in).await,
+                None => futures::future::pending().await,
+            }
+        };
+        tokio::pin!(wake_up);
+
+        loop {
+            let next_event = tokio::select! {
+                next_event = stream.try_next() => next_event,
+                _ = &mut wake_up => return Transition::next(self, Error),
+            };
+
+            match next_event {
+                Ok(Some(status)) => match status.clone() {
+                    // status updates (e.g. the operator's own) don't need a reconciliation
+                    WatchEvent::Modified(resource)
+                        if state.revision.as_ref() == Some(&ProjectRevision::from(&resource)) =>
+                    {
+                        continue;
+                    }
+                    WatchEvent::Modified(_resource) => {
+                        info!("project {} modified", state.name);
+                        return Transition::next(self, CreateNamespace);
+                    }
+                    WatchEvent::Error(e) => {
+                        warn!(
+                            "ERROR watching Project with name {}: {}",
+                            state.name, e.message
+                        );
+                    }
+                    _ => debug!(
+                        "unimplemented state while watching for changes on Project with name {}: {:?}",
+                        state.name, status
+                    ),
+                },
+                Err(e) => {
+                    let timeout = Duration::from_secs(60);
+                    state.error = e.to_string();
+                    warn!("error watching stream for new events: {} ... waiting {} seconds in order to avoid log flooding", &state.error, &timeout.as_secs());
+                    tokio::time::sleep(timeout).await;
+                    return Transition::next(self, Error);
                 }
-                WatchEvent::Error(e) => {
-                    warn!(
-                        "ERROR watching Project with name {}: {}",
-                        state.name, e.message
-                    );
+                _ => {
+                    print!("#");
                 }
-                _ => debug!(
-                    "unimplemented state while watching for changes on Project with name {}: {:?}",
-                    state.name, status
-                ),
-            },
-            Err(e) => {
-                let timeout = Duration::from_secs(60);
-                state.error = e.to_string();
-                warn!("error watching stream for new events: {} ... waiting {} seconds in order to avoid log flooding", &state.error, &timeout.as_secs());
-                tokio::time::sleep(timeout).await;
-                return Transition::next(self, Error);
-            }
-            _ => {
-                print!("#");
             }
-        }
 
-        Transition::next(self, Error)
+            return Transition::next(self, Error);
+        }
     }
 
     async fn status(
@@ -87,15 +152,50 @@ impl State<ProjectState> for Error {
     ) -> anyhow::Result<ProjectStatus> {
         debug!("status() in Error");
         let message = format!("error: {}", state.error);
+
+        let mut conditions = vec![
+            ProjectCondition::new(CONDITION_READY, false, "ReconcileError", &state.error),
+            ProjectCondition::new(CONDITION_DEGRADED, true, "ReconcileError", &state.error),
+            ProjectCondition::new(CONDITION_PROGRESSING, false, "ReconcileError", &state.error),
+        ];
+        if let Some(resource) = state.resources.iter().find(|resource| !resource.ready) {
+            conditions.push(ProjectCondition::new(
+                CONDITION_RESOURCES_READY,
+                false,
+                "ResourceNotReady",
+                &format!(
+                    "{} {} is not ready: {}",
+                    resource.kind,
+                    resource.name,
+                    resource.message.clone().unwrap_or_default()
+                ),
+            ));
+        }
+
         Ok(ProjectStatus {
             phase: Some(ProjectPhase::FailedDueToError),
             summary: Some(crate::project::shorten_string(&message)),
             message: Some(message),
-            applied_one_shot_resources: project
-                .status
-                .clone()
-                .unwrap_or_else(ProjectStatus::default)
-                .applied_one_shot_resources,
+            private: Some(project.spec.private),
+            expires_at: project.expiry_timestamp(),
+            namespaces: state.namespaces_status(),
+            orphaned_namespaces: Some(state.orphaned_namespaces.clone()),
+            conditions: Some(ProjectStatus::conditions(project, conditions)),
+            observed_generation: project.metadata.generation,
+            resources: Some(state.resources.clone()),
+            inventory: None,
+            retained_resources: None,
+            field_conflicts: None,
+            // one shot resources that were applied before the reconciliation failed must not be
+            // applied again
+            applied_one_shot_resources: merge_one_shot_records(
+                &project
+                    .status
+                    .clone()
+                    .unwrap_or_default()
+                    .applied_one_shot_resources,
+                &state.applied_one_shot_resources,
+            ),
         })
     }
 }
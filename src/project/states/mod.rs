@@ -15,23 +15,30 @@
  */
 
 use core::clone::Clone;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::{DateTime, Utc};
 
 use krator::ObjectState;
 pub use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 pub(crate) use apply_manifests::ApplyManifests;
+pub(crate) use configure_visibility::ConfigureVisibility;
 pub(crate) use create_namespace::CreateNamespace;
 pub(crate) use error::Error;
 pub(crate) use released::Released;
 pub(crate) use wait_for_changes::WaitForChanges;
 
+use crate::project::drift::DriftWatch;
 use crate::project::operator::ProjectOperatorState;
-pub use crate::project::project_status::ProjectStatus;
+pub use crate::project::project_status::{
+    ProjectFieldConflict, ProjectResourceStatus, ProjectStatus,
+};
 pub use crate::project::{project::DEFAULT_MANIFESTS_SECRET, Project, ProjectSpec};
 
 pub mod apply_manifests;
+pub mod configure_visibility;
 mod create_namespace;
 mod error;
 mod released;
@@ -42,6 +49,7 @@ mod wait_for_changes;
 pub enum ProjectPhase {
     Initializing,
     CreatingNamespace,
+    ConfiguringVisibility,
     SettingUpRBACPermissions,
     ApplyingManifests,
     FailedDueToError,
@@ -52,6 +60,58 @@ pub struct ProjectState {
     pub name: String,
     pub error: String,
     pub applied_one_shot_resources: HashSet<String>,
+    pub expiry_warning_sent: bool,
+    /// the error an event was recorded for -- errors are only reported once
+    pub reported_error: Option<String>,
+    /// namespaces of this project, as computed when they were created
+    pub namespaces: Vec<String>,
+    /// namespaces of this project that no longer belong to one of its environments
+    pub orphaned_namespaces: Vec<String>,
+    /// readiness of the resources applied during the last reconciliation
+    pub resources: Vec<ProjectResourceStatus>,
+    /// api paths of the resources applied during the last reconciliation, see `ProjectStatus`
+    pub inventory: Vec<String>,
+    /// the resources of the last reconciliation with the deletion policy `retain`
+    pub retained_resources: Vec<String>,
+    /// the resources that were not applied because of field conflicts
+    pub field_conflicts: Vec<ProjectFieldConflict>,
+    /// the manifests applied during the last reconciliation by their api path -- they are
+    /// re-applied if their resources drift
+    pub applied_manifests: BTreeMap<String, String>,
+    /// when the project was reconciled successfully the last time
+    pub synced_at: Option<DateTime<Utc>>,
+    /// the revision of the project that was reconciled last
+    pub revision: Option<ProjectRevision>,
+    /// the watches of the applied resources -- they are kept while the project waits for changes
+    pub drift_watch: Option<DriftWatch>,
+}
+
+/// what the reconciliation of a project depends on: its spec (by its generation), its labels and
+/// its annotations -- status updates don't change the revision, so they don't need a
+/// reconciliation
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProjectRevision {
+    generation: Option<i64>,
+    labels: BTreeMap<String, String>,
+    annotations: BTreeMap<String, String>,
+}
+
+impl From<&Project> for ProjectRevision {
+    fn from(project: &Project) -> Self {
+        ProjectRevision {
+            generation: project.metadata.generation,
+            labels: project.metadata.labels.clone().unwrap_or_default(),
+            annotations: project.metadata.annotations.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl ProjectState {
+    /// the namespaces of this project as they are shown in the status -- unknown until they
+    /// were created
+    pub fn namespaces_status(&self) -> Option<Vec<String>> {
+        Some(self.namespaces.clone()).filter(|namespaces| !namespaces.is_empty())
+    }
 }
 
 #[async_trait::async_trait]
@@ -20,10 +20,22 @@ use crate::project::operator::ProjectOperatorState;
 use krator::{Manifest, State, Transition};
 use tokio::sync::RwLock;
 
-use crate::project::project_status::ProjectStatus;
+use crate::project::events::{
+    record_event, EVENT_TYPE_NORMAL, REASON_PROJECT_RELEASED, REASON_RESOURCES_RETAINED,
+};
+use crate::project::project_status::{ProjectCondition, ProjectStatus, CONDITION_READY};
 use crate::project::states::ProjectState;
 use crate::project::Project;
 
+// the resources of the project that survive its deletion
+fn retained_resources(project: &Project) -> Vec<String> {
+    project
+        .status
+        .as_ref()
+        .and_then(|status| status.retained_resources.clone())
+        .unwrap_or_default()
+}
+
 #[derive(Debug, Default)]
 /// Project was released from our care.
 pub struct Released;
@@ -32,11 +44,46 @@ pub struct Released;
 impl State<ProjectState> for Released {
     async fn next(
         self: Box<Self>,
-        _shared: Arc<RwLock<ProjectOperatorState>>,
+        shared: Arc<RwLock<ProjectOperatorState>>,
         state: &mut ProjectState,
-        _manifest: Manifest<Project>,
+        manifest: Manifest<Project>,
     ) -> Transition<ProjectState> {
         debug!("next() in Released / name: {}", state.name);
+        // the project's namespaces are deleted along with the project, so the copy of this event
+        // is only visible until they are gone
+        record_event(
+            &shared.read().await.client,
+            &manifest.latest(),
+            &state.namespaces,
+            EVENT_TYPE_NORMAL,
+            REASON_PROJECT_RELEASED,
+            &format!("project {} was deleted", state.name),
+        )
+        .await;
+
+        // the namespaces are gone soon, so this event is not copied to them
+        let retained = retained_resources(&manifest.latest());
+        if !retained.is_empty() {
+            info!(
+                "project {} was deleted, retaining {}",
+                state.name,
+                retained.join(", ")
+            );
+            record_event(
+                &shared.read().await.client,
+                &manifest.latest(),
+                &[],
+                EVENT_TYPE_NORMAL,
+                REASON_RESOURCES_RETAINED,
+                &format!(
+                    "project {} was deleted, these resources were retained: {}",
+                    state.name,
+                    retained.join(", ")
+                ),
+            )
+            .await;
+        }
+
         Transition::Complete(Ok(()))
     }
 
@@ -45,10 +92,39 @@ impl State<ProjectState> for Released {
         state: &mut ProjectState,
         project: &Project,
     ) -> anyhow::Result<ProjectStatus> {
+        let retained = retained_resources(project);
+        let message = if retained.is_empty() {
+            format!("Bye, {}!", state.name)
+        } else {
+            format!(
+                "Bye, {}! Retained resources: {}",
+                state.name,
+                retained.join(", ")
+            )
+        };
+
         Ok(ProjectStatus {
             phase: None,
-            message: Some(format!("Bye, {}!", state.name)),
-            summary: Some(format!("Bye, {}!", state.name)),
+            summary: Some(crate::project::shorten_string(&message)),
+            message: Some(message),
+            private: Some(project.spec.private),
+            expires_at: project.expiry_timestamp(),
+            namespaces: state.namespaces_status(),
+            orphaned_namespaces: Some(state.orphaned_namespaces.clone()),
+            conditions: Some(ProjectStatus::conditions(
+                project,
+                vec![ProjectCondition::new(
+                    CONDITION_READY,
+                    false,
+                    "Released",
+                    "project was deleted",
+                )],
+            )),
+            observed_generation: project.metadata.generation,
+            resources: None,
+            inventory: None,
+            retained_resources: Some(retained),
+            field_conflicts: None,
             applied_one_shot_resources: project
                 .status
                 .clone()
@@ -16,11 +16,16 @@
 
 use krator::TransitionTo;
 
-use crate::project::states::{ApplyManifests, CreateNamespace, Error, WaitForChanges};
+use crate::project::states::{
+    ApplyManifests, ConfigureVisibility, CreateNamespace, Error, WaitForChanges,
+};
 
-impl TransitionTo<ApplyManifests> for CreateNamespace {}
+impl TransitionTo<ConfigureVisibility> for CreateNamespace {}
 impl TransitionTo<Error> for CreateNamespace {}
 
+impl TransitionTo<ApplyManifests> for ConfigureVisibility {}
+impl TransitionTo<Error> for ConfigureVisibility {}
+
 impl TransitionTo<WaitForChanges> for ApplyManifests {}
 impl TransitionTo<Error> for ApplyManifests {}
 
@@ -16,17 +16,29 @@
 
 use std::sync::Arc;
 
-use crate::project::operator::ProjectOperatorState;
+use anyhow::Context;
+use chrono::{Duration, Utc};
 use futures::{StreamExt, TryStreamExt};
 use krator::{Manifest, State, Transition};
-use kube::api::{ListParams, WatchEvent};
+use kube::api::{DeleteParams, ListParams, WatchEvent};
 use tokio::sync::RwLock;
 
-use crate::project::project_status::ProjectStatus;
+use crate::project::drift::{correct_drift, DriftWatch};
+use crate::project::events::{
+    publish_event, record_event, EVENT_TYPE_NORMAL, EVENT_TYPE_WARNING, REASON_DRIFT_CORRECTED,
+    REASON_PROJECT_EXPIRED, REASON_PROJECT_EXPIRING,
+};
+use crate::project::expiry::{format_countdown, status_countdown};
+use crate::project::one_shot::merge_one_shot_records;
+use crate::project::operator::ProjectOperatorState;
+use crate::project::project_status::{
+    ProjectCondition, ProjectStatus, CONDITION_DEGRADED, CONDITION_MANIFESTS_APPLIED,
+    CONDITION_NAMESPACE_READY, CONDITION_PROGRESSING, CONDITION_READY, CONDITION_RESOURCES_READY,
+};
 use crate::project::states::create_namespace::CreateNamespace;
 use crate::project::states::error::Error;
-use crate::project::states::{ProjectPhase, ProjectState};
-use crate::project::Project;
+use crate::project::states::{ProjectPhase, ProjectRevision, ProjectState};
+use crate::project::{Project, ProjectOperatorConfig};
 
 #[derive(Debug, Default)]
 /// Project is sleeping.
@@ -40,57 +52,331 @@ impl State<ProjectState> for WaitForChanges {
         state: &mut ProjectState,
         manifest: Manifest<Project>,
     ) -> Transition<ProjectState> {
+        let project = manifest.latest();
+        let (client, config, expiry_warning, resync_interval) = {
+            let shared = shared.read().await;
+            (
+                shared.client.clone(),
+                shared.config.clone(),
+                shared.config.project_expiry_warning(),
+                shared.config.resync_interval,
+            )
+        };
+
+        // the project was reconciled successfully: the next error gets reported again
+        state.reported_error = None;
+
+        let wake_up_in = match handle_expiry(&client, &project, state, expiry_warning).await {
+            Ok(Expiry::Deleted) => return Transition::next(self, WaitForChanges),
+            Ok(Expiry::WakeUpIn(wake_up_in)) => wake_up_in,
+            Err(e) => {
+                state.error = e.to_string();
+                return Transition::next(self, Error);
+            }
+        };
+
+        // the countdown in the status is updated whenever it changes
+        let wake_up_in = match project.expiry() {
+            Ok(Some(expires_at)) => {
+                let (_, changes_in) = status_countdown(expires_at - Utc::now());
+                Some(wake_up_in.map_or(changes_in, |wake_up_in| wake_up_in.min(changes_in)))
+            }
+            _ => wake_up_in,
+        };
+
+        // the project changed while it was reconciled
+        if state.revision.as_ref() != Some(&ProjectRevision::from(&project)) {
+            info!("project {} modified", state.name);
+            return Transition::next(self, CreateNamespace);
+        }
+
+        // projects are fully reconciled regularly if a resync interval is configured
+        let wake_up_in = match (resync_interval, state.synced_at) {
+            (Some(resync_interval), Some(synced_at)) => {
+                let resync_in = synced_at + resync_interval - Utc::now();
+                if resync_in <= Duration::zero() {
+                    info!("resyncing project {}", state.name);
+                    return Transition::next(self, CreateNamespace);
+                }
+                Some(wake_up_in.map_or(resync_in, |wake_up_in| wake_up_in.min(resync_in)))
+            }
+            _ => wake_up_in,
+        };
+
+        // the resources that were applied are watched, so they can be restored if they drift --
+        // the watches are kept while the project waits
+        let paths: Vec<String> = state.applied_manifests.keys().cloned().collect();
+        let drift = match &state.drift_watch {
+            Some(drift) if drift.watches(&paths) => drift.clone(),
+            _ => {
+                let drift = DriftWatch::start(&client, &state.name, paths);
+                state.drift_watch = Some(drift.clone());
+                drift
+            }
+        };
+
         let lp = &ListParams::default().fields(&format!("metadata.name={}", state.name));
-        let mut stream = kube::Api::<Project>::all(shared.read().await.client.clone())
-            .watch(lp, &(manifest.latest().metadata.resource_version.unwrap()))
+        let mut stream = kube::Api::<Project>::all(client.clone())
+            .watch(lp, &(project.metadata.resource_version.clone().unwrap()))
             .await
             .unwrap()
             .boxed();
 
-        match stream.try_next().await {
-            Ok(Some(status)) => match status.clone() {
-                WatchEvent::Modified(_resource) => {
-                    info!("project {} modified", state.name);
-                    return Transition::next(self, CreateNamespace);
+        let wake_up = async {
+            match wake_up_in.and_then(|duration| duration.to_std().ok()) {
+                Some(wake_up_in) => tokio::time::sleep(wake_up_in).await,
+                None => futures::future::pending().await,
+            }
+        };
+        tokio::pin!(wake_up);
+
+        loop {
+            let next_event = tokio::select! {
+                next_event = stream.try_next() => next_event,
+                path = drift.next_drifted() => {
+                    if let Err(e) = restore_resource(&client, &path, &project, state, &config).await {
+                        state.error = e.to_string();
+                        return Transition::next(self, Error);
+                    }
+                    return Transition::next(self, WaitForChanges);
+                },
+                _ = &mut wake_up => return Transition::next(self, WaitForChanges),
+            };
+
+            match next_event {
+                Ok(Some(status)) => match status.clone() {
+                    // status updates (e.g. the operator's own) don't need a reconciliation
+                    WatchEvent::Modified(resource)
+                        if state.revision.as_ref() == Some(&ProjectRevision::from(&resource)) =>
+                    {
+                        continue;
+                    }
+                    WatchEvent::Modified(_resource) => {
+                        info!("project {} modified", state.name);
+                        return Transition::next(self, CreateNamespace);
+                    }
+                    WatchEvent::Error(e) => {
+                        warn!(
+                            "ERROR watching Project with name {}: {}",
+                            state.name, e.message
+                        );
+                    }
+                    _ => debug!(
+                        "unimplemented state while watching for changes on Project with name {}: {:?}",
+                        state.name, status
+                    ),
+                },
+                Err(e) => {
+                    state.error = e.to_string();
+                    return Transition::next(self, Error);
                 }
-                WatchEvent::Error(e) => {
-                    warn!(
-                        "ERROR watching Project with name {}: {}",
-                        state.name, e.message
-                    );
+                _ => {
+                    print!("#");
                 }
-                _ => debug!(
-                    "unimplemented state while watching for changes on Project with name {}: {:?}",
-                    state.name, status
-                ),
-            },
-            Err(e) => {
-                state.error = e.to_string();
-                return Transition::next(self, Error);
-            }
-            _ => {
-                print!("#");
             }
-        }
 
-        Transition::next(self, WaitForChanges)
+            return Transition::next(self, WaitForChanges);
+        }
     }
 
     async fn status(
         &self,
-        _state: &mut ProjectState,
+        state: &mut ProjectState,
         project: &Project,
     ) -> anyhow::Result<ProjectStatus> {
         debug!("status() in WaitForChanges");
+        // the status must not change while the project waits, every change of it would be
+        // written -- apart from the countdown to the project's expiry, which only changes every
+        // hour (every minute within the last hour)
+        let (message, summary) = match project.expiry().ok().flatten() {
+            Some(expires_at) => {
+                let (countdown, _) = status_countdown(expires_at - Utc::now());
+                (
+                    format!(
+                        "waiting for changes (project expires in {}, at {})",
+                        countdown,
+                        expires_at.to_rfc3339()
+                    ),
+                    format!("expires in {}", countdown),
+                )
+            }
+            None => (
+                "waiting for changes".to_string(),
+                "waiting for changes".to_string(),
+            ),
+        };
+
         Ok(ProjectStatus {
             phase: Some(ProjectPhase::WaitingForChanges),
-            message: Some("waiting for changes".to_string()),
-            summary: Some("waiting for changes".to_string()),
-            applied_one_shot_resources: project
-                .status
-                .clone()
-                .unwrap_or_else(ProjectStatus::default)
-                .applied_one_shot_resources,
+            message: Some(message),
+            summary: Some(summary),
+            private: Some(project.spec.private),
+            expires_at: project.expiry_timestamp(),
+            namespaces: state.namespaces_status(),
+            orphaned_namespaces: Some(state.orphaned_namespaces.clone()),
+            conditions: Some(ProjectStatus::conditions(
+                project,
+                vec![
+                    ProjectCondition::new(
+                        CONDITION_NAMESPACE_READY,
+                        true,
+                        "NamespaceCreated",
+                        "all namespaces exist",
+                    ),
+                    ProjectCondition::new(
+                        CONDITION_MANIFESTS_APPLIED,
+                        true,
+                        "ManifestsApplied",
+                        "all manifests were applied",
+                    ),
+                    ProjectCondition::new(
+                        CONDITION_RESOURCES_READY,
+                        true,
+                        "ResourcesReady",
+                        "all applied resources are ready",
+                    ),
+                    ProjectCondition::new(CONDITION_READY, true, "Reconciled", "project is ready"),
+                    ProjectCondition::new(
+                        CONDITION_DEGRADED,
+                        false,
+                        "Reconciled",
+                        "project is ready",
+                    ),
+                    ProjectCondition::new(
+                        CONDITION_PROGRESSING,
+                        false,
+                        "Reconciled",
+                        "project is ready",
+                    ),
+                ],
+            )),
+            observed_generation: project.metadata.generation,
+            resources: Some(state.resources.clone()),
+            inventory: Some(state.inventory.clone()),
+            retained_resources: Some(state.retained_resources.clone()),
+            field_conflicts: Some(state.field_conflicts.clone()),
+            applied_one_shot_resources: merge_one_shot_records(
+                &project
+                    .status
+                    .clone()
+                    .unwrap_or_default()
+                    .applied_one_shot_resources,
+                &state.applied_one_shot_resources,
+            ),
         })
     }
 }
+
+/// what became of a project with regard to its expiry
+pub(crate) enum Expiry {
+    /// the project expired and was deleted
+    Deleted,
+    /// the project has to be woken up after this long (if at all) to warn the owners or delete it
+    WakeUpIn(Option<Duration>),
+}
+
+/// deletes a project once it expired and warns its owners `expiry_warning` before -- shared by
+/// all states a project can rest in, so failed projects expire as well
+pub(crate) async fn handle_expiry(
+    client: &kube::Client,
+    project: &Project,
+    state: &mut ProjectState,
+    expiry_warning: Duration,
+) -> anyhow::Result<Expiry> {
+    let expires_at = match project.expiry()? {
+        Some(expires_at) => expires_at,
+        None => return Ok(Expiry::WakeUpIn(None)),
+    };
+
+    let now = Utc::now();
+    if expires_at <= now {
+        info!(
+            "project {} expired at {} -- deleting it",
+            state.name, expires_at
+        );
+        record_event(
+            client,
+            project,
+            &state.namespaces,
+            EVENT_TYPE_NORMAL,
+            REASON_PROJECT_EXPIRED,
+            &format!(
+                "project {} expired at {} and gets deleted",
+                state.name,
+                expires_at.to_rfc3339()
+            ),
+        )
+        .await;
+        kube::Api::<Project>::all(client.clone())
+            .delete(&state.name, &DeleteParams::default())
+            .await
+            .context("error deleting expired project")?;
+        return Ok(Expiry::Deleted);
+    }
+
+    let warn_at = expires_at - expiry_warning;
+    if now < warn_at {
+        state.expiry_warning_sent = false;
+        return Ok(Expiry::WakeUpIn(Some(warn_at - now)));
+    }
+
+    if !state.expiry_warning_sent {
+        let message = format!(
+            "project {} and its namespace will be deleted in {} (at {}) -- update spec.expiresAt or spec.ttl to extend it",
+            state.name,
+            format_countdown(expires_at - now),
+            expires_at.to_rfc3339()
+        );
+        match publish_event(
+            client,
+            project,
+            &state.namespaces,
+            EVENT_TYPE_WARNING,
+            REASON_PROJECT_EXPIRING,
+            &message,
+        )
+        .await
+        {
+            Ok(_) => state.expiry_warning_sent = true,
+            Err(e) => warn!(
+                "error publishing expiry warning for project {}: {}",
+                state.name, e
+            ),
+        }
+    }
+
+    Ok(Expiry::WakeUpIn(Some(expires_at - now)))
+}
+
+// re-applies the manifest of a resource that was changed or deleted and records an event if this
+// restored it
+async fn restore_resource(
+    client: &kube::Client,
+    path: &str,
+    project: &Project,
+    state: &mut ProjectState,
+    config: &ProjectOperatorConfig,
+) -> anyhow::Result<()> {
+    let manifest = match state.applied_manifests.get(path) {
+        Some(manifest) => manifest.clone(),
+        None => return Ok(()),
+    };
+
+    if correct_drift(client, &manifest, project, state, config).await? {
+        info!(
+            "restored drifted resource {} of project {}",
+            path, state.name
+        );
+        record_event(
+            client,
+            project,
+            &state.namespaces,
+            EVENT_TYPE_NORMAL,
+            REASON_DRIFT_CORRECTED,
+            &format!("restored {} which was changed or deleted", path),
+        )
+        .await;
+    }
+
+    Ok(())
+}
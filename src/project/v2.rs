@@ -0,0 +1,67 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! version `v2` of the project resource: owners are always typed and values are always
+//! structured -- the deprecated `manifestValues` string is gone. Apart from that, `v2` has the
+//! same fields as `v1`, so its spec wraps the `v1` spec and only its schema differs
+
+use kube::CustomResource;
+pub use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::project::{ProjectOwner, ProjectStatus};
+
+#[derive(CustomResource, Serialize, Deserialize, PartialEq, Default, Debug, Clone)]
+/// a self service project that will create a namespace per project with the owner having cluster-admin
+/// rights in this namespace
+#[serde(transparent)]
+#[kube(
+    group = "selfservice.innoq.io",
+    version = "v2",
+    kind = "Project",
+    status = "ProjectStatus",
+    shortname = "ssp",
+    printcolumn = r#"
+     {"name":"Owners", "type":"string", "description":"owners of this project", "jsonPath":".spec.owners[*].name"},
+     {"name":"Private", "type":"boolean", "description":"whether the project's namespace is private", "jsonPath":".spec.private"},
+     {"name":"Age", "type":"date", "description":"how old this resource is", "jsonPath":".metadata.creationTimestamp"},
+     {"name":"Expires", "type":"string", "description":"when this project expires", "jsonPath":".status.expiresAt"},
+     {"name":"Phase", "type":"string", "description":"current phase of this resource", "jsonPath":".status.phase"}, {"name":"Status summary", "type":"string", "description":"current status", "jsonPath":".status.summary"}
+  "#
+)]
+pub struct ProjectSpec(pub crate::project::ProjectSpec);
+
+// the schema of the v1 spec -- without `manifestValues` and with typed owners
+impl JsonSchema for ProjectSpec {
+    fn schema_name() -> String {
+        "ProjectSpec".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        let mut schema = crate::project::ProjectSpec::json_schema(gen).into_object();
+
+        let mut owners = gen.subschema_for::<Vec<ProjectOwner>>().into_object();
+        owners.metadata().description = Some(OWNERS_DESCRIPTION.to_string());
+
+        let properties = &mut schema.object().properties;
+        properties.remove("manifestValues");
+        properties.insert("owners".to_string(), owners.into());
+
+        schema.into()
+    }
+}
+
+const OWNERS_DESCRIPTION: &str = "Owners of this project -- they will have cluster-admin rights within the created namespace. Each entry has a `kind` (`User`, `Group` or `ServiceAccount`), a `name` and -- for service accounts -- a `namespace`";
@@ -26,8 +26,9 @@ use serial_test::serial;
 
 use self_service_operators::project::project::{
     COPY_ANNOTATION_BASE, COPY_ANNOTATION_COPY_VALUE, DEFAULT_MANIFESTS_SECRET,
+    SECRET_ANNOTATION_KEY, SECRET_ANNOTATION_VALUE, VALUES_SCHEMA_ANNOTATION_KEY,
 };
-use self_service_operators::project::Project;
+use self_service_operators::project::{OwnerKind, Project, ProjectOperatorConfig, ProjectOwner};
 
 use crate::project;
 
@@ -104,7 +105,7 @@ async fn it_should_fail_if_secret_with_resource_manifests_is_not_available() ->
             assert_eq!(status.code, Some(409));
             assert_eq!(
                 status.message,
-                Some("annotation 'project.selfservice.innoq.io/i-dont-exist.foo: copy' not possible: secret with name 'i-dont-exist' does not exist".to_string())
+                Some(format!("annotation 'project.selfservice.innoq.io/i-dont-exist.foo: copy' not possible: there is no manifest bundle, secret or config map with name 'i-dont-exist' (secrets and config maps need the annotation '{}: {}')", SECRET_ANNOTATION_KEY, SECRET_ANNOTATION_VALUE))
             );
             assert_eq!(status.status, Some("Failure".to_string()));
         }
@@ -301,3 +302,260 @@ async fn it_should_fail_if_manifest_values_is_an_array() -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+#[tokio::test]
+#[serial]
+async fn it_should_render_structured_values() -> anyhow::Result<()> {
+    let (client, operator) = project::before_each().await?;
+
+    project::apply_manifest_secret(
+        &client,
+        DEFAULT_MANIFESTS_SECRET,
+        vec![include_str!("../fixtures/templated-pod.yaml")],
+    )
+    .await?;
+
+    let name = project::random_name("structured-values");
+    let mut project = Project::new(&name, Default::default());
+    let mut values = BTreeMap::new();
+    values.insert("name".to_string(), serde_json::json!("templated-name"));
+    project.spec.values = Some(values);
+
+    let result = operator.admission_hook(project).await;
+
+    match result {
+        AdmissionResult::Allow(_) => {}
+        AdmissionResult::Deny(status) => panic!(
+            "admission hook should pass when all template values are provided via values: {:?}",
+            status.message
+        ),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_should_fail_if_values_do_not_match_the_published_schema() -> anyhow::Result<()> {
+    let (client, operator) = project::before_each().await?;
+
+    project::apply_manifest_secret(
+        &client,
+        DEFAULT_MANIFESTS_SECRET,
+        vec![include_str!("../fixtures/pod.yaml")],
+    )
+    .await?;
+
+    project::annotate_manifest_secret(
+        &client,
+        DEFAULT_MANIFESTS_SECRET,
+        VALUES_SCHEMA_ANNOTATION_KEY,
+        r#"
+type: object
+required: [name]
+properties:
+  name:
+    type: string
+  replicas:
+    type: integer
+"#,
+    )
+    .await?;
+
+    let name = project::random_name("schema-violation");
+    let mut project = Project::new(&name, Default::default());
+    let mut values = BTreeMap::new();
+    values.insert("name".to_string(), serde_json::json!("foo"));
+    values.insert("replicas".to_string(), serde_json::json!("three"));
+    project.spec.values = Some(values);
+
+    let result = operator.admission_hook(project).await;
+
+    match result {
+        AdmissionResult::Deny(status) => {
+            assert_eq!(status.code, Some(409));
+            assert_eq!(status.message, Some(format!("Invalid project spec: values do not match the schema of manifest secret '{}':\n  - /replicas: \"three\" is not of type \"integer\"", DEFAULT_MANIFESTS_SECRET)));
+            assert_eq!(status.status, Some("Failure".to_string()));
+        }
+        _ => panic!("admission hook did not fail even though values did not match the schema"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_should_fail_if_service_account_owner_has_no_namespace() -> anyhow::Result<()> {
+    let (_, operator) = project::before_each().await?;
+
+    let name = project::random_name("invalid-owner");
+    let mut project = Project::new(&name, Default::default());
+    project.spec.owners = vec![ProjectOwner {
+        kind: OwnerKind::ServiceAccount,
+        name: "ci-bot".to_string(),
+        namespace: None,
+    }];
+
+    let result = operator.admission_hook(project).await;
+
+    match result {
+        AdmissionResult::Deny(status) => {
+            assert_eq!(status.code, Some(409));
+            assert_eq!(
+                status.message,
+                Some(
+                    "Invalid project spec: owner 'ci-bot' of kind ServiceAccount must have a namespace"
+                        .to_string()
+                )
+            );
+            assert_eq!(status.status, Some("Failure".to_string()));
+        }
+        _ => panic!("admission hook did not fail even though an owner was invalid"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_should_fail_if_namespace_label_is_not_allowed() -> anyhow::Result<()> {
+    let (_, operator) = project::before_each().await?;
+
+    let name = project::random_name("forbidden-namespace-label");
+    let mut project = Project::new(&name, Default::default());
+    let mut labels = BTreeMap::new();
+    labels.insert(
+        "pod-security.kubernetes.io/enforce".to_string(),
+        "privileged".to_string(),
+    );
+    project.spec.namespace_labels = Some(labels);
+
+    let result = operator.admission_hook(project).await;
+
+    match result {
+        AdmissionResult::Deny(status) => {
+            assert_eq!(status.code, Some(409));
+            assert_eq!(
+                status.message,
+                Some("Invalid project spec: projects are not allowed to set the namespace label 'pod-security.kubernetes.io/enforce' (allowed keys: none)".to_string())
+            );
+            assert_eq!(status.status, Some("Failure".to_string()));
+        }
+        _ => panic!("admission hook did not fail even though a forbidden namespace label was set"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_should_fail_if_ttl_exceeds_maximum() -> anyhow::Result<()> {
+    let (_, operator) = project::before_each_with_config(ProjectOperatorConfig {
+        max_project_ttl: Some(chrono::Duration::days(30)),
+        ..Default::default()
+    })
+    .await?;
+
+    let name = project::random_name("ttl-too-long");
+    let mut project = Project::new(&name, Default::default());
+    project.spec.ttl = Some("5w".to_string());
+
+    let result = operator.admission_hook(project).await;
+
+    match result {
+        AdmissionResult::Deny(status) => {
+            assert_eq!(status.code, Some(409));
+            assert!(
+                status
+                    .message
+                    .unwrap_or_default()
+                    .starts_with("Invalid project spec: projects can't live longer than 30d 0h"),
+                "message should name the maximum ttl"
+            );
+        }
+        _ => panic!("admission hook did not fail even though the ttl exceeded the maximum"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_should_fail_if_environment_name_is_invalid() -> anyhow::Result<()> {
+    let (_, operator) = project::before_each().await?;
+
+    let name = project::random_name("invalid-environment");
+    let mut project = Project::new(&name, Default::default());
+    project.spec.environments = vec!["dev".to_string(), "Prod".to_string()];
+
+    let result = operator.admission_hook(project).await;
+
+    match result {
+        AdmissionResult::Deny(status) => {
+            assert_eq!(status.code, Some(409));
+            assert_eq!(
+                status.message,
+                Some("Invalid project spec: environment 'Prod' must consist of lower case alphanumeric characters or '-' and must start and end with an alphanumeric character".to_string())
+            );
+        }
+        _ => panic!("admission hook did not fail even though an environment name was invalid"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_should_fail_if_manifests_would_be_rejected_by_the_api_server() -> anyhow::Result<()> {
+    let (client, operator) = project::before_each().await?;
+
+    let invalid_pod = r#"
+apiVersion: v1
+kind: Pod
+metadata:
+  name: invalid-pod
+  namespace: {{ __PROJECT_NAMESPACE__ }}
+spec:
+  containers: []
+"#;
+    let missing_namespace_config_map = r#"
+apiVersion: v1
+kind: ConfigMap
+metadata:
+  name: foo
+  namespace: i-dont-exist
+"#;
+    project::apply_manifest_secret(
+        &client,
+        "rejected-manifests",
+        vec![invalid_pod, missing_namespace_config_map],
+    )
+    .await?;
+
+    let name = project::random_name("dry-run");
+    let mut project = Project::new(&name, Default::default());
+    let mut annotations = BTreeMap::new();
+    annotations.insert(
+        format!("{}/rejected-manifests", COPY_ANNOTATION_BASE),
+        COPY_ANNOTATION_COPY_VALUE.to_string(),
+    );
+    project.meta_mut().annotations = Some(annotations);
+
+    match operator.admission_hook(project).await {
+        AdmissionResult::Deny(status) => {
+            let message = status.message.unwrap_or_default();
+            assert!(
+                message.starts_with(
+                    "Invalid project: the api server would reject 1 of its manifests:\n"
+                ),
+                "unexpected message: {}",
+                message
+            );
+            assert!(message.contains("  - ConfigMap i-dont-exist/foo: "));
+            // the project's namespace doesn't exist yet, so its objects can't be checked
+            let (_, unchecked) = message
+                .split_once("\nnot checked, as their namespace doesn't exist yet:\n")
+                .expect("unchecked manifests should be listed");
+            assert!(unchecked
+                .lines()
+                .any(|line| line == format!("  - Pod {}/invalid-pod", name)));
+        }
+        _ => panic!("admission hook did not fail even though the api server rejects manifests"),
+    }
+
+    Ok(())
+}
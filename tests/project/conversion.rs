@@ -0,0 +1,152 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use self_service_operators::project::conversion::{
+    convert, review, ConversionReview, V1_VALUES_ANNOTATION_KEY,
+};
+use self_service_operators::project::{v2, Project, ProjectSpec, Sample};
+
+#[test]
+fn it_converts_v1_projects_to_v2_and_back() -> anyhow::Result<()> {
+    let project = Project::new(
+        "conversion",
+        ProjectSpec {
+            manifest_values: Some("legacy: value\nproject_name: overridden".to_string()),
+            private: true,
+            ttl: Some("7d".to_string()),
+            environments: vec!["dev".to_string(), "prod".to_string()],
+            ..ProjectSpec::sample()
+        },
+    );
+
+    let converted = project.to_v2()?;
+    let values = converted.spec.0.values.clone().unwrap_or_default();
+    assert_eq!(values["legacy"], serde_json::json!("value"));
+    assert_eq!(
+        values["project_name"],
+        serde_json::json!("self-service-project"),
+        "values should win over manifestValues"
+    );
+    assert!(converted
+        .metadata
+        .annotations
+        .clone()
+        .unwrap_or_default()
+        .contains_key(V1_VALUES_ANNOTATION_KEY));
+
+    assert_eq!(converted.to_v1()?, project, "round trip should be lossless");
+
+    Ok(())
+}
+
+#[test]
+fn it_converts_v2_projects_to_v1_and_back() -> anyhow::Result<()> {
+    let project = v2::Project::new(
+        "conversion",
+        v2::ProjectSpec(ProjectSpec {
+            owners: ProjectSpec::sample().owners,
+            values: ProjectSpec::sample().values,
+            expires_at: Some("2021-12-24T18:00:00Z".to_string()),
+            ..Default::default()
+        }),
+    );
+
+    assert_eq!(
+        serde_json::to_value(project.to_v1()?.to_v2()?)?,
+        serde_json::to_value(project)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn it_keeps_values_that_were_changed_in_v2() -> anyhow::Result<()> {
+    let project = Project::new(
+        "conversion",
+        ProjectSpec {
+            manifest_values: Some("legacy: value".to_string()),
+            ..ProjectSpec::sample()
+        },
+    );
+
+    let mut converted = project.to_v2()?;
+    converted
+        .spec
+        .0
+        .values
+        .get_or_insert_with(Default::default)
+        .insert("legacy".to_string(), serde_json::json!("changed"));
+
+    let project = converted.to_v1()?;
+    assert_eq!(project.spec.manifest_values, None);
+    assert_eq!(
+        project.spec.values.unwrap_or_default()["legacy"],
+        serde_json::json!("changed")
+    );
+    assert_eq!(project.metadata.annotations, None);
+
+    Ok(())
+}
+
+#[test]
+fn it_answers_conversion_reviews() -> anyhow::Result<()> {
+    let project = serde_json::to_value(Project::new("conversion", ProjectSpec::sample()))?;
+
+    let conversion_review: ConversionReview = serde_json::from_value(serde_json::json!({
+        "apiVersion": "apiextensions.k8s.io/v1",
+        "kind": "ConversionReview",
+        "request": {
+            "uid": "705ab4f5-6393-11e8-b7cc-42010a800002",
+            "desiredAPIVersion": "selfservice.innoq.io/v2",
+            "objects": [project]
+        }
+    }))?;
+
+    let response = review(conversion_review).response.unwrap();
+    assert_eq!(response.uid, "705ab4f5-6393-11e8-b7cc-42010a800002");
+    assert_eq!(response.result.status, Some("Success".to_string()));
+    assert_eq!(
+        response.converted_objects,
+        vec![convert(project, "selfservice.innoq.io/v2")?]
+    );
+    assert_eq!(
+        response.converted_objects[0]["apiVersion"],
+        serde_json::json!("selfservice.innoq.io/v2")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn it_fails_conversion_reviews_for_unknown_versions() -> anyhow::Result<()> {
+    let project = serde_json::to_value(Project::new("conversion", ProjectSpec::sample()))?;
+
+    let conversion_review: ConversionReview = serde_json::from_value(serde_json::json!({
+        "apiVersion": "apiextensions.k8s.io/v1",
+        "kind": "ConversionReview",
+        "request": {
+            "uid": "705ab4f5-6393-11e8-b7cc-42010a800002",
+            "desiredAPIVersion": "selfservice.innoq.io/v3",
+            "objects": [project]
+        }
+    }))?;
+
+    let response = review(conversion_review).response.unwrap();
+    assert_eq!(response.result.status, Some("Failure".to_string()));
+    assert!(response.converted_objects.is_empty());
+
+    Ok(())
+}
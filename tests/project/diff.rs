@@ -0,0 +1,154 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use serde_json::json;
+use serial_test::serial;
+
+use self_service_operators::project::diff::{comparable, diff_project, unified_diff};
+use self_service_operators::project::project::DEFAULT_MANIFESTS_SECRET;
+use self_service_operators::project::states::ProjectPhase;
+use self_service_operators::project::{Project, ProjectOperatorConfig};
+
+use crate::project;
+
+#[test]
+fn it_produces_no_diff_for_equal_texts() {
+    assert_eq!(unified_diff("a\nb\n", "a\nb\n", "old", "new"), "");
+    assert_eq!(unified_diff("", "", "old", "new"), "");
+}
+
+#[test]
+fn it_diffs_changed_lines_with_context() {
+    let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+    let new = "1\ntwo\n3\n4\n5\n6\n7\n8\n9\n10\neleven\n12\n";
+
+    assert_eq!(
+        unified_diff(old, new, "a (live)", "a (reconciled)"),
+        "--- a (live)\n+++ a (reconciled)\n\
+         @@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n\
+         @@ -8,5 +8,5 @@\n 8\n 9\n 10\n-11\n+eleven\n 12\n"
+    );
+}
+
+#[test]
+fn it_merges_close_changes_into_one_hunk() {
+    let old = "a\nb\nc\nd\ne\n";
+    let new = "A\nb\nc\nd\nE\n";
+
+    assert_eq!(
+        unified_diff(old, new, "old", "new"),
+        "--- old\n+++ new\n@@ -1,5 +1,5 @@\n-a\n+A\n b\n c\n d\n-e\n+E\n"
+    );
+}
+
+#[test]
+fn it_diffs_created_and_deleted_objects() {
+    assert_eq!(
+        unified_diff("", "a\nb\n", "old", "new"),
+        "--- old\n+++ new\n@@ -0,0 +1,2 @@\n+a\n+b\n"
+    );
+    assert_eq!(
+        unified_diff("a\n", "", "old", "new"),
+        "--- old\n+++ new\n@@ -1 +0,0 @@\n-a\n"
+    );
+}
+
+#[test]
+fn it_ignores_fields_maintained_by_the_api_server() {
+    let object = json!({
+        "kind": "ConfigMap",
+        "metadata": {
+            "name": "foo",
+            "uid": "1234",
+            "resourceVersion": "42",
+            "managedFields": [],
+            "creationTimestamp": "2021-01-01T00:00:00Z",
+            "labels": { "app": "foo" }
+        },
+        "data": { "key": "value" },
+        "status": {}
+    });
+
+    assert_eq!(
+        comparable(&object),
+        json!({
+            "kind": "ConfigMap",
+            "metadata": { "name": "foo", "labels": { "app": "foo" } },
+            "data": { "key": "value" }
+        })
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn it_shows_what_a_reconciliation_would_change() -> anyhow::Result<()> {
+    let (client, _operator) = project::before_each().await?;
+
+    let name = project::random_name("diff");
+    project::install_project(&client, &name).await?;
+    project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges).await?;
+
+    let project = kube::Api::<Project>::all(client.clone()).get(&name).await?;
+    let config = ProjectOperatorConfig::default();
+    let diff = diff_project(
+        &client,
+        &project,
+        DEFAULT_MANIFESTS_SECRET,
+        "default",
+        &config,
+    )
+    .await?;
+    assert_eq!(diff, "", "a reconciled project should not differ");
+
+    project::apply_manifest_secret(
+        &client,
+        "extra-manifests",
+        vec![include_str!("../fixtures/templated-pod.yaml")],
+    )
+    .await?;
+
+    let mut changed_project = project.clone();
+    let mut annotations = changed_project
+        .metadata
+        .annotations
+        .clone()
+        .unwrap_or_default();
+    annotations.insert(
+        "project.selfservice.innoq.io/extra-manifests.resource0".to_string(),
+        "copy".to_string(),
+    );
+    changed_project.metadata.annotations = Some(annotations);
+    changed_project.spec.manifest_values = Some("name: other-name".into());
+
+    let diff = diff_project(
+        &client,
+        &changed_project,
+        DEFAULT_MANIFESTS_SECRET,
+        "default",
+        &config,
+    )
+    .await?;
+    assert!(
+        diff.contains(&format!(
+            "+++ /api/v1/namespaces/{}/pods/other-name (reconciled)",
+            name
+        )),
+        "unexpected diff: {}",
+        diff
+    );
+
+    Ok(())
+}
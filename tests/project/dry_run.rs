@@ -0,0 +1,68 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use self_service_operators::project::dry_run::{
+    kinds_defined_by_crds, namespaces_defined_by_manifests,
+};
+
+fn manifests() -> Vec<String> {
+    vec![
+        r#"
+apiVersion: apiextensions.k8s.io/v1
+kind: CustomResourceDefinition
+metadata:
+  name: widgets.example.com
+spec:
+  group: example.com
+  names:
+    kind: Widget
+    plural: widgets
+"#
+        .to_string(),
+        r#"
+apiVersion: v1
+kind: Namespace
+metadata:
+  name: extra
+"#
+        .to_string(),
+        r#"
+apiVersion: example.com/v1
+kind: Widget
+metadata:
+  name: foo
+  namespace: extra
+"#
+        .to_string(),
+    ]
+}
+
+#[test]
+fn it_finds_kinds_defined_by_crds() {
+    assert_eq!(
+        kinds_defined_by_crds(&manifests()),
+        vec![("example.com".to_string(), "Widget".to_string())]
+    );
+    assert!(kinds_defined_by_crds(&[]).is_empty());
+}
+
+#[test]
+fn it_finds_namespaces_defined_by_manifests() {
+    assert_eq!(
+        namespaces_defined_by_manifests(&manifests()),
+        vec!["extra".to_string()]
+    );
+}
@@ -0,0 +1,59 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::BTreeMap;
+
+use self_service_operators::project::label_selector::matches;
+
+fn labels() -> BTreeMap<String, String> {
+    let mut labels = BTreeMap::new();
+    labels.insert("tier".to_string(), "gold".to_string());
+    labels.insert("env".to_string(), "dev".to_string());
+    labels
+}
+
+#[test]
+fn it_matches_equality_based_selectors() -> anyhow::Result<()> {
+    assert!(matches("tier=gold", &labels())?);
+    assert!(matches("tier==gold", &labels())?);
+    assert!(matches("tier = gold, env=dev", &labels())?);
+    assert!(!matches("tier=silver", &labels())?);
+    assert!(matches("tier!=silver", &labels())?);
+    assert!(matches("team!=platform", &labels())?);
+    assert!(!matches("tier=gold,env=prod", &labels())?);
+
+    Ok(())
+}
+
+#[test]
+fn it_matches_set_based_selectors() -> anyhow::Result<()> {
+    assert!(matches("env in (dev, staging)", &labels())?);
+    assert!(!matches("env in (prod)", &labels())?);
+    assert!(matches("env notin (prod),tier", &labels())?);
+    assert!(matches("!deprecated", &labels())?);
+    assert!(!matches("!tier", &labels())?);
+    assert!(!matches("team", &labels())?);
+    assert!(matches("", &labels())?);
+
+    Ok(())
+}
+
+#[test]
+fn it_rejects_invalid_selectors() {
+    assert!(matches("env in (dev", &labels()).is_err());
+    assert!(matches("env within (dev)", &labels()).is_err());
+    assert!(matches("=gold", &labels()).is_err());
+}
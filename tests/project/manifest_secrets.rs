@@ -17,18 +17,21 @@
 use core::time::Duration;
 use std::collections::BTreeMap;
 
-use k8s_openapi::api::core::v1::{Namespace, Pod, Secret};
+use k8s_openapi::api::core::v1::{Namespace, Pod, Secret, ServiceAccount};
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
-use kube::api::PostParams;
+use kube::api::{DeleteParams, PostParams};
 use serial_test::serial;
 use tokio::select;
 use tokio::time;
 
 use self_service_operators::project::project::{
-    DEFAULT_MANIFESTS_SECRET, SECRET_ANNOTATION_KEY, SECRET_ANNOTATION_VALUE,
+    DEFAULT_MANIFESTS_SECRET, PROJECT_SELECTOR_ANNOTATION_KEY, SECRET_ANNOTATION_KEY,
+    SECRET_ANNOTATION_VALUE,
+};
+use self_service_operators::project::{operator, source_watch, Sample};
+use self_service_operators::project::{
+    ManifestBundle, ManifestBundleSpec, Project, ProjectBundle, ProjectOperatorConfig, ProjectSpec,
 };
-use self_service_operators::project::{operator, Sample};
-use self_service_operators::project::{Project, ProjectSpec};
 
 use crate::project;
 use crate::project::WaitForState;
@@ -253,6 +256,163 @@ async fn it_should_correctly_copy_annotated_manifests() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+#[serial]
+async fn it_should_apply_changed_manifest_secrets_to_existing_projects() -> anyhow::Result<()> {
+    let (client, _operator) = project::before_each().await?;
+    project::apply_manifest_secret(
+        &client,
+        "extra-manifests",
+        vec![include_str!("../fixtures/templated-pod.yaml")],
+    )
+    .await?;
+
+    tokio::spawn(source_watch::reconcile_on_source_changes(
+        client.clone(),
+        "default".to_string(),
+        DEFAULT_MANIFESTS_SECRET.to_string(),
+        ProjectOperatorConfig::default(),
+    ));
+
+    let name = project::random_name("changed-manifest-secret");
+    let timeout_secs = 20;
+
+    let mut spec = ProjectSpec::sample();
+    spec.manifest_values = Some("name: extra-pod".into());
+
+    let mut annotations = BTreeMap::new();
+    annotations.insert(
+        "project.selfservice.innoq.io/extra-manifests.resource0".to_string(),
+        "copy".to_string(),
+    );
+
+    let project = Project {
+        metadata: ObjectMeta {
+            name: Some(name.clone()),
+            annotations: Some(annotations),
+            ..Default::default()
+        },
+        spec,
+        ..Default::default()
+    };
+
+    let wait_for_pod_created_handle = project::wait_for_state(
+        &kube::Api::<Pod>::namespaced(client.clone(), &name),
+        &"extra-pod".to_string(),
+        WaitForState::Created,
+    );
+
+    let _ = kube::Api::all(client.clone())
+        .create(&PostParams::default(), &project)
+        .await?;
+
+    assert!(
+        select! {
+        res = wait_for_pod_created_handle => res.is_ok(),
+        _ = time::sleep(Duration::from_secs(timeout_secs)) => false
+        },
+        "namespace '{}' should contain a pod called 'extra-pod' after {} seconds",
+        name,
+        timeout_secs
+    );
+
+    let wait_for_sa_created_handle = project::wait_for_state(
+        &kube::Api::<ServiceAccount>::namespaced(client.clone(), &name),
+        &"test-sa".to_string(),
+        WaitForState::Created,
+    );
+
+    project::apply_manifest_secret(
+        &client,
+        "extra-manifests",
+        vec![include_str!("../fixtures/sa.yaml")],
+    )
+    .await?;
+
+    assert!(
+        select! {
+        res = wait_for_sa_created_handle => res.is_ok(),
+        _ = time::sleep(Duration::from_secs(timeout_secs)) => false
+        },
+        "changed manifests should be applied to project '{}' within {} seconds",
+        name,
+        timeout_secs
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_should_correctly_copy_manifests_from_config_maps() -> anyhow::Result<()> {
+    let (client, _operator) = project::before_each().await?;
+    project::apply_manifest_config_map(
+        &client,
+        "config-map-manifests",
+        vec![include_str!("../fixtures/templated-pod.yaml")],
+    )
+    .await?;
+
+    let name = project::random_name("copy-config-map-manifest");
+    let timeout_secs = 20;
+
+    let manifest_values = "name: extra-pod";
+    let mut spec = ProjectSpec::sample();
+    spec.manifest_values = Some(manifest_values.into());
+
+    let mut annotations = BTreeMap::new();
+    annotations.insert(
+        "project.selfservice.innoq.io/config-map-manifests.resource0".to_string(),
+        "copy".to_string(),
+    );
+
+    let project = Project {
+        metadata: ObjectMeta {
+            name: Some(name.clone()),
+            annotations: Some(annotations),
+            ..Default::default()
+        },
+        spec,
+        ..Default::default()
+    };
+
+    let _ = kube::Api::all(client.clone())
+        .create(&PostParams::default(), &project)
+        .await?;
+
+    project::wait_for_state(
+        &kube::Api::<Namespace>::all(client.clone()),
+        &name,
+        WaitForState::Created,
+    )
+    .await?;
+
+    let wait_for_pod_created_handle = project::wait_for_state(
+        &kube::Api::<Pod>::namespaced(client.clone(), &name),
+        &"extra-pod".to_string(),
+        WaitForState::Created,
+    );
+
+    assert!(
+        select! {
+        res = wait_for_pod_created_handle => res.is_ok(),
+        _ = time::sleep(Duration::from_secs(timeout_secs)) => false
+        },
+        "namespace '{}' should contain a pod called 'extra-pod' should be present after {} seconds",
+        name,
+        timeout_secs
+    );
+
+    assert!(
+        project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges)
+            .await
+            .is_ok(),
+        "project should be in waiting state"
+    );
+
+    Ok(())
+}
+
 #[tokio::test]
 #[serial]
 async fn it_should_skip_annotated_manifests() -> anyhow::Result<()> {
@@ -299,7 +459,12 @@ async fn it_should_skip_annotated_manifests() -> anyhow::Result<()> {
     };
 
     let manifests = project
-        .associated_manifests(&client, DEFAULT_MANIFESTS_SECRET, "default")
+        .associated_manifests(
+            &client,
+            DEFAULT_MANIFESTS_SECRET,
+            "default",
+            &ProjectOperatorConfig::default(),
+        )
         .await?;
 
     // println!("{}", manifests[0]);
@@ -307,3 +472,187 @@ async fn it_should_skip_annotated_manifests() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+#[serial]
+async fn it_should_copy_bundles_selected_by_labels_and_spec() -> anyhow::Result<()> {
+    let (client, _operator) = project::before_each().await?;
+    project::apply_manifest_secret(
+        &client,
+        "gold.extras",
+        vec![include_str!("../fixtures/templated-pod.yaml")],
+    )
+    .await?;
+    project::annotate_manifest_secret(
+        &client,
+        "gold.extras",
+        PROJECT_SELECTOR_ANNOTATION_KEY,
+        "tier in (gold, platinum)",
+    )
+    .await?;
+    project::apply_manifest_secret(
+        &client,
+        "dotted.extras",
+        vec![include_str!("../fixtures/sa.yaml")],
+    )
+    .await?;
+
+    let mut spec = ProjectSpec::sample();
+    spec.manifest_values = Some("name: gold-pod".into());
+    spec.bundles = vec![
+        ProjectBundle {
+            name: "dotted.extras".to_string(),
+            ..Default::default()
+        },
+        ProjectBundle {
+            name: DEFAULT_MANIFESTS_SECRET.to_string(),
+            skip: true,
+            ..Default::default()
+        },
+    ];
+
+    let mut labels = BTreeMap::new();
+    labels.insert("tier".to_string(), "gold".to_string());
+
+    let mut project = Project {
+        metadata: ObjectMeta {
+            name: Some(project::random_name("select-bundles")),
+            labels: Some(labels),
+            ..Default::default()
+        },
+        spec,
+        ..Default::default()
+    };
+
+    let manifests = project
+        .associated_manifests(
+            &client,
+            DEFAULT_MANIFESTS_SECRET,
+            "default",
+            &ProjectOperatorConfig::default(),
+        )
+        .await?;
+    assert_eq!(
+        manifests.len(),
+        2,
+        "the service account and the pod of the selected bundle should be copied: {:?}",
+        manifests
+    );
+    assert!(manifests.iter().any(|m| m.contains("gold-pod")));
+
+    project.metadata.labels = None;
+    let manifests = project
+        .associated_manifests(
+            &client,
+            DEFAULT_MANIFESTS_SECRET,
+            "default",
+            &ProjectOperatorConfig::default(),
+        )
+        .await?;
+    assert_eq!(
+        manifests.len(),
+        1,
+        "only the service account should be copied to projects without labels: {:?}",
+        manifests
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_should_copy_manifests_from_manifest_bundles() -> anyhow::Result<()> {
+    let (client, _operator) = project::before_each().await?;
+
+    let bundle_name = project::random_name("bundle");
+    let mut manifests = BTreeMap::new();
+    manifests.insert(
+        "pod".to_string(),
+        include_str!("../fixtures/templated-pod.yaml").to_string(),
+    );
+    let mut default_values = BTreeMap::new();
+    default_values.insert("name".to_string(), serde_json::json!("default-pod"));
+
+    let bundle = ManifestBundle::new(
+        &bundle_name,
+        ManifestBundleSpec {
+            description: Some("a pod".to_string()),
+            manifests,
+            default_values: Some(default_values),
+            values_schema: Some(serde_json::json!({
+                "type": "object",
+                "properties": { "name": { "type": "string" } }
+            })),
+            allowed_projects: Some("tier=gold".to_string()),
+            ..Default::default()
+        },
+    );
+    let api = kube::Api::<ManifestBundle>::all(client.clone());
+    api.create(&PostParams::default(), &bundle).await?;
+
+    let mut spec = ProjectSpec::sample();
+    spec.bundles = vec![ProjectBundle {
+        name: bundle_name.clone(),
+        ..Default::default()
+    }];
+
+    let mut labels = BTreeMap::new();
+    labels.insert("tier".to_string(), "gold".to_string());
+
+    let mut project = Project {
+        metadata: ObjectMeta {
+            name: Some(project::random_name("manifest-bundles")),
+            labels: Some(labels),
+            ..Default::default()
+        },
+        spec,
+        ..Default::default()
+    };
+
+    let manifests = project
+        .associated_manifests(
+            &client,
+            DEFAULT_MANIFESTS_SECRET,
+            "default",
+            &ProjectOperatorConfig::default(),
+        )
+        .await?;
+    assert!(
+        manifests.iter().any(|m| m.contains("default-pod")),
+        "the pod of the bundle should be rendered with its default values: {:?}",
+        manifests
+    );
+
+    project.spec.manifest_values = Some("name: project-pod".into());
+    let manifests = project
+        .associated_manifests(
+            &client,
+            DEFAULT_MANIFESTS_SECRET,
+            "default",
+            &ProjectOperatorConfig::default(),
+        )
+        .await?;
+    assert!(
+        manifests.iter().any(|m| m.contains("project-pod")),
+        "the values of the project should override the default values: {:?}",
+        manifests
+    );
+
+    project.metadata.labels = None;
+    let result = project
+        .associated_manifests(
+            &client,
+            DEFAULT_MANIFESTS_SECRET,
+            "default",
+            &ProjectOperatorConfig::default(),
+        )
+        .await;
+    assert!(
+        result.is_err(),
+        "projects that are not allowed to use the bundle should be rejected"
+    );
+
+    api.delete(&bundle_name, &DeleteParams::default()).await?;
+
+    Ok(())
+}
@@ -22,7 +22,7 @@ use tokio::{select, time};
 
 use futures::{StreamExt, TryStreamExt};
 use k8s_openapi::api::admissionregistration::v1::MutatingWebhookConfiguration;
-use k8s_openapi::api::core::v1::{Namespace, Secret, Service};
+use k8s_openapi::api::core::v1::{ConfigMap, Namespace, Secret, Service};
 use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
 use krator::OperatorRuntime;
@@ -34,20 +34,36 @@ use self_service_operators::project::operator::ProjectOperator;
 use self_service_operators::project::project::{
     DEFAULT_MANIFESTS_SECRET, SECRET_ANNOTATION_KEY, SECRET_ANNOTATION_VALUE,
 };
-use self_service_operators::project::{ProjectSpec, Sample};
+use self_service_operators::project::{ProjectOperatorConfig, ProjectSpec, Sample};
 
 use self_service_operators::project::states::ProjectPhase;
+use self_service_operators::project::ManifestBundle;
 use self_service_operators::project::Project;
 use std::convert::TryFrom;
 
 mod admission_webhook_tests;
+mod conversion;
+mod diff;
+mod dry_run;
+mod label_selector;
 mod manifest_secrets;
+mod one_shot;
 mod operator;
 mod project;
+mod prune;
+mod readiness;
+mod secret_admission;
+mod source_watch;
 mod states;
 mod yaml_manifest_parsing;
 
 pub async fn before_each() -> anyhow::Result<(kube::Client, ProjectOperator)> {
+    before_each_with_config(ProjectOperatorConfig::default()).await
+}
+
+pub async fn before_each_with_config(
+    operator_config: ProjectOperatorConfig,
+) -> anyhow::Result<(kube::Client, ProjectOperator)> {
     let (config, client) = get_client().await?;
 
     assert!(
@@ -78,6 +94,7 @@ pub async fn before_each() -> anyhow::Result<(kube::Client, ProjectOperator)> {
         "default",
         DEFAULT_MANIFESTS_SECRET,
         Duration::from_secs(0),
+        operator_config,
     )
     .await
     .unwrap();
@@ -125,9 +142,21 @@ pub async fn reinstall_self_service_crd(client: &kube::Client) -> anyhow::Result
     }
 
     let wait_for_crd_created = wait_for_state(&api, &name, WaitForState::Created);
-    let crd = self_service_operators::install_crd(&client, &Project::crd()).await?;
+    let crd = self_service_operators::install_crd(
+        client,
+        &self_service_operators::project::conversion::crd(),
+    )
+    .await?;
     let _ = wait_for_crd_created.await?;
 
+    // manifest bundles are not owned by projects, so their crd is only installed once
+    let bundle_crd_name = ManifestBundle::crd().metadata.name.unwrap();
+    if api.get(&bundle_crd_name).await.is_err() {
+        let wait_for_crd_created = wait_for_state(&api, &bundle_crd_name, WaitForState::Created);
+        self_service_operators::install_crd(client, &ManifestBundle::crd()).await?;
+        wait_for_crd_created.await?;
+    }
+
     const NAMESPACE: &str = "default";
     let (service, secret, config) = Project::admission_webhook_resources(NAMESPACE);
 
@@ -477,3 +506,69 @@ pub async fn apply_manifest_secret(
     let _ = wait_for_secret_created_handle.await;
     Ok(())
 }
+
+#[allow(dead_code)] // it's not used by every test and therefore sometimes throws warnings
+pub async fn apply_manifest_config_map(
+    client: &kube::Client,
+    name: &str,
+    manifests: Vec<&str>,
+) -> anyhow::Result<(), anyhow::Error> {
+    let api = kube::Api::<ConfigMap>::namespaced(client.clone(), "default");
+
+    let mut annotations = BTreeMap::new();
+    annotations.insert(
+        SECRET_ANNOTATION_KEY.to_string(),
+        SECRET_ANNOTATION_VALUE.to_string(),
+    );
+
+    let mut items = BTreeMap::new();
+    manifests.iter().enumerate().for_each(|(i, manifest)| {
+        items.insert(format!("resource{}", i), manifest.to_string());
+    });
+
+    api.patch(
+        name,
+        &PatchParams {
+            force: false,
+            field_manager: Some("operator-test".to_string()),
+            ..Default::default()
+        },
+        &Patch::Apply(&ConfigMap {
+            data: Some(items),
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                annotations: Some(annotations),
+                ..Default::default()
+            },
+            ..Default::default()
+        }),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[allow(dead_code)] // it's not used by every test and therefore sometimes throws warnings
+pub async fn annotate_manifest_secret(
+    client: &kube::Client,
+    name: &str,
+    key: &str,
+    value: &str,
+) -> anyhow::Result<(), anyhow::Error> {
+    let api = kube::Api::<Secret>::namespaced(client.clone(), "default");
+
+    api.patch(
+        name,
+        &PatchParams::default(),
+        &Patch::Merge(serde_json::json!({
+            "metadata": {
+                "annotations": {
+                    key: value
+                }
+            }
+        })),
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,180 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashSet;
+
+use self_service_operators::project::one_shot::{
+    content_hash, insert_one_shot_record, merge_one_shot_records, needs_apply, one_shot_mode,
+    one_shot_record, was_reconciled_before, OneShotMode, CREATED_RECORD,
+};
+use self_service_operators::project::project_status::ProjectStatus;
+use self_service_operators::project::{Project, ProjectSpec};
+
+const PATH: &str = "/apis/batch/v1/namespaces/foo/jobs/migrate";
+
+fn job(mode: &str, image: &str) -> String {
+    format!(
+        r#"apiVersion: batch/v1
+kind: Job
+metadata:
+  name: migrate
+  namespace: foo
+  annotations:
+    project.selfservice.innoq.io/apply: {}
+spec:
+  template:
+    spec:
+      containers:
+        - name: migrate
+          image: {}
+"#,
+        mode, image
+    )
+}
+
+#[test]
+fn it_parses_one_shot_modes() -> anyhow::Result<()> {
+    assert_eq!(one_shot_mode(&job("once", "a"))?, Some(OneShotMode::Once));
+    assert_eq!(
+        one_shot_mode(&job("create", "a"))?,
+        Some(OneShotMode::Create)
+    );
+    assert_eq!(
+        one_shot_mode(&job("on-change", "a"))?,
+        Some(OneShotMode::OnChange)
+    );
+    assert_eq!(
+        one_shot_mode(&job("on-generation", "a"))?,
+        Some(OneShotMode::OnGeneration)
+    );
+    assert_eq!(
+        one_shot_mode(&job("recreate", "a"))?,
+        Some(OneShotMode::Recreate)
+    );
+    assert_eq!(
+        one_shot_mode("apiVersion: v1\nkind: Pod\nmetadata:\n  name: a\n")?,
+        None
+    );
+    assert!(one_shot_mode(&job("always", "a")).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn it_hashes_content_stably() {
+    assert_eq!(content_hash(""), "cbf29ce484222325");
+    assert_eq!(content_hash("a"), "af63dc4c8601ec8c");
+    assert_ne!(
+        content_hash(&job("on-change", "a")),
+        content_hash(&job("on-change", "b"))
+    );
+}
+
+#[test]
+fn it_reapplies_changed_resources() {
+    let project = Project::new("xxx", ProjectSpec::default());
+
+    for mode in &[OneShotMode::OnChange, OneShotMode::Recreate] {
+        let applied = one_shot_record(*mode, PATH, &job("on-change", "a"), &project);
+        let records = vec![applied.clone()];
+
+        assert!(needs_apply(*mode, &applied, &[]));
+        assert!(!needs_apply(*mode, &applied, &records));
+
+        let changed = one_shot_record(*mode, PATH, &job("on-change", "b"), &project);
+        assert!(needs_apply(*mode, &changed, &records));
+    }
+
+    // changed `once` resources are not applied again
+    let record = one_shot_record(OneShotMode::Once, PATH, &job("once", "b"), &project);
+    assert!(!needs_apply(
+        OneShotMode::Once,
+        &record,
+        &[PATH.to_string()]
+    ));
+}
+
+#[test]
+fn it_reapplies_resources_when_the_generation_changes() {
+    let mut project = Project::new("xxx", ProjectSpec::default());
+    project.metadata.generation = Some(1);
+
+    let applied = one_shot_record(OneShotMode::OnGeneration, PATH, &job("a", "a"), &project);
+    let records = vec![applied];
+
+    let changed_content =
+        one_shot_record(OneShotMode::OnGeneration, PATH, &job("a", "b"), &project);
+    assert!(!needs_apply(
+        OneShotMode::OnGeneration,
+        &changed_content,
+        &records
+    ));
+
+    project.metadata.generation = Some(2);
+    let record = one_shot_record(OneShotMode::OnGeneration, PATH, &job("a", "a"), &project);
+    assert!(needs_apply(OneShotMode::OnGeneration, &record, &records));
+}
+
+#[test]
+fn it_applies_create_resources_only_before_the_first_reconciliation() {
+    let mut project = Project::new("xxx", ProjectSpec::default());
+    let record = one_shot_record(OneShotMode::Create, PATH, &job("create", "a"), &project);
+    assert_eq!(
+        record,
+        format!("{}#{}", PATH, content_hash(&job("create", "a")))
+    );
+
+    assert!(needs_apply(OneShotMode::Create, &record, &[]));
+    assert!(!needs_apply(
+        OneShotMode::Create,
+        &record,
+        &[CREATED_RECORD.to_string()]
+    ));
+
+    // only the explicit record counts, not the inventory ...
+    project.status = Some(ProjectStatus {
+        inventory: Some(vec![]),
+        ..ProjectStatus::default()
+    });
+    assert!(needs_apply(OneShotMode::Create, &record, &[]));
+
+    // ... which tells whether projects were reconciled by earlier versions
+    assert!(was_reconciled_before(&project));
+    assert!(!was_reconciled_before(&Project::new(
+        "xxx",
+        ProjectSpec::default()
+    )));
+}
+
+#[test]
+fn it_replaces_records_of_reapplied_resources() {
+    let mut applied = HashSet::new();
+    insert_one_shot_record(&mut applied, format!("{}#1", PATH));
+    insert_one_shot_record(&mut applied, format!("{}#2", PATH));
+    assert_eq!(applied.len(), 1);
+
+    let previous = vec![
+        format!("{}#0", PATH),
+        "/api/v1/namespaces/foo/pods/once".to_string(),
+    ];
+    assert_eq!(
+        merge_one_shot_records(&previous, &applied),
+        vec![
+            "/api/v1/namespaces/foo/pods/once".to_string(),
+            format!("{}#2", PATH)
+        ]
+    );
+}
@@ -19,6 +19,7 @@ use std::time::Duration;
 use serial_test::serial;
 
 use self_service_operators::project::operator;
+use self_service_operators::project::ProjectOperatorConfig;
 
 use crate::project;
 
@@ -31,7 +32,8 @@ async fn it_fails_with_non_existant_default_manifests_secret() -> anyhow::Result
 		client.clone(),
 		"default",
 		"non-existant-secret",
-        Duration::from_secs(0)
+        Duration::from_secs(0),
+        ProjectOperatorConfig::default()
 	)
 	.await
 	{
@@ -21,7 +21,13 @@ use kube::api::PostParams;
 use kube::{Resource, ResourceExt};
 use serial_test::serial;
 
-use self_service_operators::project::{Project, ProjectSpec};
+use self_service_operators::project::expiry::{parse_duration, status_countdown};
+use self_service_operators::project::project_status::{
+    CONDITION_DEGRADED, CONDITION_PROGRESSING, CONDITION_READY,
+};
+use self_service_operators::project::{
+    Project, ProjectCondition, ProjectOwner, ProjectSpec, ProjectStatus,
+};
 
 use crate::project;
 use self_service_operators::project::states::ProjectPhase;
@@ -42,8 +48,8 @@ async fn it_is_possible_to_update_project() -> anyhow::Result<()> {
     let mut project = api.get(&name).await?;
     let resource_version = project.resource_version();
     project.spec = ProjectSpec {
-        owners: vec!["newowner@example.com".to_string()],
-        manifest_values: project.spec.manifest_values,
+        owners: vec![ProjectOwner::from("newowner@example.com")],
+        ..project.spec
     };
     let meta = project.meta_mut();
     meta.resource_version = resource_version;
@@ -62,3 +68,138 @@ async fn it_is_possible_to_update_project() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn it_keeps_the_transition_time_of_unchanged_conditions() {
+    let mut project = Project::new("conditions", ProjectSpec::default());
+    let ready = ProjectCondition {
+        last_transition_time: "2021-01-01T00:00:00+00:00".to_string(),
+        ..ProjectCondition::new(CONDITION_READY, true, "Reconciled", "project is ready")
+    };
+    project.status = Some(ProjectStatus {
+        conditions: Some(vec![ready.clone()]),
+        ..Default::default()
+    });
+
+    let conditions = ProjectStatus::conditions(
+        &project,
+        vec![ProjectCondition::new(
+            CONDITION_READY,
+            true,
+            "Reconciled",
+            "still ready",
+        )],
+    );
+    assert_eq!(
+        conditions[0].last_transition_time,
+        ready.last_transition_time
+    );
+    assert_eq!(conditions[0].message, "still ready");
+
+    let conditions = ProjectStatus::conditions(
+        &project,
+        vec![
+            ProjectCondition::new(CONDITION_READY, false, "ReconcileError", "boom"),
+            ProjectCondition::new(CONDITION_DEGRADED, true, "ReconcileError", "boom"),
+        ],
+    );
+    assert_ne!(
+        conditions[0].last_transition_time,
+        ready.last_transition_time
+    );
+    assert_eq!(conditions[0].status, "False");
+    assert_eq!(conditions[1].type_, CONDITION_DEGRADED);
+}
+
+#[test]
+fn it_only_unsets_ready_for_a_new_generation() {
+    let mut project = Project::new("conditions", ProjectSpec::default());
+    project.metadata.generation = Some(2);
+    project.status = Some(ProjectStatus {
+        conditions: Some(vec![ProjectCondition::new(
+            CONDITION_READY,
+            true,
+            "Reconciled",
+            "project is ready",
+        )]),
+        observed_generation: Some(2),
+        ..Default::default()
+    });
+
+    let condition = |conditions: &[ProjectCondition], type_: &str| {
+        conditions
+            .iter()
+            .find(|condition| condition.type_ == type_)
+            .map(|condition| condition.status.clone())
+    };
+
+    // re-reconciling an unchanged project
+    let conditions = ProjectStatus::progressing_conditions(
+        &project,
+        "ApplyingManifests",
+        "applying configured manifests",
+        &[CONDITION_READY],
+        vec![],
+    );
+    assert_eq!(
+        condition(&conditions, CONDITION_READY),
+        Some("True".to_string())
+    );
+    assert_eq!(
+        condition(&conditions, CONDITION_PROGRESSING),
+        Some("True".to_string())
+    );
+
+    project.metadata.generation = Some(3);
+    let conditions = ProjectStatus::progressing_conditions(
+        &project,
+        "ApplyingManifests",
+        "applying configured manifests",
+        &[CONDITION_READY],
+        vec![],
+    );
+    assert_eq!(
+        condition(&conditions, CONDITION_READY),
+        Some("False".to_string())
+    );
+}
+
+#[test]
+fn it_rejects_ttls_that_are_too_long() {
+    assert_eq!(parse_duration("2w").unwrap(), chrono::Duration::days(14));
+    assert!(parse_duration("10000000000000000s").is_err());
+    assert!(parse_duration("2000000000000000000w").is_err());
+
+    let mut project = Project::new("xxx", ProjectSpec::default());
+    project.spec.ttl = Some("7d".to_string());
+    assert!(project.expiry().unwrap().is_some());
+
+    // fits into a duration, but not into a date
+    project.spec.ttl = Some("100000000w".to_string());
+    assert!(project.expiry().is_err());
+}
+
+#[test]
+fn it_counts_down_to_the_expiry_in_hours_and_minutes() {
+    let (countdown, changes_in) = status_countdown(
+        chrono::Duration::days(2) + chrono::Duration::hours(4) + chrono::Duration::minutes(30),
+    );
+    assert_eq!(countdown, "2d 4h");
+    assert_eq!(
+        changes_in,
+        chrono::Duration::minutes(30) + chrono::Duration::seconds(1)
+    );
+
+    let (countdown, changes_in) =
+        status_countdown(chrono::Duration::hours(5) + chrono::Duration::seconds(20));
+    assert_eq!(countdown, "5h");
+    assert_eq!(changes_in, chrono::Duration::seconds(21));
+
+    let (countdown, changes_in) =
+        status_countdown(chrono::Duration::minutes(35) + chrono::Duration::seconds(10));
+    assert_eq!(countdown, "35m");
+    assert_eq!(changes_in, chrono::Duration::seconds(11));
+
+    let (countdown, _) = status_countdown(chrono::Duration::minutes(-5));
+    assert_eq!(countdown, "0m");
+}
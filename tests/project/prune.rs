@@ -0,0 +1,70 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use serde_json::json;
+
+use self_service_operators::project::prune::{is_prunable, stale_resources};
+use self_service_operators::project::{Project, ProjectSpec};
+
+#[test]
+fn it_finds_resources_that_are_no_longer_rendered() {
+    let previous = vec![
+        "/api/v1/namespaces/foo/pods/a".to_string(),
+        "/api/v1/namespaces/foo/pods/b".to_string(),
+        "/api/v1/namespaces/foo/serviceaccounts/c".to_string(),
+    ];
+    let current = vec![
+        "/api/v1/namespaces/foo/pods/b".to_string(),
+        "/api/v1/namespaces/foo/pods/d".to_string(),
+    ];
+
+    assert_eq!(
+        stale_resources(&previous, &current),
+        vec![
+            "/api/v1/namespaces/foo/pods/a".to_string(),
+            "/api/v1/namespaces/foo/serviceaccounts/c".to_string(),
+        ]
+    );
+    assert!(stale_resources(&[], &current).is_empty());
+}
+
+#[test]
+fn it_only_prunes_resources_owned_by_the_project() {
+    let project = Project::new("foo", ProjectSpec::default());
+    let resource = |owner: &str, annotations: serde_json::Value| {
+        json!({
+            "metadata": {
+                "name": "bar",
+                "annotations": annotations,
+                "ownerReferences": [{ "kind": "Project", "name": owner }]
+            }
+        })
+    };
+
+    assert!(is_prunable(&resource("foo", json!({})), &project));
+    assert!(!is_prunable(&resource("other", json!({})), &project));
+    assert!(!is_prunable(
+        &json!({ "metadata": { "name": "bar" } }),
+        &project
+    ));
+    assert!(!is_prunable(
+        &resource(
+            "foo",
+            json!({ "project.selfservice.innoq.io/prune": "disabled" })
+        ),
+        &project
+    ));
+}
@@ -0,0 +1,96 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use serde_json::json;
+
+use self_service_operators::project::readiness::{readiness, Readiness};
+
+fn with_condition(kind: &str, type_: &str, status: &str) -> serde_json::Value {
+    json!({
+        "kind": kind,
+        "metadata": { "name": "foo", "generation": 1 },
+        "status": {
+            "observedGeneration": 1,
+            "conditions": [{ "type": type_, "status": status, "message": "some message" }]
+        }
+    })
+}
+
+#[test]
+fn it_checks_the_readiness_of_well_known_kinds() {
+    assert_eq!(
+        readiness(&with_condition(
+            "CustomResourceDefinition",
+            "Established",
+            "True"
+        )),
+        Readiness::Ready
+    );
+    assert!(matches!(
+        readiness(&json!({ "kind": "CustomResourceDefinition" })),
+        Readiness::Pending(_)
+    ));
+
+    assert_eq!(
+        readiness(&with_condition("Job", "Complete", "True")),
+        Readiness::Ready
+    );
+    assert_eq!(
+        readiness(&with_condition("Job", "Failed", "True")),
+        Readiness::Failed("job failed: some message".to_string())
+    );
+
+    assert_eq!(
+        readiness(&with_condition("Deployment", "Available", "True")),
+        Readiness::Ready
+    );
+    assert!(matches!(
+        readiness(&with_condition("Deployment", "Available", "False")),
+        Readiness::Pending(_)
+    ));
+
+    let mut outdated_deployment = with_condition("Deployment", "Available", "True");
+    outdated_deployment["metadata"]["generation"] = json!(2);
+    assert!(matches!(
+        readiness(&outdated_deployment),
+        Readiness::Pending(_)
+    ));
+
+    assert_eq!(
+        readiness(&json!({ "kind": "Pod", "status": { "phase": "Succeeded" } })),
+        Readiness::Ready
+    );
+    assert!(matches!(
+        readiness(&json!({ "kind": "Pod", "status": { "phase": "Failed" } })),
+        Readiness::Failed(_)
+    ));
+}
+
+#[test]
+fn it_checks_the_ready_condition_of_other_kinds() {
+    assert_eq!(
+        readiness(&json!({ "kind": "ServiceAccount", "metadata": { "name": "foo" } })),
+        Readiness::Ready
+    );
+    assert_eq!(
+        readiness(&with_condition("Certificate", "Ready", "True")),
+        Readiness::Ready
+    );
+    assert_eq!(
+        readiness(&with_condition("Certificate", "Ready", "False")),
+        Readiness::Pending("not Ready: some message".to_string())
+    );
+}
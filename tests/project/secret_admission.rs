@@ -0,0 +1,279 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use k8s_openapi::ByteString;
+use kube::api::PostParams;
+use serial_test::serial;
+
+use self_service_operators::project::manifest_source::ManifestSource;
+use self_service_operators::project::project::{
+    DEFAULT_MANIFESTS_SECRET, SECRET_ANNOTATION_KEY, SECRET_ANNOTATION_VALUE,
+};
+use self_service_operators::project::secret_admission::{render_failures, response, RenderFailure};
+use self_service_operators::project::states::ProjectPhase;
+use self_service_operators::project::{
+    ManifestBundle, ManifestBundleSpec, Project, ProjectBundle, ProjectOperatorConfig, ProjectSpec,
+    Sample,
+};
+
+use crate::project;
+
+fn failures() -> Vec<RenderFailure> {
+    vec![
+        RenderFailure {
+            project: "foo".to_string(),
+            error: "error rendering 'resource0':\nvariable 'name' not found".to_string(),
+        },
+        RenderFailure {
+            project: "bar".to_string(),
+            error: "error parsing yaml document".to_string(),
+        },
+    ]
+}
+
+#[test]
+fn it_allows_changes_that_break_no_project() {
+    let response = response("uid".to_string(), "secret 'extra-manifests'", &[], false);
+
+    assert!(response.allowed);
+    assert!(response.status.is_none());
+    assert!(response.warnings.is_empty());
+}
+
+#[test]
+fn it_denies_changes_naming_the_broken_projects() {
+    let response = response(
+        "uid".to_string(),
+        "secret 'extra-manifests'",
+        &failures(),
+        false,
+    );
+
+    assert!(!response.allowed);
+    assert_eq!(response.uid, "uid");
+    let message = response.status.unwrap().message.unwrap();
+    assert!(
+        message.starts_with(
+            "manifest secret 'extra-manifests' breaks the manifests of the projects 'foo', 'bar':"
+        ),
+        "unexpected message: {}",
+        message
+    );
+    assert!(message.contains("  - foo: error rendering 'resource0':\nvariable 'name' not found"));
+}
+
+#[test]
+fn it_only_warns_in_warn_only_mode() {
+    let response = response(
+        "uid".to_string(),
+        "secret 'extra-manifests'",
+        &failures(),
+        true,
+    );
+
+    assert!(response.allowed);
+    assert!(response.status.is_none());
+    assert_eq!(
+        response.warnings,
+        vec![
+            "manifest secret 'extra-manifests' breaks the manifests of project 'foo': error rendering 'resource0': variable 'name' not found".to_string(),
+            "manifest secret 'extra-manifests' breaks the manifests of project 'bar': error parsing yaml document".to_string(),
+        ]
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn it_finds_projects_broken_by_a_changed_manifest_secret() -> anyhow::Result<()> {
+    let (client, _operator) = project::before_each().await?;
+    project::apply_manifest_secret(
+        &client,
+        "extra-manifests",
+        vec![include_str!("../fixtures/templated-pod.yaml")],
+    )
+    .await?;
+
+    let name = project::random_name("secret-admission");
+    let mut spec = ProjectSpec::sample();
+    spec.manifest_values = Some("name: extra-pod".into());
+
+    let mut annotations = BTreeMap::new();
+    annotations.insert(
+        "project.selfservice.innoq.io/extra-manifests.resource0".to_string(),
+        "copy".to_string(),
+    );
+
+    let project = Project {
+        metadata: ObjectMeta {
+            name: Some(name.clone()),
+            annotations: Some(annotations),
+            ..Default::default()
+        },
+        spec,
+        ..Default::default()
+    };
+
+    kube::Api::<Project>::all(client.clone())
+        .create(&PostParams::default(), &project)
+        .await?;
+    project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges).await?;
+
+    let secret = |manifest: &str| {
+        let mut annotations = BTreeMap::new();
+        annotations.insert(
+            SECRET_ANNOTATION_KEY.to_string(),
+            SECRET_ANNOTATION_VALUE.to_string(),
+        );
+        let mut data = BTreeMap::new();
+        data.insert(
+            "resource0".to_string(),
+            ByteString(manifest.as_bytes().to_vec()),
+        );
+
+        Secret {
+            metadata: ObjectMeta {
+                name: Some("extra-manifests".to_string()),
+                annotations: Some(annotations),
+                ..Default::default()
+            },
+            data: Some(data),
+            ..Default::default()
+        }
+    };
+
+    let config = ProjectOperatorConfig::default();
+    let failures = render_failures(
+        &client,
+        "default",
+        DEFAULT_MANIFESTS_SECRET,
+        &config,
+        ManifestSource::from(secret(include_str!("../fixtures/sa.yaml"))),
+    )
+    .await?;
+    assert!(failures.is_empty(), "unexpected failures: {:?}", failures);
+
+    let failures = render_failures(
+        &client,
+        "default",
+        DEFAULT_MANIFESTS_SECRET,
+        &config,
+        ManifestSource::from(secret("name: {{ missing_value }}")),
+    )
+    .await?;
+    assert_eq!(
+        failures
+            .iter()
+            .map(|f| f.project.as_str())
+            .collect::<Vec<_>>(),
+        vec![name.as_str()]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_finds_projects_broken_by_the_templates_of_a_manifest_bundle() -> anyhow::Result<()> {
+    let (client, _operator) = project::before_each().await?;
+    let templates_name = project::random_name("bundle-templates");
+    project::apply_manifest_config_map(
+        &client,
+        &templates_name,
+        vec![include_str!("../fixtures/templated-pod.yaml")],
+    )
+    .await?;
+
+    let mut default_values = BTreeMap::new();
+    default_values.insert("name".to_string(), serde_json::json!("bundle-pod"));
+
+    let bundle_name = project::random_name("templated-bundle");
+    kube::Api::<ManifestBundle>::all(client.clone())
+        .create(
+            &PostParams::default(),
+            &ManifestBundle::new(
+                &bundle_name,
+                ManifestBundleSpec {
+                    templates_from: Some(templates_name.clone()),
+                    default_values: Some(default_values),
+                    ..Default::default()
+                },
+            ),
+        )
+        .await?;
+
+    let name = project::random_name("bundle-admission");
+    let mut spec = ProjectSpec::sample();
+    spec.bundles = vec![ProjectBundle {
+        name: bundle_name.clone(),
+        ..Default::default()
+    }];
+    kube::Api::<Project>::all(client.clone())
+        .create(&PostParams::default(), &Project::new(&name, spec))
+        .await?;
+
+    let config_map = |manifest: &str| {
+        let mut annotations = BTreeMap::new();
+        annotations.insert(
+            SECRET_ANNOTATION_KEY.to_string(),
+            SECRET_ANNOTATION_VALUE.to_string(),
+        );
+        let mut data = BTreeMap::new();
+        data.insert("resource0".to_string(), manifest.to_string());
+
+        ConfigMap {
+            metadata: ObjectMeta {
+                name: Some(templates_name.clone()),
+                annotations: Some(annotations),
+                ..Default::default()
+            },
+            data: Some(data),
+            ..Default::default()
+        }
+    };
+
+    let config = ProjectOperatorConfig::default();
+    let failures = render_failures(
+        &client,
+        "default",
+        DEFAULT_MANIFESTS_SECRET,
+        &config,
+        ManifestSource::from(config_map(include_str!("../fixtures/sa.yaml"))),
+    )
+    .await?;
+    assert!(failures.is_empty(), "unexpected failures: {:?}", failures);
+
+    let failures = render_failures(
+        &client,
+        "default",
+        DEFAULT_MANIFESTS_SECRET,
+        &config,
+        ManifestSource::from(config_map("name: {{ missing_value }}")),
+    )
+    .await?;
+    assert_eq!(
+        failures
+            .iter()
+            .map(|f| f.project.as_str())
+            .collect::<Vec<_>>(),
+        vec![name.as_str()]
+    );
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use self_service_operators::project::source_watch::SourceVersions;
+
+fn sources(versions: &[(&str, Option<&str>)]) -> Vec<(String, Option<String>)> {
+    versions
+        .iter()
+        .map(|(name, version)| (name.to_string(), version.map(String::from)))
+        .collect()
+}
+
+#[test]
+fn it_ignores_the_initial_list_of_sources() {
+    let mut versions = SourceVersions::default();
+
+    assert!(versions
+        .replace("secret", sources(&[("a", Some("1")), ("b", Some("2"))]))
+        .is_empty());
+    assert!(!versions.update("secret", "a", Some("1".to_string())));
+    assert!(versions.update("secret", "a", Some("3".to_string())));
+}
+
+#[test]
+fn it_detects_changed_and_deleted_sources() {
+    let mut versions = SourceVersions::default();
+    versions.replace("secret", sources(&[("a", Some("1")), ("b", Some("2"))]));
+    versions.replace("config map", sources(&[("a", Some("5"))]));
+
+    assert!(versions.update("secret", "c", Some("4".to_string())));
+    assert!(versions.update("secret", "c", None));
+    assert!(!versions.update("secret", "c", None));
+
+    // a re-list only reports changes of sources of its own kind
+    assert_eq!(
+        versions.replace("secret", sources(&[("a", Some("1")), ("d", Some("6"))])),
+        vec![("secret", "d".to_string()), ("secret", "b".to_string())]
+    );
+    assert!(versions
+        .replace("config map", sources(&[("a", Some("5"))]))
+        .is_empty());
+}
+
+#[test]
+fn it_treats_losing_operator_access_like_a_deletion() {
+    let mut versions = SourceVersions::default();
+    versions.replace("secret", sources(&[("a", Some("1")), ("b", None)]));
+
+    // secrets without the operator-access annotation don't matter until they get it
+    assert!(!versions.update("secret", "b", None));
+    assert!(versions.update("secret", "b", Some("2".to_string())));
+    assert!(versions.update("secret", "a", None));
+}
@@ -17,9 +17,10 @@
 use core::time::Duration;
 use std::collections::BTreeMap;
 
-use k8s_openapi::api::core::v1::{ConfigMap, Pod, PodStatus};
+use k8s_openapi::api::core::v1::{ConfigMap, Event, Pod, PodStatus, ServiceAccount};
+use k8s_openapi::api::rbac::v1::ClusterRole;
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
-use kube::api::{DeleteParams, PostParams};
+use kube::api::{DeleteParams, ListParams, Patch, PatchParams, PostParams};
 use kube::Resource;
 use kube::ResourceExt;
 use log::debug;
@@ -27,8 +28,13 @@ use serial_test::serial;
 use tokio::select;
 use tokio::time;
 
+use self_service_operators::project::events::{
+    REASON_MANIFEST_APPLIED, REASON_NAMESPACE_CREATED, REASON_PROJECT_READY,
+    REASON_RESOURCES_RETAINED,
+};
+use self_service_operators::project::project_status::CONDITION_RESOURCES_READY;
 use self_service_operators::project::Sample;
-use self_service_operators::project::{Project, ProjectSpec};
+use self_service_operators::project::{Project, ProjectOwner, ProjectSpec};
 
 use crate::project;
 use crate::project::{wait_for_state, WaitForState};
@@ -416,8 +422,8 @@ async fn it_should_recreate_deleted_resources() -> anyhow::Result<()> {
     let mut project = api.get(&name).await?;
     let resource_version = project.resource_version();
     project.spec = ProjectSpec {
-        owners: vec!["newowner@example.com".to_string()],
-        manifest_values: project.spec.manifest_values,
+        owners: vec![ProjectOwner::from("newowner@example.com")],
+        ..project.spec
     };
     let meta = project.meta_mut();
     meta.resource_version = resource_version;
@@ -438,3 +444,340 @@ async fn it_should_recreate_deleted_resources() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+// the reasons of the events in `namespace` about the object of the given kind and name
+async fn event_reasons(
+    client: &kube::Client,
+    namespace: &str,
+    kind: &str,
+    name: &str,
+) -> anyhow::Result<Vec<String>> {
+    let fields = format!("involvedObject.kind={},involvedObject.name={}", kind, name);
+
+    Ok(kube::Api::<Event>::namespaced(client.clone(), namespace)
+        .list(&ListParams::default().fields(&fields))
+        .await?
+        .items
+        .into_iter()
+        .filter_map(|event| event.reason)
+        .collect())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_records_events_for_namespace_creation_and_applied_manifests() -> anyhow::Result<()> {
+    let (client, _) = project::before_each().await?;
+
+    let name = project::random_name("events");
+    let _ = project::install_project(&client, &name).await?;
+    project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges).await?;
+
+    // project events are stored in `default`, as projects are cluster scoped ...
+    let project_reasons = event_reasons(&client, "default", "Project", &name).await?;
+    // ... and copied to the project's namespace
+    let namespace_reasons = event_reasons(&client, &name, "Namespace", &name).await?;
+
+    for reason in [REASON_MANIFEST_APPLIED, REASON_PROJECT_READY].iter() {
+        assert!(
+            project_reasons.iter().any(|r| r == reason),
+            "there should be a project event with reason {}: {:?}",
+            reason,
+            project_reasons
+        );
+    }
+
+    for reason in [
+        REASON_NAMESPACE_CREATED,
+        REASON_MANIFEST_APPLIED,
+        REASON_PROJECT_READY,
+    ]
+    .iter()
+    {
+        assert!(
+            namespace_reasons.iter().any(|r| r == reason),
+            "there should be an event with reason {} in the project namespace: {:?}",
+            reason,
+            namespace_reasons
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_does_not_repeat_events_when_nothing_changed() -> anyhow::Result<()> {
+    let (client, _) = project::before_each().await?;
+
+    let name = project::random_name("quiet-events");
+    let _ = project::install_project(&client, &name).await?;
+    project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges).await?;
+
+    let event_counts = || async {
+        let fields = format!("involvedObject.kind=Project,involvedObject.name={}", name);
+        let events = kube::Api::<Event>::namespaced(client.clone(), "default")
+            .list(&ListParams::default().fields(&fields))
+            .await?
+            .items;
+
+        let count = |reason: &str| -> (usize, i32) {
+            let events = events
+                .iter()
+                .filter(|event| event.reason.as_deref() == Some(reason));
+            (
+                events.clone().count(),
+                events.map(|event| event.count.unwrap_or(1)).sum(),
+            )
+        };
+
+        Ok::<_, anyhow::Error>((count(REASON_MANIFEST_APPLIED), count(REASON_PROJECT_READY)))
+    };
+    let (manifest_applied, project_ready) = event_counts().await?;
+
+    // a new annotation re-reconciles the project, but doesn't change its manifests
+    kube::Api::<Project>::all(client.clone())
+        .patch(
+            &name,
+            &PatchParams::default(),
+            &Patch::Merge(serde_json::json!({
+                "metadata": { "annotations": { "example.com/touched": "true" } }
+            })),
+        )
+        .await?;
+    time::sleep(Duration::from_secs(5)).await;
+    project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges).await?;
+
+    assert_eq!(
+        event_counts().await?,
+        (manifest_applied, project_ready),
+        "unchanged resources and a project that stayed ready should not be reported again"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_should_error_when_applied_resources_fail() -> anyhow::Result<()> {
+    let (client, _) = project::before_each().await?;
+
+    let name = project::random_name("failing-resources");
+
+    project::apply_manifest_secret(
+        &client,
+        "extra-manifests",
+        vec![include_str!("../../fixtures/failing-job.yaml")],
+    )
+    .await?;
+
+    let mut annotations = BTreeMap::new();
+    annotations.insert(
+        "project.selfservice.innoq.io/extra-manifests".to_string(),
+        "copy".to_string(),
+    );
+
+    let project = Project {
+        metadata: ObjectMeta {
+            name: Some(name.clone()),
+            annotations: Some(annotations),
+            ..Default::default()
+        },
+        spec: ProjectSpec::sample(),
+        ..Default::default()
+    };
+
+    let api: kube::Api<Project> = kube::Api::all(client.clone());
+    api.create(&PostParams::default(), &project).await?;
+
+    // the job has to be scheduled and fail first
+    let mut status = None;
+    for _ in 0..60 {
+        time::sleep(Duration::from_secs(1)).await;
+        let current_status = api.get(&name).await?.status;
+        if let Some(ProjectPhase::FailedDueToError) =
+            current_status.as_ref().and_then(|s| s.phase.clone())
+        {
+            status = current_status;
+            break;
+        }
+    }
+    let status = status.expect("project should be in error state");
+
+    let job = status
+        .resources
+        .unwrap_or_default()
+        .into_iter()
+        .find(|resource| resource.name == "failing-job")
+        .expect("the status should list the job");
+    assert!(!job.ready, "the job should not be ready: {:?}", job);
+
+    let resources_ready = status
+        .conditions
+        .unwrap_or_default()
+        .into_iter()
+        .find(|condition| condition.type_ == CONDITION_RESOURCES_READY)
+        .expect("there should be a ResourcesReady condition");
+    assert_eq!(resources_ready.status, "False");
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_should_prune_resources_that_are_no_longer_rendered() -> anyhow::Result<()> {
+    let (client, _) = project::before_each().await?;
+
+    let name = project::random_name("prune-resources");
+
+    project::apply_manifest_secret(
+        &client,
+        "extra-manifests",
+        vec![include_str!("../../fixtures/sa.yaml")],
+    )
+    .await?;
+
+    let mut annotations = BTreeMap::new();
+    annotations.insert(
+        "project.selfservice.innoq.io/extra-manifests".to_string(),
+        "copy".to_string(),
+    );
+
+    let project = Project {
+        metadata: ObjectMeta {
+            name: Some(name.clone()),
+            annotations: Some(annotations),
+            ..Default::default()
+        },
+        spec: ProjectSpec::sample(),
+        ..Default::default()
+    };
+
+    let api: kube::Api<Project> = kube::Api::all(client.clone());
+    api.create(&PostParams::default(), &project).await?;
+    project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges).await?;
+
+    let sa_api = kube::Api::<ServiceAccount>::namespaced(client.clone(), &name);
+    let sa_path = format!("/api/v1/namespaces/{}/serviceaccounts/test-sa", name);
+    assert!(sa_api.get("test-sa").await.is_ok());
+    assert!(
+        api.get(&name)
+            .await?
+            .status
+            .and_then(|status| status.inventory)
+            .unwrap_or_default()
+            .contains(&sa_path),
+        "the service account should be part of the inventory"
+    );
+
+    let sa_deleted = wait_for_state(&sa_api, &"test-sa".to_string(), WaitForState::Deleted);
+
+    let mut project = api.get(&name).await?;
+    project.metadata.annotations = None;
+    project.metadata.managed_fields = None;
+    api.replace(&name, &PostParams::default(), &project).await?;
+
+    select! {
+        _ = sa_deleted => {},
+        _ = time::sleep(Duration::from_secs(20)) => panic!("service account should have been pruned"),
+    }
+
+    project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges).await?;
+    assert!(
+        !api.get(&name)
+            .await?
+            .status
+            .and_then(|status| status.inventory)
+            .unwrap_or_default()
+            .contains(&sa_path),
+        "the service account should no longer be part of the inventory"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_should_keep_retained_resources_when_the_project_is_deleted() -> anyhow::Result<()> {
+    let (client, _) = project::before_each().await?;
+
+    let name = project::random_name("retained-resources");
+    let cluster_role_name = format!("{}-audit", name);
+    let timeout_secs = 30;
+
+    project::apply_manifest_secret(
+        &client,
+        "extra-manifests",
+        vec![include_str!("../../fixtures/retained-cluster-role.yaml")],
+    )
+    .await?;
+
+    let mut annotations = BTreeMap::new();
+    annotations.insert(
+        "project.selfservice.innoq.io/extra-manifests".to_string(),
+        "copy".to_string(),
+    );
+
+    let project = Project {
+        metadata: ObjectMeta {
+            name: Some(name.clone()),
+            annotations: Some(annotations),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let api: kube::Api<Project> = kube::Api::all(client.clone());
+    api.create(&PostParams::default(), &project).await?;
+    project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges).await?;
+
+    let project = api.get(&name).await?;
+    let cluster_role_path = format!(
+        "/apis/rbac.authorization.k8s.io/v1/clusterroles/{}",
+        cluster_role_name
+    );
+    assert_eq!(
+        project.status.unwrap().retained_resources,
+        Some(vec![cluster_role_path])
+    );
+
+    let cluster_roles = kube::Api::<ClusterRole>::all(client.clone());
+    assert!(cluster_roles
+        .get(&cluster_role_name)
+        .await?
+        .metadata
+        .owner_references
+        .is_none());
+
+    let wait_for_project_deleted_handle = wait_for_state(&api, &name, WaitForState::Deleted);
+    api.delete(&name, &DeleteParams::default()).await?;
+
+    assert!(
+        select! {
+        res = wait_for_project_deleted_handle => res.is_ok(),
+        _ = time::sleep(Duration::from_secs(timeout_secs)) => false
+        },
+        "project '{}' should be deleted after {} seconds",
+        name,
+        timeout_secs
+    );
+
+    assert!(
+        cluster_roles.get(&cluster_role_name).await.is_ok(),
+        "cluster role '{}' should survive the deletion of the project",
+        cluster_role_name
+    );
+
+    let events = kube::Api::<Event>::namespaced(client.clone(), "default")
+        .list(&ListParams::default())
+        .await?;
+    assert!(events.items.iter().any(|event| {
+        event.reason.as_deref() == Some(REASON_RESOURCES_RETAINED)
+            && event.involved_object.name.as_deref() == Some(name.as_str())
+    }));
+
+    cluster_roles
+        .delete(&cluster_role_name, &DeleteParams::default())
+        .await?;
+
+    Ok(())
+}
@@ -20,12 +20,13 @@
 use core::time::Duration;
 
 use k8s_openapi::api::rbac::v1::{ClusterRole, ClusterRoleBinding, RoleBinding};
+use kube::api::PostParams;
 use kube::Resource;
 use serial_test::serial;
 use tokio::select;
 use tokio::time;
 
-use self_service_operators::project::Project;
+use self_service_operators::project::{OwnerKind, Project, ProjectOwner, ProjectSpec};
 
 use crate::project;
 use crate::project::WaitForState;
@@ -136,7 +137,7 @@ async fn it_should_create_clusterrole_and_clusterrolebinding_for_handling_this_p
         "cluster role binding subject kind should be correct"
     );
     assert_eq!(
-        subject.name, project.spec.owners[0],
+        subject.name, project.spec.owners[0].name,
         "cluster role binding subject name should be correct"
     );
 
@@ -192,13 +193,13 @@ async fn it_creates_rolebinding() -> anyhow::Result<()> {
 
     let subject = &rb.subjects.as_ref().unwrap()[0];
     assert_eq!(
-        subject.name, project.spec.owners[0],
+        subject.name, project.spec.owners[0].name,
         "subject name should be correct"
     );
 
     let subject1 = &rb.subjects.as_ref().unwrap()[1];
     assert_eq!(
-        subject1.name, project.spec.owners[1],
+        subject1.name, project.spec.owners[1].name,
         "subject name should be correct"
     );
 
@@ -227,3 +228,75 @@ async fn it_creates_rolebinding() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+#[serial]
+async fn it_creates_rolebinding_subjects_for_groups_and_service_accounts() -> anyhow::Result<()> {
+    let (client, _) = project::before_each().await?;
+    let timeout_secs = 10;
+    let name = project::random_name("typed-owners-test");
+    let resource_name = "selfservice:project:owner";
+
+    let api = kube::Api::<RoleBinding>::namespaced(client.clone(), name.as_str());
+    let wait_for_rolebinding_created_handle =
+        project::wait_for_state(&api, &resource_name.to_string(), WaitForState::Created);
+
+    let project = Project::new(
+        &name,
+        ProjectSpec {
+            owners: vec![
+                ProjectOwner {
+                    kind: OwnerKind::Group,
+                    name: "developers".to_string(),
+                    namespace: None,
+                },
+                ProjectOwner {
+                    kind: OwnerKind::ServiceAccount,
+                    name: "ci-bot".to_string(),
+                    namespace: Some("ci".to_string()),
+                },
+            ],
+            manifest_values: Some("name: templated-name".to_string()),
+            ..Default::default()
+        },
+    );
+    kube::Api::<Project>::all(client.clone())
+        .create(&PostParams::default(), &project)
+        .await?;
+
+    assert!(
+        select! {
+        res = wait_for_rolebinding_created_handle => res.is_ok(),
+        _ = time::sleep(Duration::from_secs(timeout_secs)) => false
+        },
+        "rolebinding for the owners should be created within {} seconds",
+        timeout_secs
+    );
+
+    let rb = api.get(resource_name).await?;
+    let subjects = rb.subjects.as_ref().unwrap();
+
+    assert_eq!(subjects[0].kind, "Group", "subject kind should be correct");
+    assert_eq!(
+        subjects[0].name, "developers",
+        "subject name should be correct"
+    );
+    assert_eq!(
+        subjects[0].api_group,
+        Some("rbac.authorization.k8s.io".to_string()),
+        "subject api group should be correct"
+    );
+
+    assert_eq!(
+        subjects[1].kind, "ServiceAccount",
+        "subject kind should be correct"
+    );
+    assert_eq!(subjects[1].name, "ci-bot", "subject name should be correct");
+    assert_eq!(
+        subjects[1].namespace,
+        Some("ci".to_string()),
+        "subject namespace should be correct"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,114 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::time::Duration;
+
+use k8s_openapi::api::networking::v1::NetworkPolicy;
+use k8s_openapi::api::rbac::v1::{ClusterRole, RoleBinding};
+use kube::api::PostParams;
+use kube::{Resource, ResourceExt};
+use serial_test::serial;
+use tokio::time;
+
+use self_service_operators::project::states::configure_visibility::{
+    DEFAULT_DENY_NETWORK_POLICY, PROJECT_OWNER_RESOURCE_PREFIX, PROJECT_VIEWER_RESOURCE_PREFIX,
+};
+use self_service_operators::project::states::ProjectPhase;
+use self_service_operators::project::{Project, ProjectOperatorConfig};
+
+use crate::project;
+
+#[tokio::test]
+#[serial]
+async fn it_restricts_access_to_private_projects() -> anyhow::Result<()> {
+    let (client, _) = project::before_each_with_config(ProjectOperatorConfig {
+        project_viewer_group: Some("employees".to_string()),
+        ..Default::default()
+    })
+    .await?;
+
+    let name = project::random_name("visibility");
+    let _ = project::install_project(&client, &name).await?;
+    project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges).await?;
+
+    let network_policy_api: kube::Api<NetworkPolicy> = kube::Api::namespaced(client.clone(), &name);
+    let role_binding_api: kube::Api<RoleBinding> = kube::Api::namespaced(client.clone(), &name);
+
+    assert!(
+        role_binding_api
+            .get(PROJECT_VIEWER_RESOURCE_PREFIX)
+            .await
+            .is_ok(),
+        "viewer group should be able to view a non-private project"
+    );
+    assert!(
+        network_policy_api
+            .get(DEFAULT_DENY_NETWORK_POLICY)
+            .await
+            .is_err(),
+        "non-private project should not have a default deny network policy"
+    );
+
+    let project_api: kube::Api<Project> = kube::Api::all(client.clone());
+    let mut project = project_api.get(&name).await?;
+    let resource_version = project.resource_version();
+    project.spec.private = true;
+
+    let meta = project.meta_mut();
+    meta.resource_version = resource_version;
+    meta.managed_fields = None;
+    project_api
+        .replace(&name, &PostParams::default(), &project)
+        .await?;
+
+    for _ in 0..10 {
+        if network_policy_api
+            .get(DEFAULT_DENY_NETWORK_POLICY)
+            .await
+            .is_ok()
+        {
+            break;
+        }
+        time::sleep(Duration::from_secs(1)).await;
+    }
+
+    let network_policy = network_policy_api.get(DEFAULT_DENY_NETWORK_POLICY).await?;
+    assert!(
+        project::assert_is_owned_by_project(&project_api.get(&name).await?, &network_policy)
+            .is_ok(),
+        "network policy should be owned by project"
+    );
+    assert!(
+        role_binding_api
+            .get(PROJECT_VIEWER_RESOURCE_PREFIX)
+            .await
+            .is_err(),
+        "viewer group should not be able to view a private project"
+    );
+
+    let owner_role = kube::Api::<ClusterRole>::all(client.clone())
+        .get(&format!("{}:{}", PROJECT_OWNER_RESOURCE_PREFIX, name))
+        .await?;
+    let rules = owner_role.rules.unwrap_or_default();
+    assert_eq!(rules[0].resource_names, Some(vec![name.clone()]));
+    assert_eq!(
+        rules[0].verbs,
+        vec!["get".to_string()],
+        "owners should be able to get their private project"
+    );
+
+    Ok(())
+}
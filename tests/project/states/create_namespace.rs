@@ -14,16 +14,20 @@
  * limitations under the License.
  */
 
+use std::collections::BTreeMap;
 use std::time::Duration;
 
 use anyhow::bail;
 use k8s_openapi::api::core::v1::Namespace;
-use kube::api::DeleteParams;
+use kube::api::{DeleteParams, PostParams};
+use kube::{Resource, ResourceExt};
 use serial_test::serial;
 use tokio::select;
 use tokio::time;
 
-use self_service_operators::project::Project;
+use self_service_operators::project::environment::{orphaned_namespaces, renamed_namespaces};
+use self_service_operators::project::states::ProjectPhase;
+use self_service_operators::project::{Project, ProjectOperatorConfig, ProjectSpec, Sample};
 
 use crate::project;
 use crate::project::WaitForState;
@@ -74,3 +78,278 @@ async fn it_creates_and_deletes_namespace() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+#[serial]
+async fn it_sets_and_updates_namespace_labels_and_annotations() -> anyhow::Result<()> {
+    let mut namespace_labels = BTreeMap::new();
+    namespace_labels.insert(
+        "pod-security.kubernetes.io/enforce".to_string(),
+        "baseline".to_string(),
+    );
+    namespace_labels.insert("istio-injection".to_string(), "disabled".to_string());
+
+    let (client, _) = project::before_each_with_config(ProjectOperatorConfig {
+        namespace_labels,
+        allowed_namespace_labels: vec!["istio-injection".to_string(), "cost-center".to_string()],
+        allowed_namespace_annotations: vec!["example.com/*".to_string()],
+        ..Default::default()
+    })
+    .await?;
+
+    let name = project::random_name("namespace-metadata");
+    let _ = project::install_project(&client, &name).await?;
+    project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges).await?;
+
+    let ns_api: kube::Api<Namespace> = kube::Api::all(client.clone());
+    let labels = ns_api.get(&name).await?.metadata.labels.unwrap_or_default();
+    assert_eq!(
+        labels.get("pod-security.kubernetes.io/enforce"),
+        Some(&"baseline".to_string()),
+        "namespace should carry the default labels"
+    );
+
+    let project_api: kube::Api<Project> = kube::Api::all(client.clone());
+    let mut project = project_api.get(&name).await?;
+    let resource_version = project.resource_version();
+
+    let mut project_labels = BTreeMap::new();
+    project_labels.insert("istio-injection".to_string(), "enabled".to_string());
+    project_labels.insert("cost-center".to_string(), "4711".to_string());
+    project.spec.namespace_labels = Some(project_labels);
+
+    let mut project_annotations = BTreeMap::new();
+    project_annotations.insert("example.com/team".to_string(), "a-team".to_string());
+    project.spec.namespace_annotations = Some(project_annotations);
+
+    let meta = project.meta_mut();
+    meta.resource_version = resource_version;
+    meta.managed_fields = None;
+    project_api
+        .replace(&name, &PostParams::default(), &project)
+        .await?;
+
+    let mut namespace = ns_api.get(&name).await?;
+    for _ in 0..10 {
+        if namespace.labels().get("cost-center").is_some() {
+            break;
+        }
+        time::sleep(Duration::from_secs(1)).await;
+        namespace = ns_api.get(&name).await?;
+    }
+
+    let labels = namespace.labels();
+    assert_eq!(
+        labels.get("istio-injection"),
+        Some(&"enabled".to_string()),
+        "project labels should overwrite default labels"
+    );
+    assert_eq!(
+        labels.get("cost-center"),
+        Some(&"4711".to_string()),
+        "project labels should be set on the existing namespace"
+    );
+    assert_eq!(
+        labels.get("pod-security.kubernetes.io/enforce"),
+        Some(&"baseline".to_string()),
+        "default labels should be kept"
+    );
+    assert_eq!(
+        namespace.annotations().get("example.com/team"),
+        Some(&"a-team".to_string()),
+        "project annotations should be set on the existing namespace"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_creates_one_namespace_per_environment() -> anyhow::Result<()> {
+    let (client, _) = project::before_each().await?;
+
+    let name = project::random_name("environments");
+    let _ = project::install_project(&client, &name).await?;
+    project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges).await?;
+
+    let project_api: kube::Api<Project> = kube::Api::all(client.clone());
+    let mut project = project_api.get(&name).await?;
+    project.spec.environments = vec!["dev".to_string(), "prod".to_string()];
+    let meta = project.meta_mut();
+    meta.managed_fields = None;
+
+    // the namespace without environment would be orphaned
+    assert!(
+        project_api
+            .replace(&name, &PostParams::default(), &project)
+            .await
+            .is_err(),
+        "switching to environments should be denied while the namespace without environment exists"
+    );
+
+    let ns_api: kube::Api<Namespace> = kube::Api::all(client.clone());
+    let wait_for_namespace_deleted_handle =
+        project::wait_for_state(&ns_api, &name, WaitForState::Deleted);
+    ns_api.delete(&name, &DeleteParams::default()).await?;
+    wait_for_namespace_deleted_handle.await?;
+
+    let resource_version = project_api.get(&name).await?.resource_version();
+    project.meta_mut().resource_version = resource_version;
+    project_api
+        .replace(&name, &PostParams::default(), &project)
+        .await?;
+
+    let prod_namespace = format!("{}-prod", name);
+    for _ in 0..10 {
+        if ns_api.get(&prod_namespace).await.is_ok() {
+            break;
+        }
+        time::sleep(Duration::from_secs(1)).await;
+    }
+
+    let project = project_api.get(&name).await?;
+    for environment in ["dev", "prod"].iter() {
+        let namespace = ns_api.get(&format!("{}-{}", name, environment)).await?;
+        assert!(
+            project::assert_is_owned_by_project(&project, &namespace).is_ok(),
+            "namespace of environment {} should be owned by project",
+            environment
+        );
+        assert_eq!(
+            namespace
+                .labels()
+                .get("project.selfservice.innoq.io/environment"),
+            Some(&environment.to_string()),
+            "namespace should be labeled with its environment"
+        );
+    }
+
+    project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges).await?;
+
+    // removing an environment would orphan its namespace
+    let mut project = project_api.get(&name).await?;
+    project.spec.environments = vec!["dev".to_string()];
+    project.meta_mut().managed_fields = None;
+    assert!(
+        project_api
+            .replace(&name, &PostParams::default(), &project)
+            .await
+            .is_err(),
+        "removing an environment should be denied while its namespace exists"
+    );
+    assert!(
+        ns_api.get(&prod_namespace).await.is_ok(),
+        "namespace of the environment should be kept"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_names_namespaces_after_the_configured_template() -> anyhow::Result<()> {
+    let (client, _) = project::before_each_with_config(ProjectOperatorConfig {
+        namespace_name_template: Some("sandbox-{{ name }}".to_string()),
+        ..Default::default()
+    })
+    .await?;
+
+    let name = project::random_name("namespace-template");
+    let project_api: kube::Api<Project> = kube::Api::all(client.clone());
+    project_api
+        .create(
+            &PostParams::default(),
+            &Project::new(&name, ProjectSpec::sample()),
+        )
+        .await?;
+    project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges).await?;
+
+    let namespace = format!("sandbox-{}", name);
+    let ns_api: kube::Api<Namespace> = kube::Api::all(client.clone());
+    assert!(
+        ns_api.get(&namespace).await.is_ok(),
+        "namespace should be named after the template"
+    );
+    assert!(
+        ns_api.get(&name).await.is_err(),
+        "no namespace with the project's name should be created"
+    );
+
+    let status = project_api.get(&name).await?.status.unwrap_or_default();
+    assert_eq!(
+        status.namespaces,
+        Some(vec![namespace]),
+        "status should show the actual namespace"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn it_detects_renamed_namespaces() {
+    let config = ProjectOperatorConfig {
+        namespace_name_template: Some("{{ labels.team }}-{{ name }}".to_string()),
+        ..Default::default()
+    };
+
+    let mut labels = BTreeMap::new();
+    labels.insert("team".to_string(), "blue".to_string());
+    let mut project = Project::new("shop", ProjectSpec::default());
+    project.spec.environments = vec!["dev".to_string(), "prod".to_string()];
+    project.metadata.labels = Some(labels.clone());
+
+    let existing = vec![
+        ("blue-shop-dev".to_string(), Some("dev".to_string())),
+        ("blue-shop-prod".to_string(), Some("prod".to_string())),
+        ("blue-shop-test".to_string(), Some("test".to_string())),
+    ];
+
+    // removed environments are not renamed
+    let environments = project.environments(&config).unwrap();
+    assert!(renamed_namespaces(&existing, &environments).is_empty());
+
+    labels.insert("team".to_string(), "red".to_string());
+    project.metadata.labels = Some(labels);
+    let environments = project.environments(&config).unwrap();
+    assert_eq!(
+        renamed_namespaces(&existing, &environments),
+        vec![
+            ("blue-shop-dev".to_string(), "red-shop-dev".to_string()),
+            ("blue-shop-prod".to_string(), "red-shop-prod".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn it_detects_orphaned_namespaces() {
+    let config = ProjectOperatorConfig::default();
+
+    let mut project = Project::new("shop", ProjectSpec::default());
+    project.spec.environments = vec!["dev".to_string(), "prod".to_string()];
+    let environments = project.environments(&config).unwrap();
+
+    // an environment was removed
+    let existing = vec![
+        ("shop-dev".to_string(), Some("dev".to_string())),
+        ("shop-prod".to_string(), Some("prod".to_string())),
+        ("shop-test".to_string(), Some("test".to_string())),
+    ];
+    assert_eq!(
+        orphaned_namespaces(&existing, &environments),
+        vec!["shop-test".to_string()]
+    );
+
+    // a project without environments got environments
+    let existing = vec![("shop".to_string(), None)];
+    assert_eq!(
+        orphaned_namespaces(&existing, &environments),
+        vec!["shop".to_string()]
+    );
+    assert!(renamed_namespaces(&existing, &environments).is_empty());
+
+    let existing = vec![
+        ("shop-dev".to_string(), Some("dev".to_string())),
+        ("shop-prod".to_string(), Some("prod".to_string())),
+    ];
+    assert!(orphaned_namespaces(&existing, &environments).is_empty());
+}
@@ -74,7 +74,7 @@ async fn it_fails_with_correct_error_state_when_invalid_manifests_are_used() ->
 
         let status = &project.status.clone().unwrap();
         last_summary = status.summary.clone().unwrap();
-        if last_summary == *"error: error installing manifest: giving up aft..." {
+        if last_summary == *"error: error installing manifest: api version v..." {
             return Ok(());
         }
     }
@@ -16,5 +16,7 @@
 
 mod apply_manifests;
 mod apply_manifests_check_provided_default_manifests;
+mod configure_visibility;
 mod create_namespace;
 mod error;
+mod wait_for_changes;
@@ -0,0 +1,199 @@
+/*
+ * Copyright 2021 Daniel Bornkessel
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::time::Duration;
+
+use anyhow::bail;
+use chrono::Utc;
+use k8s_openapi::api::core::v1::{Event, Namespace};
+use k8s_openapi::api::rbac::v1::RoleBinding;
+use kube::api::{DeleteParams, ListParams, PostParams};
+use kube::{Resource, ResourceExt};
+use serial_test::serial;
+use tokio::select;
+use tokio::time;
+
+use self_service_operators::project::events::REASON_DRIFT_CORRECTED;
+use self_service_operators::project::project_status::{
+    CONDITION_DEGRADED, CONDITION_MANIFESTS_APPLIED, CONDITION_NAMESPACE_READY, CONDITION_READY,
+};
+use self_service_operators::project::states::{ProjectPhase, ProjectRevision};
+use self_service_operators::project::{Project, ProjectSpec, ProjectStatus};
+
+use crate::project;
+use crate::project::WaitForState;
+
+#[tokio::test]
+#[serial]
+async fn it_deletes_expired_projects() -> anyhow::Result<()> {
+    let timeout_secs = 60;
+    let (client, _) = project::before_each().await?;
+
+    let name = project::random_name("expiry-test");
+    let _ = project::install_project(&client, &name).await?;
+    project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges).await?;
+
+    let project_api: kube::Api<Project> = kube::Api::all(client.clone());
+    let mut project = project_api.get(&name).await?;
+    let resource_version = project.resource_version();
+    project.spec.expires_at = Some((Utc::now() + chrono::Duration::seconds(5)).to_rfc3339());
+
+    let meta = project.meta_mut();
+    meta.resource_version = resource_version;
+    meta.managed_fields = None;
+
+    let wait_for_project_deleted_handle =
+        project::wait_for_state(&project_api, &name, WaitForState::Deleted);
+    let wait_for_namespace_deleted_handle = project::wait_for_state(
+        &kube::Api::<Namespace>::all(client.clone()),
+        &name,
+        WaitForState::Deleted,
+    );
+
+    project_api
+        .replace(&name, &PostParams::default(), &project)
+        .await?;
+
+    select! {
+        res = futures::future::try_join(wait_for_project_deleted_handle, wait_for_namespace_deleted_handle) => {
+            if let Err(e) = res {
+                bail!("error waiting for expired project {} to be deleted: {}", name, e)
+            }
+        },
+        _ = time::sleep(Duration::from_secs(timeout_secs)) => bail!("expired project {} was not deleted within {} seconds", name, timeout_secs)
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_reports_ready_conditions_when_waiting_for_changes() -> anyhow::Result<()> {
+    let (client, _) = project::before_each().await?;
+
+    let name = project::random_name("conditions-test");
+    let _ = project::install_project(&client, &name).await?;
+    project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges).await?;
+
+    let project = kube::Api::<Project>::all(client.clone()).get(&name).await?;
+    let status = project.status.clone().unwrap_or_default();
+    let conditions = status.conditions.unwrap_or_default();
+
+    for (type_, expected) in [
+        (CONDITION_NAMESPACE_READY, "True"),
+        (CONDITION_MANIFESTS_APPLIED, "True"),
+        (CONDITION_READY, "True"),
+        (CONDITION_DEGRADED, "False"),
+    ]
+    .iter()
+    {
+        let condition = conditions.iter().find(|c| &c.type_ == type_);
+        assert_eq!(
+            condition.map(|c| c.status.as_str()),
+            Some(*expected),
+            "condition {} should be {}: {:?}",
+            type_,
+            expected,
+            conditions
+        );
+    }
+
+    assert_eq!(status.observed_generation, project.metadata.generation);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn it_restores_deleted_resources_without_project_changes() -> anyhow::Result<()> {
+    let (client, _) = project::before_each().await?;
+
+    let name = project::random_name("drift-correction");
+    let _ = project::install_project(&client, &name).await?;
+    project::assert_project_is_in_phase(&client, &name, ProjectPhase::WaitingForChanges).await?;
+
+    let role_binding_name = "selfservice:project:owner".to_string();
+    let api = kube::Api::<RoleBinding>::namespaced(client.clone(), &name);
+    api.delete(&role_binding_name, &DeleteParams::default())
+        .await?;
+
+    let mut restored = false;
+    for _ in 0..30 {
+        time::sleep(Duration::from_secs(1)).await;
+        if api.get(&role_binding_name).await.is_ok() {
+            restored = true;
+            break;
+        }
+    }
+    assert!(
+        restored,
+        "the deleted role binding should have been restored"
+    );
+
+    let reasons = kube::Api::<Event>::namespaced(client.clone(), &name)
+        .list(&ListParams::default().fields(&format!("involvedObject.name={}", name)))
+        .await?
+        .items
+        .into_iter()
+        .filter_map(|event| event.reason)
+        .collect::<Vec<_>>();
+    assert!(
+        reasons
+            .iter()
+            .any(|reason| reason == REASON_DRIFT_CORRECTED),
+        "there should be an event for the restored role binding: {:?}",
+        reasons
+    );
+
+    Ok(())
+}
+
+#[test]
+fn it_only_changes_the_revision_if_more_than_the_status_changes() {
+    let mut project = Project::new("revision", ProjectSpec::default());
+    project.metadata.generation = Some(1);
+    let revision = ProjectRevision::from(&project);
+
+    project.metadata.resource_version = Some("42".to_string());
+    project.status = Some(ProjectStatus {
+        summary: Some("waiting for changes".to_string()),
+        ..Default::default()
+    });
+    assert_eq!(ProjectRevision::from(&project), revision);
+
+    let mut changed = project.clone();
+    changed.metadata.generation = Some(2);
+    assert_ne!(ProjectRevision::from(&changed), revision);
+
+    let mut labeled = project.clone();
+    labeled
+        .metadata
+        .labels
+        .get_or_insert_with(Default::default)
+        .insert("team".to_string(), "a".to_string());
+    assert_ne!(ProjectRevision::from(&labeled), revision);
+
+    let mut annotated = project;
+    annotated
+        .metadata
+        .annotations
+        .get_or_insert_with(Default::default)
+        .insert(
+            "project.selfservice.innoq.io/manifests-changed-at".to_string(),
+            Utc::now().to_rfc3339(),
+        );
+    assert_ne!(ProjectRevision::from(&annotated), revision);
+}
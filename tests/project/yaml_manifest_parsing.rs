@@ -14,15 +14,20 @@
  * limitations under the License.
  */
 
-use k8s_openapi::api::core::v1::{Pod, Secret, ServiceAccount};
+use k8s_openapi::api::core::v1::{ConfigMap, Pod, Secret, ServiceAccount};
 use kube::api::DeleteParams;
 use serial_test::serial;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 
-use self_service_operators::project::states::apply_manifests::is_one_shot_resource;
+use self_service_operators::project::states::apply_manifests::{
+    add_owner_to_yaml_manifest, ensure_retainable, field_ownership, is_one_shot_resource,
+    is_retained_resource, is_transient_error, owned_namespaces, split_yaml_manifest, sync_waves,
+    Applied, FieldOwnership,
+};
 use self_service_operators::project::states::{apply_manifests, ProjectState};
-use self_service_operators::project::Project;
+use self_service_operators::project::ProjectOperatorConfig;
 use self_service_operators::project::ProjectSpec;
+use self_service_operators::project::{OwnerKind, Project, ProjectOwner};
 
 use crate::project;
 use crate::project::WaitForState;
@@ -35,7 +40,11 @@ async fn it_construct_a_correct_api_path_for_yaml_manifest() -> anyhow::Result<(
     let project = Project::new("xxx", ProjectSpec::default());
 
     // Create a pod from JSON
-    let pod_manifest = project.render(include_str!("../fixtures/pod.yaml"), "foo")?;
+    let pod_manifest = project.render(
+        include_str!("../fixtures/pod.yaml"),
+        "foo",
+        &ProjectOperatorConfig::default(),
+    )?;
 
     let pod_api_path = apply_manifests::resource_path(&client, &pod_manifest).await?;
     assert_eq!("/api/v1/namespaces/xxx/pods/foo".to_string(), pod_api_path);
@@ -75,7 +84,7 @@ async fn it_rejects_manifests_with_an_unset_namespace() -> anyhow::Result<()> {
             .unwrap()
             .to_string()
             .as_str(),
-        "setting namespace is required: resource v1/Pod with name 'foo' has no namespace set ... in most cases you want to set it to {{ __PROJECT_NAME__ }}\nManifest is: ---\napiVersion: v1\nkind: Pod\nmetadata:\n  name: foo\nspec:\n  containers:\n    - name: foo\n      image: alpine\n      command: ['sh', '-c', 'echo Hello Kubernetes! && sleep 3600']\n",
+        "setting namespace is required: resource v1/Pod with name 'foo' has no namespace set ... in most cases you want to set it to {{ __PROJECT_NAMESPACE__ }}\nManifest is: ---\napiVersion: v1\nkind: Pod\nmetadata:\n  name: foo\nspec:\n  containers:\n    - name: foo\n      image: alpine\n      command: ['sh', '-c', 'echo Hello Kubernetes! && sleep 3600']\n",
     );
 
     Ok(())
@@ -112,7 +121,8 @@ async fn it_should_correctly_create_yaml_manifest_resources() -> anyhow::Result<
 
     // Create a pod from YAML
     let pod_manifest = include_str!("../fixtures/pod2.yaml");
-    let templated_manifest = project.render(&pod_manifest, "foo");
+    let templated_manifest =
+        project.render(&pod_manifest, "foo", &ProjectOperatorConfig::default());
     apply_manifests::apply_yaml_manifest(
         &client,
         &templated_manifest.unwrap(),
@@ -121,7 +131,20 @@ async fn it_should_correctly_create_yaml_manifest_resources() -> anyhow::Result<
             name: name.clone(),
             error: "".to_string(),
             applied_one_shot_resources: HashSet::new(),
+            expiry_warning_sent: false,
+            reported_error: None,
+            namespaces: vec![],
+            orphaned_namespaces: vec![],
+            resources: vec![],
+            inventory: vec![],
+            retained_resources: vec![],
+            field_conflicts: vec![],
+            applied_manifests: BTreeMap::new(),
+            synced_at: None,
+            revision: None,
+            drift_watch: None,
         },
+        &ProjectOperatorConfig::default(),
     )
     .await?;
 
@@ -147,6 +170,80 @@ async fn it_should_correctly_create_yaml_manifest_resources() -> anyhow::Result<
     Ok(())
 }
 
+#[tokio::test]
+#[serial]
+async fn it_does_not_conflict_with_itself_when_reapplying_created_resources() -> anyhow::Result<()>
+{
+    let (client, _) = project::before_each().await?;
+
+    let name = project::random_name("apply-manifest-owner");
+    let project = project::install_project(&client, &name).await?;
+    let config = ProjectOperatorConfig {
+        report_field_conflicts: true,
+        ..Default::default()
+    };
+    let mut state = ProjectState {
+        name: name.clone(),
+        error: "".to_string(),
+        applied_one_shot_resources: HashSet::new(),
+        expiry_warning_sent: false,
+        reported_error: None,
+        namespaces: vec![],
+        orphaned_namespaces: vec![],
+        resources: vec![],
+        inventory: vec![],
+        retained_resources: vec![],
+        field_conflicts: vec![],
+        applied_manifests: BTreeMap::new(),
+        synced_at: None,
+        revision: None,
+        drift_watch: None,
+    };
+    let manifest = |value: &str| {
+        format!(
+            "apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: settings\n  namespace: {}\ndata:\n  value: {}\n",
+            name, value
+        )
+    };
+
+    let created = apply_manifests::apply_yaml_manifest(
+        &client,
+        &manifest("a"),
+        &project,
+        &mut state,
+        &config,
+    )
+    .await?;
+    assert_eq!(created, Applied::Changed);
+
+    // the resource was created by a server side apply: a non-forced apply of the same field
+    // manager doesn't conflict with it
+    let updated = apply_manifests::apply_yaml_manifest(
+        &client,
+        &manifest("b"),
+        &project,
+        &mut state,
+        &config,
+    )
+    .await?;
+    assert_eq!(updated, Applied::Changed);
+    assert!(state.field_conflicts.is_empty());
+
+    let config_map = kube::Api::<ConfigMap>::namespaced(client.clone(), &name)
+        .get("settings")
+        .await?;
+    assert_eq!(
+        config_map.data.unwrap().get("value"),
+        Some(&"b".to_string())
+    );
+
+    kube::Api::<Project>::all(client.clone())
+        .delete(name.as_str(), &DeleteParams::default())
+        .await?;
+
+    Ok(())
+}
+
 #[tokio::test]
 #[serial]
 async fn it_detects_one_shot_manifests_correctly() -> anyhow::Result<()> {
@@ -154,14 +251,302 @@ async fn it_detects_one_shot_manifests_correctly() -> anyhow::Result<()> {
 
     let project = Project::new("xxx", ProjectSpec::default());
 
-    let pod_manifest =
-        project.render(include_str!("../fixtures/apply-once-resource.yaml"), "foo")?;
+    let pod_manifest = project.render(
+        include_str!("../fixtures/apply-once-resource.yaml"),
+        "foo",
+        &ProjectOperatorConfig::default(),
+    )?;
 
     assert_eq!(is_one_shot_resource(&pod_manifest).unwrap(), true);
 
-    let pod_manifest = project.render(include_str!("../fixtures/pod.yaml"), "foo")?;
+    let pod_manifest = project.render(
+        include_str!("../fixtures/pod.yaml"),
+        "foo",
+        &ProjectOperatorConfig::default(),
+    )?;
 
     assert_eq!(is_one_shot_resource(&pod_manifest).unwrap(), false);
 
     Ok(())
 }
+
+#[test]
+fn it_does_not_add_the_project_as_owner_of_retained_resources() -> anyhow::Result<()> {
+    let mut project = Project::new("xxx", ProjectSpec::default());
+    project.metadata.uid = Some("c0ffee".to_string());
+
+    let retained = project.render(
+        include_str!("../fixtures/retained-cluster-role.yaml"),
+        "foo",
+        &ProjectOperatorConfig::default(),
+    )?;
+    assert!(is_retained_resource(&retained)?);
+    let owned: serde_yaml::Value =
+        serde_yaml::from_str(&add_owner_to_yaml_manifest(&retained, &project)?)?;
+    assert!(owned["metadata"]["ownerReferences"].is_null());
+
+    let deleted = project.render(
+        include_str!("../fixtures/pod.yaml"),
+        "foo",
+        &ProjectOperatorConfig::default(),
+    )?;
+    assert!(!is_retained_resource(&deleted)?);
+    let owned: serde_yaml::Value =
+        serde_yaml::from_str(&add_owner_to_yaml_manifest(&deleted, &project)?)?;
+    assert_eq!(owned["metadata"]["ownerReferences"][0]["uid"], "c0ffee");
+
+    let invalid = retained.replace("deletion-policy: retain", "deletion-policy: orphan");
+    assert!(is_retained_resource(&invalid).is_err());
+    assert!(add_owner_to_yaml_manifest(&invalid, &project).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn it_rejects_retained_resources_in_owned_namespaces() -> anyhow::Result<()> {
+    let manifest = |kind: &str, namespace: &str, policy: &str| {
+        format!(
+            "apiVersion: v1\nkind: {}\nmetadata:\n  name: foo\n  namespace: {}\n  annotations:\n    project.selfservice.innoq.io/deletion-policy: {}\n",
+            kind, namespace, policy
+        )
+    };
+
+    let owned = owned_namespaces(
+        &["foo".to_string()],
+        &[
+            manifest("Namespace", "", "delete").replace("name: foo", "name: foo-tools"),
+            manifest("Namespace", "", "retain").replace("name: foo", "name: foo-data"),
+        ],
+    )?;
+    assert_eq!(owned, vec!["foo".to_string(), "foo-tools".to_string()]);
+
+    assert!(
+        ensure_retainable(&manifest("PersistentVolumeClaim", "foo", "retain"), &owned).is_err()
+    );
+    assert!(ensure_retainable(
+        &manifest("PersistentVolumeClaim", "foo-tools", "retain"),
+        &owned
+    )
+    .is_err());
+    assert!(ensure_retainable(
+        &manifest("PersistentVolumeClaim", "foo-data", "retain"),
+        &owned
+    )
+    .is_ok());
+    assert!(ensure_retainable(&manifest("PersistentVolumeClaim", "foo", "delete"), &owned).is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn it_determines_the_field_ownership_of_manifests() -> anyhow::Result<()> {
+    let manifest = |ownership: &str| {
+        format!(
+            "apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: foo\n  annotations:\n    project.selfservice.innoq.io/field-ownership: {}\n",
+            ownership
+        )
+    };
+    let unannotated = "apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: foo\n";
+
+    let forcing = ProjectOperatorConfig::default();
+    let reporting = ProjectOperatorConfig {
+        report_field_conflicts: true,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        field_ownership(unannotated, &forcing)?,
+        FieldOwnership::Authoritative
+    );
+    assert_eq!(
+        field_ownership(unannotated, &reporting)?,
+        FieldOwnership::Shared
+    );
+    for config in &[&forcing, &reporting] {
+        assert_eq!(
+            field_ownership(&manifest("authoritative"), config)?,
+            FieldOwnership::Authoritative
+        );
+        assert_eq!(
+            field_ownership(&manifest("initial"), config)?,
+            FieldOwnership::Initial
+        );
+        assert!(field_ownership(&manifest("mine"), config).is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn it_splits_multi_document_manifests_and_lists() -> anyhow::Result<()> {
+    let project = Project::new("xxx", ProjectSpec::default());
+
+    let manifest = project.render(
+        include_str!("../fixtures/multi-document.yaml"),
+        "foo",
+        &ProjectOperatorConfig::default(),
+    )?;
+    let objects = split_yaml_manifest(&manifest)?;
+
+    let names = objects
+        .iter()
+        .map(|object| {
+            let yaml: serde_yaml::Value = serde_yaml::from_str(object).unwrap();
+            yaml["metadata"]["name"].as_str().unwrap().to_string()
+        })
+        .collect::<Vec<String>>();
+    assert_eq!(
+        names,
+        vec![
+            "first-sa",
+            "second-sa",
+            "first-config-map",
+            "second-config-map"
+        ]
+    );
+
+    let one_shot_objects = objects
+        .iter()
+        .map(|object| is_one_shot_resource(object).unwrap())
+        .collect::<Vec<bool>>();
+    assert_eq!(one_shot_objects, vec![false, true, false, false]);
+
+    assert!(split_yaml_manifest("kind: List\n").is_err());
+    assert!(split_yaml_manifest("just a string").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn it_groups_manifests_into_sync_waves() -> anyhow::Result<()> {
+    let manifest = |kind: &str, name: &str, wave: Option<&str>| -> String {
+        let annotations = wave
+            .map(|wave| {
+                format!(
+                    "\n  annotations:\n    project.selfservice.innoq.io/sync-wave: \"{}\"",
+                    wave
+                )
+            })
+            .unwrap_or_default();
+        format!(
+            "apiVersion: v1\nkind: {}\nmetadata:\n  name: {}{}\n",
+            kind, name, annotations
+        )
+    };
+
+    let manifests = vec![
+        manifest("Pod", "pod", None),
+        manifest("ConfigMap", "late-config-map", Some("5")),
+        manifest("ServiceAccount", "sa", None),
+        manifest("CustomResourceDefinition", "crd", None),
+        manifest("ConfigMap", "config-map", None),
+        manifest("Pod", "early-pod", Some("-10")),
+    ];
+
+    let waves = sync_waves(&manifests)?
+        .into_iter()
+        .map(|(wave, manifests)| {
+            let names = manifests
+                .iter()
+                .map(|m| {
+                    let yaml: serde_yaml::Value = serde_yaml::from_str(m).unwrap();
+                    yaml["metadata"]["name"].as_str().unwrap().to_string()
+                })
+                .collect::<Vec<_>>();
+            (wave, names)
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        waves,
+        vec![
+            (-10, vec!["early-pod".to_string()]),
+            (-2, vec!["crd".to_string()]),
+            (-1, vec!["sa".to_string()]),
+            (0, vec!["pod".to_string(), "config-map".to_string()]),
+            (5, vec!["late-config-map".to_string()]),
+        ]
+    );
+
+    assert!(sync_waves(&[manifest("Pod", "pod", Some("first"))]).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn it_only_considers_some_errors_as_transient() {
+    let api_error = |code: u16| {
+        anyhow::Error::new(kube::Error::Api(kube::error::ErrorResponse {
+            status: "Failure".to_string(),
+            message: "".to_string(),
+            reason: "".to_string(),
+            code,
+        }))
+        .context("error applying manifest")
+    };
+
+    assert!(is_transient_error(&api_error(404)));
+    assert!(is_transient_error(&api_error(409)));
+    assert!(is_transient_error(&api_error(503)));
+    assert!(!is_transient_error(&api_error(422)));
+    assert!(!is_transient_error(&api_error(400)));
+    assert!(!is_transient_error(&anyhow::anyhow!(
+        "api version v1 not available in kubernetes cluster"
+    )));
+}
+
+#[test]
+fn it_renders_owner_names_and_owner_subjects() -> anyhow::Result<()> {
+    let mut project = Project::new("xxx", ProjectSpec::default());
+    project.spec.owners = vec![
+        ProjectOwner::from("superdev@example.com"),
+        ProjectOwner {
+            kind: OwnerKind::ServiceAccount,
+            name: "deployer".to_string(),
+            namespace: Some("ci".to_string()),
+        },
+    ];
+
+    assert_eq!(
+        project.render(
+            "{{#each __PROJECT_OWNERS__ }}{{ this }} {{/each}}",
+            "foo",
+            &ProjectOperatorConfig::default()
+        )?,
+        "superdev@example.com deployer "
+    );
+    assert_eq!(
+        project.render(
+            "{{#each __PROJECT_OWNER_SUBJECTS__ }}{{ this.kind }}:{{ this.name }} {{/each}}",
+            "foo",
+            &ProjectOperatorConfig::default()
+        )?,
+        "User:superdev@example.com ServiceAccount:deployer "
+    );
+
+    Ok(())
+}
+
+#[test]
+fn it_renders_the_namespace_as_configured() -> anyhow::Result<()> {
+    let project = Project::new("xxx", ProjectSpec::default());
+    let config = ProjectOperatorConfig {
+        namespace_name_template: Some("sandbox-{{ name }}".to_string()),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        project.render("{{ __PROJECT_NAMESPACE__ }}", "foo", &config)?,
+        "sandbox-xxx"
+    );
+    assert_eq!(
+        project.render(
+            "{{ __PROJECT_NAMESPACE__ }}",
+            "foo",
+            &ProjectOperatorConfig::default()
+        )?,
+        "xxx"
+    );
+
+    Ok(())
+}